@@ -15,6 +15,7 @@ struct Mesh {
     uint vertex_tangent_offset;
     uint mat_data_offset;
     uint index_offset;
+    uint vertex_uv1_offset; // zero if the mesh has no second uv set
 };
 
 struct Vertex {
@@ -46,6 +47,9 @@ VertexPacked pack_vertex(Vertex v) {
 }
 
 static const uint MESH_MATERIAL_FLAG_EMISSIVE_USED_AS_LIGHT = 1;
+static const uint MESH_MATERIAL_FLAG_ALPHA_MASK = 2;
+static const uint MESH_MATERIAL_FLAG_ALPHA_BLEND = 4;
+static const uint MESH_MATERIAL_FLAG_DOUBLE_SIDED = 8;
 
 struct MeshMaterial {
     float base_color_mult[4];
@@ -57,9 +61,27 @@ struct MeshMaterial {
     float metalness_factor;
     float emissive[3];
     uint flags;
+    // Indexed by map: albedo, normal, spec, emissive
     float map_transforms[6 * 4];
+    uint map_uv_sets[4];
+    float alpha_cutoff;
+    float transmission;
+    float ior;
+    float clearcoat;
+    float clearcoat_roughness;
+    float sheen_color[3];
+    float sheen_roughness;
 };
 
+// Blending is not supported by the deferred passes, so blended materials are alpha-tested too.
+bool material_is_alpha_tested(MeshMaterial mat) {
+    return 0 != (mat.flags & (MESH_MATERIAL_FLAG_ALPHA_MASK | MESH_MATERIAL_FLAG_ALPHA_BLEND));
+}
+
+bool material_is_double_sided(MeshMaterial mat) {
+    return 0 != (mat.flags & MESH_MATERIAL_FLAG_DOUBLE_SIDED);
+}
+
 float2 transform_material_uv(MeshMaterial mat, float2 uv, uint map_idx) {
     uint xo = map_idx * 6;
     float2x2 rot_scl = float2x2(mat.map_transforms[xo+0], mat.map_transforms[xo+1], mat.map_transforms[xo+2], mat.map_transforms[xo+3]);
@@ -67,5 +89,10 @@ float2 transform_material_uv(MeshMaterial mat, float2 uv, uint map_idx) {
     return mul(rot_scl, uv) + offset;
 }
 
+// Selects the uv set sampled by the map, and applies the map's transform to it.
+float2 material_map_uv(MeshMaterial mat, float2 uv0, float2 uv1, uint map_idx) {
+    return transform_material_uv(mat, mat.map_uv_sets[map_idx] == 1 ? uv1 : uv0, map_idx);
+}
+
 
 #endif
\ No newline at end of file
@@ -60,10 +60,12 @@ bool rt_is_shadowed(
     RayDesc ray
 ) {
     ShadowRayPayload shadow_payload = ShadowRayPayload::new_hit();
+
+    // Shadow rays use the hit group at index 1, whose any hit shader takes their payload.
     TraceRay(
         acceleration_structure,
         RAY_FLAG_ACCEPT_FIRST_HIT_AND_END_SEARCH | RAY_FLAG_SKIP_CLOSEST_HIT_SHADER,
-        0xff, 0, 0, 1, ray, shadow_payload
+        0xff, 1, 0, 1, ray, shadow_payload
     );
 
     return shadow_payload.is_shadowed;
@@ -15,6 +15,7 @@ struct PsIn {
     [[vk::location(5)]] float3 bitangent: TEXCOORD5;
     [[vk::location(6)]] float3 vs_pos: TEXCOORD6;
     [[vk::location(7)]] float3 prev_vs_pos: TEXCOORD7;
+    [[vk::location(8)]] float2 uv1: TEXCOORD8;
 };
 
 [[vk::push_constant]]
@@ -36,22 +37,27 @@ struct PsOut {
     float4 velocity: SV_TARGET2;
 };
 
-PsOut main(PsIn ps) {
+PsOut main(PsIn ps, bool is_front_face: SV_IsFrontFace) {
     Mesh mesh = meshes[push_constants.mesh_index];
     MeshMaterial material = vertices.Load<MeshMaterial>(mesh.mat_data_offset + ps.material_id * sizeof(MeshMaterial));
 
+    // Face culling is disabled in the pipeline, and done per material instead
+    if (!is_front_face && !material_is_double_sided(material)) {
+        discard;
+    }
+
     const float lod_bias = -0.5;
 
-    float2 albedo_uv = transform_material_uv(material, ps.uv, 0);
+    float2 albedo_uv = material_map_uv(material, ps.uv, ps.uv1, 0);
     Texture2D albedo_tex = bindless_textures[NonUniformResourceIndex(material.albedo_map)];
     float4 albedo_texel = albedo_tex.SampleBias(sampler_llr, albedo_uv, lod_bias);
-    if (albedo_texel.a < 0.5) {
+    if (material_is_alpha_tested(material) && albedo_texel.a * material.base_color_mult[3] < material.alpha_cutoff) {
         discard;
     }
 
     float3 albedo = albedo_texel.xyz * float4(material.base_color_mult).xyz * ps.color.xyz;
 
-    float2 spec_uv = transform_material_uv(material, ps.uv, 2);
+    float2 spec_uv = material_map_uv(material, ps.uv, ps.uv1, 2);
     Texture2D spec_tex = bindless_textures[NonUniformResourceIndex(material.spec_map)];
     const float4 metalness_roughness = spec_tex.SampleBias(sampler_llr, spec_uv, lod_bias);
     float perceptual_roughness = material.roughness_mult * metalness_roughness.x;
@@ -73,13 +79,14 @@ PsOut main(PsIn ps) {
 
         [branch]
         if (!frame_constants.render_overrides.has_flag(RenderOverrideFlags::NO_NORMAL_MAPS)) {
+            float2 normal_uv = material_map_uv(material, ps.uv, ps.uv1, 1);
             Texture2D normal_tex = bindless_textures[NonUniformResourceIndex(material.normal_map)];
 
 #if 1
-            float3 ts_normal = float3(normal_tex.SampleBias(sampler_llr, ps.uv, lod_bias).xy * 2.0 - 1.0, 0);
+            float3 ts_normal = float3(normal_tex.SampleBias(sampler_llr, normal_uv, lod_bias).xy * 2.0 - 1.0, 0);
             ts_normal.z = sqrt(max(0.01, 1.0 - dot(ts_normal.xy, ts_normal.xy)));
 #else
-            float3 ts_normal = normal_tex.SampleBias(sampler_llr, ps.uv, lod_bias).xyz * 2.0 - 1.0;
+            float3 ts_normal = normal_tex.SampleBias(sampler_llr, normal_uv, lod_bias).xyz * 2.0 - 1.0;
 #endif
 
             if (frame_constants.render_overrides.has_flag(RenderOverrideFlags::FLIP_NORMAL_MAP_YZ)) {
@@ -113,7 +120,7 @@ PsOut main(PsIn ps) {
         normal_ws = geometric_normal_ws;
     }
 
-    float2 emissive_uv = transform_material_uv(material, ps.uv, 3);
+    float2 emissive_uv = material_map_uv(material, ps.uv, ps.uv1, 3);
     Texture2D emissive_tex = bindless_textures[NonUniformResourceIndex(material.emissive_map)];
     float3 emissive = 1.0.xxx
         * emissive_tex.SampleBias(sampler_llr, emissive_uv, lod_bias).rgb
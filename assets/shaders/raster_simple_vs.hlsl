@@ -25,6 +25,7 @@ struct VsOut {
     [[vk::location(5)]] float3 bitangent: TEXCOORD5;
     [[vk::location(6)]] float3 vs_pos: TEXCOORD6;
     [[vk::location(7)]] float3 prev_vs_pos: TEXCOORD7;
+    [[vk::location(8)]] float2 uv1: TEXCOORD8;
 };
 
 VsOut main(uint vid: SV_VertexID, uint instance_index: SV_InstanceID) {
@@ -48,6 +49,10 @@ VsOut main(uint vid: SV_VertexID, uint instance_index: SV_InstanceID) {
             : float4(1, 0, 0, 1);            
 
     float2 uv = asfloat(vertices.Load2(vid * sizeof(float2) + mesh.vertex_uv_offset));
+    float2 uv1 =
+        mesh.vertex_uv1_offset != 0
+            ? asfloat(vertices.Load2(vid * sizeof(float2) + mesh.vertex_uv1_offset))
+            : uv;
     uint material_id = vertices.Load(vid * sizeof(uint) + mesh.vertex_mat_offset);
 
     //float3 ws_pos = v.position + float3(push_constants.instance_position);
@@ -63,6 +68,7 @@ VsOut main(uint vid: SV_VertexID, uint instance_index: SV_InstanceID) {
     vsout.position = cs_pos;
     vsout.color = v_color;
     vsout.uv = uv;
+    vsout.uv1 = uv1;
     vsout.normal = v.normal;
     vsout.material_id = material_id;
     vsout.tangent = v_tangent_packed.xyz;
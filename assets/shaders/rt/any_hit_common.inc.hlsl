@@ -0,0 +1,60 @@
+#include "../inc/samplers.hlsl"
+#include "../inc/mesh.hlsl"
+#include "../inc/bindless.hlsl"
+
+struct RayHitAttrib {
+    float2 bary;
+};
+
+// Whether an any hit shader should ignore the current hit: it's either on the back face
+// of a single-sided material in an instance without face culling, or alpha-tested away.
+bool any_hit_is_ignored(RayHitAttrib attrib) {
+    Mesh mesh = meshes[InstanceID()];
+
+    // Indices of the triangle
+    uint3 ind = uint3(
+        vertices.Load((PrimitiveIndex() * 3 + 0) * sizeof(uint) + mesh.index_offset),
+        vertices.Load((PrimitiveIndex() * 3 + 1) * sizeof(uint) + mesh.index_offset),
+        vertices.Load((PrimitiveIndex() * 3 + 2) * sizeof(uint) + mesh.index_offset)
+    );
+
+    uint material_id = vertices.Load(ind.x * sizeof(uint) + mesh.vertex_mat_offset);
+    MeshMaterial material = vertices.Load<MeshMaterial>(mesh.mat_data_offset + material_id * sizeof(MeshMaterial));
+
+    // Instances with double-sided materials have face culling disabled;
+    // apply it here for the single-sided ones.
+    if ((RayFlags() & RAY_FLAG_CULL_BACK_FACING_TRIANGLES) != 0
+        && HitKind() == HIT_KIND_TRIANGLE_BACK_FACE
+        && !material_is_double_sided(material)
+    ) {
+        return true;
+    }
+
+    if (material_is_alpha_tested(material)) {
+        float3 barycentrics = float3(1.0 - attrib.bary.x - attrib.bary.y, attrib.bary.x, attrib.bary.y);
+
+        float2 uv0 = asfloat(vertices.Load2(ind.x * sizeof(float2) + mesh.vertex_uv_offset));
+        float2 uv1 = asfloat(vertices.Load2(ind.y * sizeof(float2) + mesh.vertex_uv_offset));
+        float2 uv2 = asfloat(vertices.Load2(ind.z * sizeof(float2) + mesh.vertex_uv_offset));
+        float2 uv = uv0 * barycentrics.x + uv1 * barycentrics.y + uv2 * barycentrics.z;
+
+        float2 second_uv = uv;
+        if (mesh.vertex_uv1_offset != 0) {
+            float2 suv0 = asfloat(vertices.Load2(ind.x * sizeof(float2) + mesh.vertex_uv1_offset));
+            float2 suv1 = asfloat(vertices.Load2(ind.y * sizeof(float2) + mesh.vertex_uv1_offset));
+            float2 suv2 = asfloat(vertices.Load2(ind.z * sizeof(float2) + mesh.vertex_uv1_offset));
+            second_uv = suv0 * barycentrics.x + suv1 * barycentrics.y + suv2 * barycentrics.z;
+        }
+
+        // No ray cones here; the top mip is good enough for a coverage test.
+        float2 albedo_uv = material_map_uv(material, uv, second_uv, 0);
+        Texture2D albedo_tex = bindless_textures[NonUniformResourceIndex(material.albedo_map)];
+        float alpha = albedo_tex.SampleLevel(sampler_llr, albedo_uv, 0).a * material.base_color_mult[3];
+
+        if (alpha < material.alpha_cutoff) {
+            return true;
+        }
+    }
+
+    return false;
+}
@@ -0,0 +1,11 @@
+#include "../inc/frame_constants.hlsl"
+#include "../inc/rt.hlsl"
+#include "any_hit_common.inc.hlsl"
+
+// Only invoked for non-opaque meshes: those with alpha-tested or double-sided materials.
+[shader("anyhit")]
+void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib: SV_IntersectionAttributes) {
+    if (any_hit_is_ignored(attrib)) {
+        IgnoreHit();
+    }
+}
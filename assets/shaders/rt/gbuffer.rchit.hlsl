@@ -84,6 +84,14 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
     float2 uv2 = asfloat(vertices.Load2(ind.z * sizeof(float2) + mesh.vertex_uv_offset));
     float2 uv = uv0 * barycentrics.x + uv1 * barycentrics.y + uv2 * barycentrics.z;
 
+    float2 second_uv = uv;
+    if (mesh.vertex_uv1_offset != 0) {
+        float2 suv0 = asfloat(vertices.Load2(ind.x * sizeof(float2) + mesh.vertex_uv1_offset));
+        float2 suv1 = asfloat(vertices.Load2(ind.y * sizeof(float2) + mesh.vertex_uv1_offset));
+        float2 suv2 = asfloat(vertices.Load2(ind.z * sizeof(float2) + mesh.vertex_uv1_offset));
+        second_uv = suv0 * barycentrics.x + suv1 * barycentrics.y + suv2 * barycentrics.z;
+    }
+
     const float cone_width = payload.ray_cone.width_at_t(hit_dist);
     const float3 v0_pos_ws = mul(ObjectToWorld3x4(), float4(v0.position, 1.0));
     const float3 v1_pos_ws = mul(ObjectToWorld3x4(), float4(v1.position, 1.0));
@@ -93,7 +101,7 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
     uint material_id = vertices.Load(ind.x * sizeof(uint) + mesh.vertex_mat_offset);
     MeshMaterial material = vertices.Load<MeshMaterial>(mesh.mat_data_offset + material_id * sizeof(MeshMaterial));
 
-    float2 albedo_uv = transform_material_uv(material, uv, 0);
+    float2 albedo_uv = material_map_uv(material, uv, second_uv, 0);
     const BindlessTextureWithLod albedo_tex =
         compute_texture_lod(material.albedo_map, lod_triangle_constant, WorldRayDirection(), surf_normal, cone_width);
 
@@ -102,7 +110,7 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
         * float4(material.base_color_mult).xyz
         * v_color.rgb;
 
-    float2 spec_uv = transform_material_uv(material, uv, 2);
+    float2 spec_uv = material_map_uv(material, uv, second_uv, 2);
     const BindlessTextureWithLod spec_tex =
         compute_texture_lod(material.spec_map, lod_triangle_constant, WorldRayDirection(), surf_normal, cone_width);
     float4 metalness_roughness = spec_tex.tex.SampleLevel(sampler_llr, spec_uv, spec_tex.lod);
@@ -147,7 +155,7 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
         float3 tangent = tangent0 * barycentrics.x + tangent1 * barycentrics.y + tangent2 * barycentrics.z;
         float3 bitangent = bitangent0 * barycentrics.x + bitangent1 * barycentrics.y + bitangent2 * barycentrics.z;
 
-        float2 normal_uv = transform_material_uv(material, uv, 0);
+        float2 normal_uv = material_map_uv(material, uv, second_uv, 1);
         const BindlessTextureWithLod normal_tex =
             compute_texture_lod(material.normal_map, lod_triangle_constant, WorldRayDirection(), surf_normal, cone_width);
 
@@ -165,7 +173,7 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
     }
 #endif
 
-    float2 emissive_uv = transform_material_uv(material, uv, 3);
+    float2 emissive_uv = material_map_uv(material, uv, second_uv, 3);
     const BindlessTextureWithLod emissive_tex =
         compute_texture_lod(material.emissive_map, lod_triangle_constant, WorldRayDirection(), surf_normal, cone_width);
 
@@ -182,6 +190,11 @@ void main(inout GbufferRayPayload payload: SV_RayPayload, in RayHitAttrib attrib
             * frame_constants.pre_exposure;
     }
 
+    // Double-sided materials can be hit from behind; shade them as seen from the front.
+    if (HitKind() == HIT_KIND_TRIANGLE_BACK_FACE && material_is_double_sided(material)) {
+        normal = -normal;
+    }
+
     GbufferData gbuffer = GbufferData::create_zero();
     gbuffer.albedo = albedo;
     gbuffer.normal = normalize(mul(ObjectToWorld3x4(), float4(normal, 0.0)));
@@ -0,0 +1,12 @@
+#include "../inc/frame_constants.hlsl"
+#include "../inc/rt.hlsl"
+#include "any_hit_common.inc.hlsl"
+
+// The any hit shader of shadow rays, which have their own hit group.
+// Lets light through the alpha-tested parts of materials.
+[shader("anyhit")]
+void main(inout ShadowRayPayload payload: SV_RayPayload, in RayHitAttrib attrib: SV_IntersectionAttributes) {
+    if (any_hit_is_ignored(attrib)) {
+        IgnoreHit();
+    }
+}
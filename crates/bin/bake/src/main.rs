@@ -1,19 +1,79 @@
+mod manifest;
+
 use anyhow::Result;
+use glam::Quat;
 use kajiya_asset_pipe::*;
-use std::path::PathBuf;
+use manifest::{Manifest, ManifestAsset};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "bake", about = "Kanelbullar")]
 struct Opt {
-    #[structopt(long, parse(from_os_str))]
-    scene: PathBuf,
+    #[structopt(long, parse(from_os_str), required_unless = "manifest")]
+    scene: Option<PathBuf>,
 
     #[structopt(long, default_value = "1.0")]
     scale: f32,
 
-    #[structopt(short = "o")]
-    output_name: String,
+    #[structopt(short = "o", required_unless = "manifest")]
+    output_name: Option<String>,
+
+    /// How to generate missing normals: flat, smooth-area, or smooth-angle. Applies to manifest
+    /// assets which don't set their own; defaults to flat
+    #[structopt(long)]
+    normals: Option<NormalGeneration>,
+
+    /// Preserve the node hierarchy, writing a `.scene` file and one `.mesh` per unique mesh
+    #[structopt(long)]
+    hierarchy: bool,
+
+    /// Weld vertices, strip degenerate triangles, and reorder the mesh data for rendering
+    #[structopt(long)]
+    optimize: bool,
+
+    /// Generate a chain of simplified index buffers, for the renderer to pick from by distance
+    #[structopt(long)]
+    lods: bool,
+
+    /// Bake every material as opaque and single-sided. Meshes with any alpha-tested or
+    /// double-sided material run any hit shaders for all of their triangles when ray traced
+    #[structopt(long)]
+    force_opaque: bool,
+
+    /// Texture compression preset: ultrafast, veryfast, fast, basic, or slow. Applies to manifest
+    /// assets which don't set their own; defaults to basic
+    #[structopt(long)]
+    texture_quality: Option<TexCompressionQuality>,
+
+    /// Downscale larger textures to fit, preserving the aspect ratio. Overrides `max_texture_size`
+    /// from the manifest; defaults to 2048
+    #[structopt(long)]
+    max_texture_size: Option<u32>,
+
+    /// Keep textures at full resolution. Overrides `full_res_textures` from the manifest
+    #[structopt(long, conflicts_with = "max-texture-size")]
+    full_res_textures: bool,
+
+    /// Also write the baked textures as KTX2, for inspection with standard tools
+    #[structopt(long)]
+    export_ktx2: bool,
+
+    /// Bake all the assets listed in a RON manifest instead of a single scene
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["scene", "output-name"])]
+    manifest: Option<PathBuf>,
+
+    /// Where to write the baked assets. Overrides `output_dir` from the manifest; defaults to `cache`
+    #[structopt(long, parse(from_os_str))]
+    output_dir: Option<PathBuf>,
+
+    /// How many assets from the manifest to bake in parallel; defaults to the number of CPUs
+    #[structopt(long)]
+    jobs: Option<usize>,
 }
 
 fn main() -> Result<()> {
@@ -21,9 +81,163 @@ fn main() -> Result<()> {
 
     let opt = Opt::from_args();
 
-    process_mesh_asset(MeshAssetProcessParams {
-        path: opt.scene,
-        output_name: opt.output_name,
+    if let Some(manifest) = &opt.manifest {
+        let all_baked = bake_manifest(manifest, &opt)?;
+        if !all_baked {
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
+
+    let params = MeshAssetProcessParams {
+        path: opt.scene.unwrap(),
+        output_name: opt.output_name.unwrap(),
+        output_dir: opt.output_dir.unwrap_or_else(|| "cache".into()),
         scale: opt.scale,
+        rotation: Quat::IDENTITY,
+        normal_generation: opt.normals.unwrap_or_default(),
+        tex_settings: TexBakeSettings {
+            compression_quality: opt.texture_quality.unwrap_or_default(),
+            max_size: if opt.full_res_textures {
+                None
+            } else {
+                Some(opt.max_texture_size.unwrap_or(DEFAULT_MAX_TEXTURE_SIZE))
+            },
+            ..Default::default()
+        },
+        optimize_mesh: opt.optimize,
+        generate_lods: opt.lods,
+        force_opaque: opt.force_opaque,
+        export_ktx2: opt.export_ktx2,
+    };
+
+    if opt.hierarchy {
+        process_scene_asset(params)
+    } else {
+        process_mesh_asset(params)
+    }
+}
+
+/// Bakes every asset in the manifest, and prints a summary. Returns false if any of them failed.
+fn bake_manifest(manifest_path: &Path, opt: &Opt) -> Result<bool> {
+    let manifest = Manifest::load(manifest_path)?;
+
+    let output_dir = opt
+        .output_dir
+        .clone()
+        .or_else(|| manifest.output_dir.clone())
+        .unwrap_or_else(|| "cache".into());
+
+    // For assets which don't set their own; `None` keeps the full resolution
+    let max_texture_size = if opt.full_res_textures
+        || (opt.max_texture_size.is_none() && manifest.full_res_textures)
+    {
+        None
+    } else {
+        Some(
+            opt.max_texture_size
+                .or(manifest.max_texture_size)
+                .unwrap_or(DEFAULT_MAX_TEXTURE_SIZE),
+        )
+    };
+
+    let job_count = opt
+        .jobs
+        .unwrap_or_else(num_cpus::get)
+        .min(manifest.assets.len())
+        .max(1);
+
+    println!(
+        "Baking {} assets into {:?} using {} jobs...",
+        manifest.assets.len(),
+        output_dir,
+        job_count
+    );
+
+    let next_asset = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<()>, Duration)> = easy_parallel::Parallel::new()
+        .each(0..job_count, |_| {
+            let mut results = Vec::new();
+            loop {
+                let asset_idx = next_asset.fetch_add(1, Ordering::Relaxed);
+                let asset = match manifest.assets.get(asset_idx) {
+                    Some(asset) => asset,
+                    None => break results,
+                };
+
+                let start = Instant::now();
+                let result = bake_asset(asset, &output_dir, max_texture_size, opt);
+                results.push((asset_idx, result, start.elapsed()));
+            }
+        })
+        .run()
+        .into_iter()
+        .flatten()
+        .collect();
+
+    results.sort_by_key(|(asset_idx, _, _)| *asset_idx);
+
+    println!();
+    let mut failed_count = 0;
+    for (asset_idx, result, duration) in &results {
+        let asset = &manifest.assets[*asset_idx];
+        match result {
+            Ok(()) => println!("ok     {} ({:.1}s)", asset.name, duration.as_secs_f32()),
+            Err(err) => {
+                failed_count += 1;
+                println!(
+                    "FAILED {} ({:.1}s): {:#}",
+                    asset.name,
+                    duration.as_secs_f32(),
+                    err
+                );
+            }
+        }
+    }
+
+    println!(
+        "\n{} assets baked, {} failed",
+        results.len() - failed_count,
+        failed_count
+    );
+
+    Ok(failed_count == 0)
+}
+
+fn bake_asset(
+    asset: &ManifestAsset,
+    output_dir: &Path,
+    max_texture_size: Option<u32>,
+    opt: &Opt,
+) -> Result<()> {
+    let mut params = asset.process_params(
+        output_dir,
+        max_texture_size,
+        opt.normals.unwrap_or_default(),
+        opt.texture_quality.unwrap_or_default(),
+    )?;
+    params.optimize_mesh |= opt.optimize;
+    params.generate_lods |= opt.lods;
+    params.force_opaque |= opt.force_opaque;
+    params.export_ktx2 |= opt.export_ktx2;
+    let hierarchy = asset.hierarchy;
+
+    // Keep baking the other assets if the pipeline panics on this one
+    std::panic::catch_unwind(move || {
+        if hierarchy {
+            process_scene_asset(params)
+        } else {
+            process_mesh_asset(params)
+        }
+    })
+    .unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown error");
+
+        Err(anyhow::anyhow!("Panicked: {}", message))
     })
 }
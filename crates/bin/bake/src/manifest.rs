@@ -0,0 +1,224 @@
+use anyhow::Context as _;
+use glam::{EulerRot, Quat};
+use kajiya_asset_pipe::{
+    MeshAssetProcessParams, NormalGeneration, TexBakeSettings, TexCompressionMode,
+    TexCompressionQuality,
+};
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+/// A list of assets to bake in one go. Stored as RON:
+///
+/// ```ron
+/// (
+///     output_dir: Some("cache"),
+///     max_texture_size: Some(1024),
+///     assets: [
+///         (
+///             source: "meshes/car/scene.gltf",
+///             name: "car",
+///             scale: 0.01,
+///             rotation: (0, 90, 0),
+///             normals: "smooth-angle",
+///             optimize: true,
+///             lods: true,
+///             force_opaque: false,
+///             textures: (
+///                 mips: true,
+///                 quality: "slow",
+///                 emissive: Some("bc6h"),
+///                 full_res: true,
+///             ),
+///         ),
+///     ],
+/// )
+/// ```
+///
+/// Relative paths are resolved against the directory containing the manifest.
+#[derive(serde::Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub output_dir: Option<PathBuf>,
+    // For assets which don't set their own; defaults to `DEFAULT_MAX_TEXTURE_SIZE`
+    #[serde(default)]
+    pub max_texture_size: Option<u32>,
+    // Keep the textures of assets which don't set their own max size at full resolution
+    #[serde(default)]
+    pub full_res_textures: bool,
+    pub assets: Vec<ManifestAsset>,
+}
+
+fn default_asset_scale() -> f32 {
+    1.0
+}
+
+#[derive(serde::Deserialize)]
+pub struct ManifestAsset {
+    pub source: PathBuf,
+    // Name of the baked files, without the extension
+    pub name: String,
+    #[serde(default = "default_asset_scale")]
+    pub scale: f32,
+    // Euler angles in degrees, as in the viewer's scene files
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    // How to generate missing normals: flat, smooth-area, or smooth-angle.
+    // Defaults to `--normals`
+    #[serde(default)]
+    pub normals: Option<String>,
+    // Preserve the node hierarchy, writing a `.scene` file and one `.mesh` per unique mesh
+    #[serde(default)]
+    pub hierarchy: bool,
+    // Weld vertices, strip degenerate triangles, and reorder the mesh data for rendering
+    #[serde(default)]
+    pub optimize: bool,
+    // Generate a chain of simplified index buffers, for the renderer to pick from by distance
+    #[serde(default)]
+    pub lods: bool,
+    // Bake every material as opaque and single-sided, so that ray tracing skips any hit shaders
+    #[serde(default)]
+    pub force_opaque: bool,
+    #[serde(default)]
+    pub textures: ManifestTextureSettings,
+    // Also write the baked textures as KTX2, for inspection with standard tools
+    #[serde(default)]
+    pub export_ktx2: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct ManifestTextureSettings {
+    pub mips: bool,
+    pub compress: bool,
+    // Encoder preset: ultrafast, veryfast, fast, basic, or slow. Defaults to `--texture-quality`
+    pub quality: Option<String>,
+    // Compression overriding the importer's choice for each material map:
+    // none, bc1, bc3, bc4, bc5, bc6h, or bc7
+    pub normal: Option<String>,
+    pub spec: Option<String>,
+    pub albedo: Option<String>,
+    pub emissive: Option<String>,
+    // Larger textures are downscaled to fit, preserving the aspect ratio
+    pub max_size: Option<u32>,
+    // Keep the textures at full resolution, regardless of any max size
+    pub full_res: bool,
+}
+
+impl Default for ManifestTextureSettings {
+    fn default() -> Self {
+        let defaults = TexBakeSettings::default();
+        Self {
+            mips: defaults.use_mips,
+            compress: defaults.compress,
+            quality: None,
+            normal: None,
+            spec: None,
+            albedo: None,
+            emissive: None,
+            max_size: None,
+            full_res: false,
+        }
+    }
+}
+
+impl ManifestTextureSettings {
+    /// `default_max_size` and `default_quality` apply unless the asset sets its own;
+    /// a `None` max size keeps the full resolution.
+    fn bake_settings(
+        &self,
+        default_max_size: Option<u32>,
+        default_quality: TexCompressionQuality,
+    ) -> anyhow::Result<TexBakeSettings> {
+        let parse_compression =
+            |mode: &Option<String>| -> anyhow::Result<Option<TexCompressionMode>> {
+                mode.as_deref().map(str::parse).transpose()
+            };
+
+        Ok(TexBakeSettings {
+            use_mips: self.mips,
+            compress: self.compress,
+            compression_quality: match &self.quality {
+                Some(quality) => quality.parse()?,
+                None => default_quality,
+            },
+            // In the order of `MeshMaterial::maps`
+            slot_compression: [
+                parse_compression(&self.normal)?,
+                parse_compression(&self.spec)?,
+                parse_compression(&self.albedo)?,
+                parse_compression(&self.emissive)?,
+            ],
+            max_size: if self.full_res {
+                None
+            } else {
+                self.max_size.or(default_max_size)
+            },
+        })
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Opening manifest {:?}", path))?;
+        let mut manifest: Self =
+            ron::de::from_reader(file).with_context(|| format!("Parsing manifest {:?}", path))?;
+
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        manifest.output_dir = manifest.output_dir.map(|dir| base_dir.join(dir));
+        for asset in &mut manifest.assets {
+            asset.source = base_dir.join(&asset.source);
+        }
+
+        // Assets are baked in parallel, so they must not overwrite each other's output
+        let mut names = HashSet::new();
+        for asset in &manifest.assets {
+            if !names.insert(asset.name.as_str()) {
+                anyhow::bail!(
+                    "Duplicate asset name {:?} in manifest {:?}",
+                    asset.name,
+                    path
+                );
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl ManifestAsset {
+    /// `max_texture_size`, `normals` and `texture_quality` apply unless the asset sets its own.
+    pub fn process_params(
+        &self,
+        output_dir: &Path,
+        max_texture_size: Option<u32>,
+        normals: NormalGeneration,
+        texture_quality: TexCompressionQuality,
+    ) -> anyhow::Result<MeshAssetProcessParams> {
+        Ok(MeshAssetProcessParams {
+            path: self.source.clone(),
+            output_name: self.name.clone(),
+            output_dir: output_dir.to_owned(),
+            scale: self.scale,
+            rotation: Quat::from_euler(
+                EulerRot::YXZ,
+                self.rotation[1].to_radians(),
+                self.rotation[0].to_radians(),
+                self.rotation[2].to_radians(),
+            ),
+            normal_generation: match &self.normals {
+                Some(normals) => normals.parse()?,
+                None => normals,
+            },
+            tex_settings: self
+                .textures
+                .bake_settings(max_texture_size, texture_quality)?,
+            optimize_mesh: self.optimize,
+            generate_lods: self.lods,
+            force_opaque: self.force_opaque,
+            export_ktx2: self.export_ktx2,
+        })
+    }
+}
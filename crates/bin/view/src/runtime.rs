@@ -21,7 +21,8 @@ use std::{
     collections::{hash_map::DefaultHasher, HashMap},
     fs::File,
     hash::{Hash, Hasher},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 pub const MAX_FPS_LIMIT: u32 = 256;
@@ -46,7 +47,20 @@ pub struct RuntimeState {
     sequence_playback_state: SequencePlaybackState,
     pub sequence_playback_speed: f32,
 
-    known_meshes: HashMap<PathBuf, MeshHandle>,
+    known_meshes: HashMap<PathBuf, KnownMesh>,
+}
+
+struct KnownMesh {
+    handle: MeshHandle,
+    // When the baked file was last written; a mesh whose file has been re-baked since gets loaded again
+    modified: Option<SystemTime>,
+}
+
+fn baked_file_modified(path: &Path) -> Option<SystemTime> {
+    canonical_path_from_vfs(path)
+        .ok()
+        .and_then(|path| path.metadata().ok())
+        .and_then(|metadata| metadata.modified().ok())
 }
 
 enum SequencePlaybackState {
@@ -611,26 +625,48 @@ impl RuntimeState {
                 let cached_mesh_name = format!("{:8.8x}", path_hash);
                 let cached_mesh_path = PathBuf::from(format!("/cache/{}.mesh", cached_mesh_name));
 
-                if !canonical_path_from_vfs(&cached_mesh_path).map_or(false, |path| path.exists()) {
-                    kajiya_asset_pipe::process_mesh_asset(
-                        kajiya_asset_pipe::MeshAssetProcessParams {
-                            path: path.clone(),
-                            output_name: cached_mesh_name,
-                            scale: 1.0,
-                        },
-                    )?;
+                // Checking whether the mesh is up to date means importing its source,
+                // so meshes which have already been loaded are reused as they are.
+                if let Some(mesh) = self.known_mesh(&cached_mesh_path) {
+                    return Ok(mesh);
                 }
 
+                // Only re-bakes if the mesh is missing, or its sources have changed
+                kajiya_asset_pipe::process_mesh_asset(kajiya_asset_pipe::MeshAssetProcessParams {
+                    path: path.clone(),
+                    output_name: cached_mesh_name,
+                    output_dir: "cache".into(),
+                    scale: 1.0,
+                    rotation: Quat::IDENTITY,
+                    normal_generation: Default::default(),
+                    tex_settings: Default::default(),
+                    optimize_mesh: false,
+                    generate_lods: false,
+                    force_opaque: false,
+                    export_ktx2: false,
+                })?;
+
                 cached_mesh_path
             }
             MeshSource::Cache(path) => path.clone(),
         };
 
-        Ok(*self.known_meshes.entry(path.clone()).or_insert_with(|| {
-            world_renderer
-                .add_baked_mesh(path, AddMeshOptions::new())
-                .unwrap()
-        }))
+        if let Some(mesh) = self.known_mesh(&path) {
+            return Ok(mesh);
+        }
+
+        let modified = baked_file_modified(&path);
+        let handle = world_renderer.add_baked_mesh(&path, AddMeshOptions::new())?;
+        self.known_meshes
+            .insert(path, KnownMesh { handle, modified });
+
+        Ok(handle)
+    }
+
+    /// The mesh previously loaded from `path`, unless the file has been re-baked since.
+    fn known_mesh(&self, path: &Path) -> Option<MeshHandle> {
+        let known = self.known_meshes.get(path)?;
+        (known.modified == baked_file_modified(path)).then(|| known.handle)
     }
 
     pub(crate) fn add_mesh_instance(
@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use kajiya_asset::asset_header::{validate_baked_asset_file, BakedAsset};
+
+/// Remembers the key of the inputs each baked file was produced from, so that files
+/// whose inputs haven't changed can be reused across runs.
+///
+/// Stored next to the baked files, one `<key> <file name>` line per file.
+pub struct BakeCache {
+    output_dir: PathBuf,
+    entries: HashMap<String, u64>,
+    // Only these are merged back into the file by `save`, which serializes with other
+    // saves, so that concurrent bakes into the same directory keep each other's entries.
+    changes: HashMap<String, Option<u64>>,
+}
+
+impl BakeCache {
+    const FILE_NAME: &'static str = "bake_cache.txt";
+
+    /// Loads the cache of `output_dir`. Starts empty if there's no cache yet.
+    pub fn load(output_dir: &Path) -> Self {
+        Self {
+            output_dir: output_dir.to_owned(),
+            entries: Self::read_entries(&output_dir.join(Self::FILE_NAME)),
+            changes: Default::default(),
+        }
+    }
+
+    fn read_entries(path: &Path) -> HashMap<String, u64> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                if path.exists() {
+                    log::warn!("Could not read the bake cache {:?}: {}", path, err);
+                }
+                return Default::default();
+            }
+        };
+
+        // Malformed lines are skipped; their files just get baked again.
+        contents
+            .lines()
+            .filter_map(|line| {
+                let (key, file_name) = line.split_once(' ')?;
+                Some((file_name.to_owned(), u64::from_str_radix(key, 16).ok()?))
+            })
+            .collect()
+    }
+
+    /// Whether `file_name` was baked from inputs with the given `key`, and is still a valid `T`.
+    pub fn is_fresh<T: BakedAsset>(&self, file_name: &str, key: u64) -> bool {
+        self.entries.get(file_name) == Some(&key)
+            && validate_baked_asset_file::<T>(&self.output_dir.join(file_name)).is_ok()
+    }
+
+    pub fn contains(&self, file_name: &str) -> bool {
+        self.entries.contains_key(file_name)
+    }
+
+    /// Whether `file_name` has been baked with any key, and is still a valid `T`.
+    pub fn is_baked<T: BakedAsset>(&self, file_name: &str) -> bool {
+        self.contains(file_name)
+            && validate_baked_asset_file::<T>(&self.output_dir.join(file_name)).is_ok()
+    }
+
+    /// Records that `file_name` has been baked from inputs with the given `key`.
+    pub fn insert(&mut self, file_name: String, key: u64) {
+        self.entries.insert(file_name.clone(), key);
+        self.changes.insert(file_name, Some(key));
+    }
+
+    pub fn remove(&mut self, file_name: &str) {
+        if self.entries.remove(file_name).is_some() {
+            self.changes.insert(file_name.to_owned(), None);
+        }
+    }
+
+    /// Merges the changes into the cache on disk.
+    ///
+    /// The read-merge-write is exclusive across the threads of this process, and
+    /// across processes through a lock file next to the cache.
+    pub fn save(&self) -> std::io::Result<()> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+
+        static SAVE_MUTEX: Mutex<()> = Mutex::new(());
+        let _guard = SAVE_MUTEX.lock().unwrap_or_else(|err| err.into_inner());
+
+        let path = self.output_dir.join(Self::FILE_NAME);
+        let _lock = CacheFileLock::acquire(&path.with_extension("lock"))?;
+
+        let mut entries = Self::read_entries(&path);
+        for (file_name, key) in &self.changes {
+            match key {
+                Some(key) => entries.insert(file_name.clone(), *key),
+                None => entries.remove(file_name),
+            };
+        }
+
+        let mut entries: Vec<(&String, &u64)> = entries.iter().collect();
+        entries.sort();
+
+        let contents: String = entries
+            .into_iter()
+            .map(|(file_name, key)| format!("{:016x} {}\n", key, file_name))
+            .collect();
+
+        write_file_atomically(&path, |file| file.write_all(contents.as_bytes()))
+    }
+}
+
+/// Exclusive ownership of a lock file, removed on drop.
+struct CacheFileLock {
+    path: PathBuf,
+}
+
+impl CacheFileLock {
+    // Saves take milliseconds; an older lock was left behind by a crashed process.
+    const STALE_AFTER: Duration = Duration::from_secs(30);
+
+    fn acquire(path: &Path) -> std::io::Result<Self> {
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+            {
+                Ok(_) => {
+                    return Ok(Self {
+                        path: path.to_owned(),
+                    })
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let is_stale = std::fs::metadata(path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                        .map_or(false, |age| age > Self::STALE_AFTER);
+
+                    if is_stale {
+                        log::warn!("Removing the stale bake cache lock {:?}", path);
+                        let _ = std::fs::remove_file(path);
+                    } else {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for CacheFileLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Writes `path` through a uniquely named temporary file next to it, which is then moved
+/// into place. Readers never see a partially written file, and the previous version stays
+/// intact for anyone who still has it open or mapped, e.g. a running viewer.
+///
+/// On failure, the temporary file is removed and `path` is left as it was.
+pub(crate) fn write_file_atomically<E: From<std::io::Error>>(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> Result<(), E>,
+) -> Result<(), E> {
+    static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let temp_path = path.with_file_name(format!(
+        "{}.{}.{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = File::create(&temp_path)
+        .map_err(E::from)
+        .and_then(|mut file| write(&mut file))
+        .and_then(|()| std::fs::rename(&temp_path, path).map_err(E::from));
+
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+
+    written
+}
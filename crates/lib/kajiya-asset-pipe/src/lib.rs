@@ -2,100 +2,709 @@ use async_channel::unbounded;
 use async_executor::Executor;
 use easy_parallel::Parallel;
 use glam::Quat;
-use kajiya_asset::mesh::{pack_triangle_mesh, GpuImage, LoadGltfScene, PackedTriMesh};
+use kajiya_asset::{
+    animation::pack_animation_set,
+    asset_header::{inspect_baked_asset_file, ASSET_FORMAT_VERSION},
+    image::ImageSource,
+    ktx2::write_ktx2,
+    mesh::{
+        hash_gltf_sources, hash_obj_sources, is_obj_path, pack_scene_hierarchy, pack_triangle_mesh,
+        roughness_normal_map, GpuImage, LoadGltfScene, LoadGltfSceneHierarchy, LoadObjScene,
+        MeshMaterialFlags, MeshMaterialMap, PackedAnimationSet, PackedScene, PackedTriMesh,
+        TangentStats, TriangleMesh,
+    },
+    optimize::{generate_lods, optimize_triangle_mesh, MeshOptimizationStats},
+    validate::{validate_gltf, ValidationSeverity},
+};
 use smol::future;
-use std::{collections::HashSet, fs::File, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use turbosloth::*;
+use twox_hash::XxHash64;
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+
+mod bake_cache;
+
+use bake_cache::{write_file_atomically, BakeCache};
+
+pub use kajiya_asset::mesh::{
+    NormalGeneration, TexBakeSettings, TexCompressionMode, TexCompressionQuality,
+    DEFAULT_MAX_TEXTURE_SIZE,
+};
 
 pub struct MeshAssetProcessParams {
     pub path: PathBuf,
     pub output_name: String,
+    // The renderer loads assets from the `/cache` VFS mount point, by default `cache`
+    pub output_dir: PathBuf,
     pub scale: f32,
+    pub rotation: Quat,
+    pub normal_generation: NormalGeneration,
+    pub tex_settings: TexBakeSettings,
+    // Weld vertices, strip degenerate triangles, and reorder the mesh data for rendering
+    pub optimize_mesh: bool,
+    // Store a chain of simplified index buffers with each mesh
+    pub generate_lods: bool,
+    // Bake every material as opaque and single-sided. A single alpha-tested or double-sided
+    // material makes the renderer run any hit shaders for every triangle of its mesh.
+    pub force_opaque: bool,
+    // Also write each baked image as `<identity>.ktx2`, for inspection with standard tools
+    pub export_ktx2: bool,
+}
+
+/// Bake keys are stored on disk, so they use a fixed algorithm and seed;
+/// `DefaultHasher` may change between Rust releases.
+fn bake_key_hasher() -> XxHash64 {
+    XxHash64::with_seed(0)
+}
+
+impl MeshAssetProcessParams {
+    /// Hash of everything the baked meshes depend on: the contents of the source files
+    /// (including images, whose changes can affect the mesh materials) and the bake parameters.
+    fn bake_key(&self, hierarchy: bool) -> Result<u64> {
+        let mut hasher = bake_key_hasher();
+        ASSET_FORMAT_VERSION.hash(&mut hasher);
+        hierarchy.hash(&mut hasher);
+        if is_obj_path(&self.path) {
+            hash_obj_sources(&self.path, &mut hasher)?;
+        } else {
+            hash_gltf_sources(&self.path, &mut hasher)?;
+        }
+        self.scale.to_bits().hash(&mut hasher);
+        self.rotation.x.to_bits().hash(&mut hasher);
+        self.rotation.y.to_bits().hash(&mut hasher);
+        self.rotation.z.to_bits().hash(&mut hasher);
+        self.rotation.w.to_bits().hash(&mut hasher);
+        self.normal_generation.hash(&mut hasher);
+        self.tex_settings.hash(&mut hasher);
+        self.optimize_mesh.hash(&mut hasher);
+        self.generate_lods.hash(&mut hasher);
+        self.force_opaque.hash(&mut hasher);
+        // Images are only revisited when the meshes get baked
+        self.export_ktx2.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+}
+
+fn hash_image_source(source: &ImageSource, state: &mut impl Hasher) -> Result<()> {
+    source.read()?.hash(state);
+    Ok(())
+}
+
+/// Hash of the sources of the baked image of `mesh.maps[map_idx]`. Its bake parameters
+/// are already part of the identity of the image, and thus of its file name.
+fn image_bake_key(mesh: &TriangleMesh, map_idx: usize) -> Result<u64> {
+    let mut hasher = bake_key_hasher();
+    ASSET_FORMAT_VERSION.hash(&mut hasher);
+
+    match &mesh.maps[map_idx] {
+        MeshMaterialMap::Image { source, .. } => hash_image_source(source, &mut hasher)?,
+        MeshMaterialMap::Placeholder(values) => values.hash(&mut hasher),
+    }
+
+    // The normal map's variance gets baked into the roughness
+    if let Some(normal_map) = roughness_normal_map(mesh, map_idx) {
+        hash_image_source(normal_map, &mut hasher)?;
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Checks a glTF source for problems before loading it. Writes `<output_name>.validation.txt`
+/// and `.validation.json` if any are found, and fails if the asset can't be baked.
+fn validate_source(opt: &MeshAssetProcessParams) -> Result<()> {
+    // OBJ files are parsed in one go; their problems surface when loading
+    if is_obj_path(&opt.path) {
+        return Ok(());
+    }
+
+    let report = validate_gltf(&opt.path)?;
+
+    let text_path = opt
+        .output_dir
+        .join(format!("{}.validation.txt", opt.output_name));
+    let json_path = opt
+        .output_dir
+        .join(format!("{}.validation.json", opt.output_name));
+
+    if report.issues.is_empty() {
+        // Reports from earlier bakes would be misleading
+        let _ = std::fs::remove_file(&text_path);
+        let _ = std::fs::remove_file(&json_path);
+        return Ok(());
+    }
+
+    let text = report.to_text();
+    print!("{}", text);
+
+    std::fs::write(&text_path, text).with_context(|| format!("Writing {:?}", text_path))?;
+    std::fs::write(&json_path, report.to_json())
+        .with_context(|| format!("Writing {:?}", json_path))?;
+
+    if report.has_errors() {
+        anyhow::bail!(
+            "{:?} failed validation with {} errors; see {:?}",
+            opt.path,
+            report.count(ValidationSeverity::Error),
+            text_path
+        );
+    }
+
+    Ok(())
+}
+
+/// Where the image of `mesh.maps[map_idx]` comes from, for error messages.
+fn image_source_name(mesh: &TriangleMesh, map_idx: usize) -> String {
+    match &mesh.maps[map_idx] {
+        MeshMaterialMap::Image {
+            source: ImageSource::File(path),
+            ..
+        } => path.display().to_string(),
+        MeshMaterialMap::Image {
+            source: ImageSource::Memory(_),
+            ..
+        } => format!("embedded image of material map {}", map_idx),
+        MeshMaterialMap::Placeholder(values) => format!("placeholder {:?}", values),
+    }
+}
+
+fn report_tangent_stats(stats: &TangentStats) {
+    if stats.generated_from_uvs > 0 || stats.synthesized_without_uvs > 0 {
+        println!(
+            "Generated tangents for {} vertices from their UVs, and synthesized them for {} without UVs",
+            stats.generated_from_uvs, stats.synthesized_without_uvs
+        );
+    }
+
+    if stats.degenerate_uv_triangles > 0 {
+        println!(
+            "{} triangles have degenerate UVs, and got arbitrary tangents",
+            stats.degenerate_uv_triangles
+        );
+    }
 }
 
+fn force_opaque_materials(mesh: &mut TriangleMesh) {
+    for material in &mut mesh.materials {
+        material.flags &= !(MeshMaterialFlags::MESH_MATERIAL_FLAGS_ALPHA_TESTED
+            | MeshMaterialFlags::MESH_MATERIAL_FLAG_DOUBLE_SIDED);
+    }
+}
+
+fn report_optimization_stats(stats: &MeshOptimizationStats) {
+    println!(
+        "Optimized the mesh: {} -> {} vertices, {} -> {} triangles ({} degenerate), ACMR {:.2} -> {:.2}",
+        stats.vertices_before,
+        stats.vertices_after,
+        stats.triangles_before,
+        stats.triangles_after,
+        stats.degenerate_triangles,
+        stats.acmr_before,
+        stats.acmr_after
+    );
+}
+
+fn report_lods(mesh: &TriangleMesh) {
+    if mesh.lods.is_empty() {
+        println!("The mesh is too simple for LODs");
+        return;
+    }
+
+    let lods: Vec<String> = mesh
+        .lods
+        .iter()
+        .map(|lod| format!("{} ({:.3})", lod.indices.len() / 3, lod.error))
+        .collect();
+
+    println!(
+        "Generated {} LODs from {} triangles; triangles (error): {}",
+        lods.len(),
+        mesh.indices.len() / 3,
+        lods.join(", ")
+    );
+}
+
+fn image_file_name(identity: u64) -> String {
+    format!("{:8.8x}.image", identity)
+}
+
+/// Whether the images referenced by the baked `mesh_file` are still there and valid, along
+/// with their KTX2 exports if requested. Their sources are part of the bake key of the mesh,
+/// so they are up to date whenever the mesh is.
+fn mesh_images_are_baked(
+    opt: &MeshAssetProcessParams,
+    bake_cache: &BakeCache,
+    mesh_file: &str,
+) -> bool {
+    let identities = inspect_baked_asset_file::<PackedTriMesh::Flat, _>(
+        &opt.output_dir.join(mesh_file),
+        |mesh| {
+            mesh.maps
+                .iter()
+                .map(|img| img.identity())
+                .collect::<Vec<u64>>()
+        },
+    );
+
+    identities.map_or(false, |identities| {
+        identities.into_iter().all(|identity| {
+            bake_cache.is_baked::<GpuImage::Flat>(&image_file_name(identity))
+                && (!opt.export_ktx2
+                    || opt
+                        .output_dir
+                        .join(format!("{:8.8x}.ktx2", identity))
+                        .exists())
+        })
+    })
+}
+
+/// The mesh files referenced by the baked `scene_file`.
+fn scene_mesh_files(opt: &MeshAssetProcessParams, scene_file: &str) -> Result<Vec<String>> {
+    inspect_baked_asset_file::<PackedScene::Flat, _>(&opt.output_dir.join(scene_file), |scene| {
+        scene
+            .mesh_names
+            .iter()
+            .map(|name| format!("{}.mesh", String::from_utf8_lossy(name.as_slice())))
+            .collect()
+    })
+}
+
+/// Writes `<output_name>.mesh`, `<output_name>.anim` for skinned meshes, and the images they use.
+///
+/// Files whose sources and parameters haven't changed since they were last baked
+/// into the same output directory are left alone.
 pub fn process_mesh_asset(opt: MeshAssetProcessParams) -> Result<()> {
     let lazy_cache = LazyCache::create();
 
-    std::fs::create_dir_all("cache")?;
+    std::fs::create_dir_all(&opt.output_dir)?;
+
+    let mut bake_cache = BakeCache::load(&opt.output_dir);
+    let bake_key = opt
+        .bake_key(false)
+        .with_context(|| format!("Hashing the sources of {:?}", opt.path))?;
+
+    let mesh_file = format!("{}.mesh", opt.output_name);
+    let anim_file = format!("{}.anim", opt.output_name);
+
+    if bake_cache.is_fresh::<PackedTriMesh::Flat>(&mesh_file, bake_key)
+        && (!bake_cache.contains(&anim_file)
+            || bake_cache.is_fresh::<PackedAnimationSet::Flat>(&anim_file, bake_key))
+        && mesh_images_are_baked(&opt, &bake_cache, &mesh_file)
+    {
+        println!("{:?} is up to date.", opt.path);
+        return Ok(());
+    }
+
+    validate_source(&opt)?;
 
     {
         println!("Loading {:?}...", opt.path);
 
-        let mesh = LoadGltfScene {
+        // Picked by the file extension; anything else is assumed to be glTF
+        let mesh = if is_obj_path(&opt.path) {
+            let mesh = LoadObjScene {
+                path: opt.path,
+                scale: opt.scale,
+                rotation: opt.rotation,
+                normal_generation: opt.normal_generation,
+            }
+            .into_lazy();
+
+            smol::block_on(mesh.eval(&lazy_cache))?
+        } else {
+            let mesh = LoadGltfScene {
+                path: opt.path,
+                scale: opt.scale,
+                rotation: opt.rotation,
+                normal_generation: opt.normal_generation,
+            }
+            .into_lazy();
+
+            smol::block_on(mesh.eval(&lazy_cache))?
+        };
+        report_tangent_stats(&mesh.tangent_stats);
+
+        let mut mesh = Cow::Borrowed(&*mesh);
+        if opt.force_opaque {
+            force_opaque_materials(mesh.to_mut());
+        }
+        if opt.optimize_mesh {
+            report_optimization_stats(&optimize_triangle_mesh(mesh.to_mut()));
+        }
+        if opt.generate_lods {
+            generate_lods(mesh.to_mut())?;
+            report_lods(&mesh);
+        }
+        let mesh = &*mesh;
+
+        // Discarded together if the images fail to bake
+        let mut written_files = Vec::new();
+
+        if !mesh.skeleton.is_empty() {
+            println!(
+                "Packing the skeleton ({} joints, {} animation clips)...",
+                mesh.skeleton.len(),
+                mesh.animations.len()
+            );
+
+            write_file_atomically(&opt.output_dir.join(&anim_file), |file| {
+                pack_animation_set(mesh).try_flatten_into(file)
+            })?;
+            bake_cache.insert(anim_file.clone(), bake_key);
+            written_files.push(anim_file);
+        } else {
+            bake_cache.remove(&anim_file);
+        }
+
+        let image_keys = (0..mesh.maps.len())
+            .map(|map_idx| image_bake_key(mesh, map_idx))
+            .collect::<Result<Vec<u64>>>()?;
+        let image_sources: Vec<String> = (0..mesh.maps.len())
+            .map(|map_idx| image_source_name(mesh, map_idx))
+            .collect();
+
+        println!("Packing the mesh...");
+        let mesh: PackedTriMesh::Proto = pack_triangle_mesh(mesh, &opt.tex_settings);
+
+        write_file_atomically(&opt.output_dir.join(&mesh_file), |file| {
+            mesh.try_flatten_into(file)
+        })?;
+        bake_cache.insert(mesh_file.clone(), bake_key);
+        written_files.push(mesh_file);
+
+        let images_baked = process_images(
+            &lazy_cache,
+            mesh.maps
+                .into_iter()
+                .zip(image_keys)
+                .zip(image_sources)
+                .map(|((img, key), source)| (img, key, source))
+                .collect(),
+            &opt.output_dir,
+            &mut bake_cache,
+            opt.export_ktx2,
+        );
+
+        if images_baked.is_err() {
+            discard_outputs(&mut bake_cache, &opt.output_dir, &written_files);
+        }
+
+        // Keeps the images which did get baked
+        bake_cache.save()?;
+        images_baked?;
+
+        println!("Done.");
+    }
+
+    Ok(())
+}
+
+/// Like `process_mesh_asset`, but preserves the node hierarchy of the source scene.
+///
+/// Writes `<output_name>.scene`, and one `<output_name>.<index>.mesh` per unique mesh.
+pub fn process_scene_asset(opt: MeshAssetProcessParams) -> Result<()> {
+    if is_obj_path(&opt.path) {
+        anyhow::bail!(
+            "{:?} has no node hierarchy; OBJ files can only be baked as a single mesh",
+            opt.path
+        );
+    }
+
+    let lazy_cache = LazyCache::create();
+
+    std::fs::create_dir_all(&opt.output_dir)?;
+
+    let mut bake_cache = BakeCache::load(&opt.output_dir);
+    let bake_key = opt
+        .bake_key(true)
+        .with_context(|| format!("Hashing the sources of {:?}", opt.path))?;
+
+    // The meshes are written along with the scene, under the same key
+    let scene_file = format!("{}.scene", opt.output_name);
+    let is_fresh = bake_cache.is_fresh::<PackedScene::Flat>(&scene_file, bake_key)
+        && scene_mesh_files(&opt, &scene_file).map_or(false, |mesh_files| {
+            mesh_files.iter().all(|mesh_file| {
+                bake_cache.is_fresh::<PackedTriMesh::Flat>(mesh_file, bake_key)
+                    && mesh_images_are_baked(&opt, &bake_cache, mesh_file)
+            })
+        });
+
+    if is_fresh {
+        println!("{:?} is up to date.", opt.path);
+        return Ok(());
+    }
+
+    validate_source(&opt)?;
+
+    {
+        println!("Loading {:?}...", opt.path);
+
+        let scene = LoadGltfSceneHierarchy {
             path: opt.path,
             scale: opt.scale,
-            //rotation: Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
-            rotation: Quat::IDENTITY,
+            rotation: opt.rotation,
+            normal_generation: opt.normal_generation,
         }
         .into_lazy();
 
-        let mesh = &*smol::block_on(mesh.eval(&lazy_cache))?;
+        let scene = &*smol::block_on(scene.eval(&lazy_cache))?;
 
-        println!("Packing the mesh...");
-        let mesh: PackedTriMesh::Proto = pack_triangle_mesh(mesh);
-
-        mesh.flatten_into(&mut File::create(format!(
-            "cache/{}.mesh",
-            opt.output_name
-        ))?);
-        let unique_images: Vec<Lazy<GpuImage::Proto>> = mesh
-            .maps
-            .into_iter()
-            .collect::<HashSet<_>>()
-            .into_iter()
-            .collect::<Vec<_>>();
-
-        let ex = &Executor::new();
-        let (signal, shutdown) = unbounded::<()>();
-
-        // Prepare tasks for processing all images
-        let lazy_cache = &lazy_cache;
-        let images = unique_images.iter().cloned().map(|img| async move {
-            let loaded = img.eval(lazy_cache).await?;
-            let img_dst = PathBuf::from(format!("cache/{:8.8x}.image", img.identity()));
-
-            match File::create(&img_dst) {
-                Ok(mut file) => loaded.flatten_into(&mut file),
-                Err(err) => {
-                    if img_dst.exists() {
-                        log::info!("Could not create {:?}; ignoring", img_dst);
-                    } else {
-                        anyhow::anyhow!(err);
-                    }
-                }
-            };
-
-            anyhow::Result::<()>::Ok(())
-        });
+        let mut tangent_stats = TangentStats::default();
+        for mesh in &scene.meshes {
+            tangent_stats += mesh.tangent_stats;
+        }
+        report_tangent_stats(&tangent_stats);
+
+        println!(
+            "Packing {} meshes referenced by {} nodes...",
+            scene.meshes.len(),
+            scene.nodes.len()
+        );
+
+        let mut mesh_names = Vec::with_capacity(scene.meshes.len());
+        let mut mesh_files = Vec::with_capacity(scene.meshes.len());
+        let mut maps = Vec::new();
+        let mut optimization_stats = MeshOptimizationStats::default();
+        let mut lod_count = 0;
+
+        for (mesh_idx, mesh) in scene.meshes.iter().enumerate() {
+            let mesh_name = format!("{}.{}", opt.output_name, mesh_idx);
+            let mesh_file = format!("{}.mesh", mesh_name);
+
+            let mut mesh = Cow::Borrowed(mesh);
+            if opt.force_opaque {
+                force_opaque_materials(mesh.to_mut());
+            }
+            if opt.optimize_mesh {
+                optimization_stats += optimize_triangle_mesh(mesh.to_mut());
+            }
+            if opt.generate_lods {
+                generate_lods(mesh.to_mut())?;
+                lod_count += mesh.lods.len();
+            }
+            let mesh = &*mesh;
+
+            let image_keys = (0..mesh.maps.len())
+                .map(|map_idx| image_bake_key(mesh, map_idx))
+                .collect::<Result<Vec<u64>>>()?;
+            let image_sources: Vec<String> = (0..mesh.maps.len())
+                .map(|map_idx| image_source_name(mesh, map_idx))
+                .collect();
+
+            let mesh: PackedTriMesh::Proto = pack_triangle_mesh(mesh, &opt.tex_settings);
+            write_file_atomically(&opt.output_dir.join(&mesh_file), |file| {
+                mesh.try_flatten_into(file)
+            })?;
+            bake_cache.insert(mesh_file.clone(), bake_key);
+
+            maps.extend(
+                mesh.maps
+                    .into_iter()
+                    .zip(image_keys)
+                    .zip(image_sources)
+                    .map(|((img, key), source)| (img, key, source)),
+            );
+            mesh_names.push(mesh_name);
+            mesh_files.push(mesh_file);
+        }
+
+        if opt.optimize_mesh {
+            report_optimization_stats(&optimization_stats);
+        }
+        if opt.generate_lods {
+            println!("Generated {} LODs in total", lod_count);
+        }
+
+        write_file_atomically(&opt.output_dir.join(&scene_file), |file| {
+            pack_scene_hierarchy(scene, &mesh_names).try_flatten_into(file)
+        })?;
+        bake_cache.insert(scene_file.clone(), bake_key);
+
+        let images_baked = process_images(
+            &lazy_cache,
+            maps,
+            &opt.output_dir,
+            &mut bake_cache,
+            opt.export_ktx2,
+        );
 
-        // Now spawn them onto the executor
-        let images = images.map(|task| ex.spawn(task));
-        let image_count = images.len();
-
-        if image_count > 0 {
-            // A task to join them all
-            let all_images = futures::future::try_join_all(images);
-
-            println!("Processing {} images...", image_count);
-
-            // Now spawn threads for the executor and run it to completion
-            Parallel::new()
-                .each(0..num_cpus::get(), |_| {
-                    future::block_on(ex.run(shutdown.recv()))
-                })
-                .finish(|| {
-                    future::block_on(async {
-                        all_images.await.expect("Failed to load mesh images");
-                        drop(signal);
-                    })
-                });
+        if images_baked.is_err() {
+            mesh_files.push(scene_file);
+            discard_outputs(&mut bake_cache, &opt.output_dir, &mesh_files);
         }
 
+        // Keeps the images which did get baked
+        bake_cache.save()?;
+        images_baked?;
+
         println!("Done.");
     }
 
     Ok(())
 }
+
+/// An image which could not be baked.
+#[derive(Debug)]
+pub struct ImageBakeFailure {
+    // Where the image comes from, e.g. the path of its source file
+    pub source: String,
+    // The baked file which was not written
+    pub file_name: String,
+    pub cause: anyhow::Error,
+}
+
+/// All the images of an asset which failed to bake.
+#[derive(Debug)]
+pub struct ImageBakeError {
+    pub failures: Vec<ImageBakeFailure>,
+}
+
+impl std::fmt::Display for ImageBakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} images failed to bake:", self.failures.len())?;
+        for failure in &self.failures {
+            write!(
+                f,
+                "\n  {} ({}): {:#}",
+                failure.source, failure.file_name, failure.cause
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ImageBakeError {}
+
+/// Removes baked files which reference images that failed to bake, so that the next
+/// bake doesn't consider them up to date.
+fn discard_outputs(bake_cache: &mut BakeCache, output_dir: &Path, file_names: &[String]) {
+    for file_name in file_names {
+        bake_cache.remove(file_name);
+        let _ = std::fs::remove_file(output_dir.join(file_name));
+    }
+}
+
+/// Bakes one image into `output_dir`, returning the cache entry of the written file.
+/// A failure leaves any previously baked version of the image untouched.
+async fn bake_image(
+    lazy_cache: &Arc<LazyCache>,
+    img: &Lazy<GpuImage::Proto>,
+    key: u64,
+    output_dir: &Path,
+    export_ktx2: bool,
+) -> Result<(String, u64)> {
+    let loaded = img.eval(lazy_cache).await?;
+
+    // The renderer binds material maps as 2D textures
+    if loaded.cube || loaded.array_layers > 1 {
+        anyhow::bail!(
+            "Cube maps and texture arrays can't be used as material maps; this one has {} {}",
+            loaded.array_layers,
+            if loaded.cube { "cube faces" } else { "layers" }
+        );
+    }
+
+    let img_file = image_file_name(img.identity());
+    let img_dst = output_dir.join(&img_file);
+
+    if export_ktx2 {
+        let ktx2_dst = output_dir.join(format!("{:8.8x}.ktx2", img.identity()));
+        write_file_atomically(&ktx2_dst, |file| write_ktx2(&loaded, file))
+            .with_context(|| format!("Writing {:?}", ktx2_dst))?;
+    }
+
+    // An existing file which can't be replaced would leave a stale texture behind
+    write_file_atomically(&img_dst, |file| loaded.try_flatten_into(file))
+        .with_context(|| format!("Writing {:?}", img_dst))?;
+
+    Ok((img_file, key))
+}
+
+/// Bakes the images which aren't up to date in `bake_cache`. Each map comes with its
+/// bake key, and a description of its source for error messages.
+///
+/// All the images are attempted; the ones which fail are listed in an `ImageBakeError`.
+fn process_images(
+    lazy_cache: &Arc<LazyCache>,
+    maps: Vec<(Lazy<GpuImage::Proto>, u64, String)>,
+    output_dir: &Path,
+    bake_cache: &mut BakeCache,
+    export_ktx2: bool,
+) -> Result<()> {
+    // Maps sharing an image are baked once
+    let unique_images: Vec<((Lazy<GpuImage::Proto>, u64), String)> = maps
+        .into_iter()
+        .map(|(img, key, source)| ((img, key), source))
+        .collect::<HashMap<_, _>>()
+        .into_iter()
+        .filter(|((img, key), _)| {
+            !bake_cache.is_fresh::<GpuImage::Flat>(&image_file_name(img.identity()), *key)
+                || (export_ktx2
+                    && !output_dir
+                        .join(format!("{:8.8x}.ktx2", img.identity()))
+                        .exists())
+        })
+        .collect::<Vec<_>>();
+
+    let ex = &Executor::new();
+    let (signal, shutdown) = unbounded::<()>();
+
+    // Prepare tasks for processing all images. Each yields the cache entry of the written file.
+    let images = unique_images
+        .into_iter()
+        .map(|((img, key), source)| async move {
+            let result = bake_image(lazy_cache, &img, key, output_dir, export_ktx2).await;
+            (img, source, result)
+        });
+
+    // Now spawn them onto the executor
+    let images = images.map(|task| ex.spawn(task));
+    let image_count = images.len();
+
+    if image_count == 0 {
+        return Ok(());
+    }
+
+    // A task to join them all
+    let all_images = futures::future::join_all(images);
+
+    println!("Processing {} images...", image_count);
+
+    // Now spawn threads for the executor and run it to completion
+    let (_, results) = Parallel::new()
+        .each(0..num_cpus::get(), |_| {
+            future::block_on(ex.run(shutdown.recv()))
+        })
+        .finish(|| {
+            future::block_on(async {
+                let results = all_images.await;
+                drop(signal);
+                results
+            })
+        });
+
+    let mut failures = Vec::new();
+    for (img, source, result) in results {
+        match result {
+            Ok((img_file, key)) => bake_cache.insert(img_file, key),
+            Err(cause) => failures.push(ImageBakeFailure {
+                source,
+                file_name: image_file_name(img.identity()),
+                cause,
+            }),
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(ImageBakeError { failures }.into())
+    }
+}
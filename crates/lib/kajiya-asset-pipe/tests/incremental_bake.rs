@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+
+use glam::Quat;
+use kajiya_asset::{ktx2::write_ktx2, mesh::GpuImage};
+use kajiya_asset_pipe::{
+    process_mesh_asset, process_scene_asset, MeshAssetProcessParams, NormalGeneration,
+};
+use kajiya_backend::ash::vk;
+
+// One textured triangle
+const SCENE_GLTF: &str = r#"{
+    "asset": { "version": "2.0" },
+    "scene": 0,
+    "scenes": [{ "nodes": [0] }],
+    "nodes": [{ "mesh": 0 }],
+    "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "TEXCOORD_0": 1 }, "material": 0 }] }],
+    "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } }],
+    "textures": [{ "source": 0 }],
+    "images": [{ "uri": "albedo.ktx2" }],
+    "buffers": [{ "uri": "scene.bin", "byteLength": 60 }],
+    "bufferViews": [
+        { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+        { "buffer": 0, "byteOffset": 36, "byteLength": 24 }
+    ],
+    "accessors": [
+        { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+        { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }
+    ]
+}"#;
+
+fn write_scene(root: &Path) {
+    std::fs::create_dir_all(root).unwrap();
+    std::fs::write(root.join("scene.gltf"), SCENE_GLTF).unwrap();
+
+    let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+    let uvs = [0.0f32, 0.0, 1.0, 0.0, 0.0, 1.0];
+    let buffer: Vec<u8> = positions
+        .iter()
+        .chain(uvs.iter())
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    std::fs::write(root.join("scene.bin"), buffer).unwrap();
+
+    let image = GpuImage::Proto {
+        format: vk::Format::R8G8B8A8_SRGB,
+        extent: [2, 2, 1],
+        array_layers: 1,
+        cube: false,
+        mips: vec![(0..16).collect(), vec![1, 2, 3, 4]],
+    };
+    let mut ktx2 = Vec::new();
+    write_ktx2(&image, &mut ktx2).unwrap();
+    std::fs::write(root.join("albedo.ktx2"), ktx2).unwrap();
+}
+
+fn params(root: &Path) -> MeshAssetProcessParams {
+    MeshAssetProcessParams {
+        path: root.join("scene.gltf"),
+        output_name: "scene".to_owned(),
+        output_dir: root.join("cache"),
+        scale: 1.0,
+        rotation: Quat::IDENTITY,
+        normal_generation: NormalGeneration::Flat,
+        tex_settings: Default::default(),
+        optimize_mesh: false,
+        generate_lods: false,
+        force_opaque: false,
+        export_ktx2: false,
+    }
+}
+
+fn baked_images(output_dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(output_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "image"))
+        .collect()
+}
+
+/// Bakes with `process`, deletes the baked images, and checks that baking again restores them.
+fn check_deleted_images_get_rebaked(
+    name: &str,
+    process: fn(MeshAssetProcessParams) -> anyhow::Result<()>,
+) {
+    let root = std::env::temp_dir().join(format!(
+        "kajiya-incremental-{}-{}",
+        name,
+        std::process::id()
+    ));
+    write_scene(&root);
+
+    process(params(&root)).unwrap();
+    let images = baked_images(&root.join("cache"));
+    assert!(!images.is_empty());
+
+    for image in &images {
+        std::fs::remove_file(image).unwrap();
+    }
+
+    // The mesh itself is still up to date
+    process(params(&root)).unwrap();
+    let rebaked = baked_images(&root.join("cache"));
+    let _ = std::fs::remove_dir_all(&root);
+
+    assert_eq!(images.len(), rebaked.len());
+}
+
+#[test]
+fn deleted_mesh_images_get_rebaked() {
+    check_deleted_images_get_rebaked("mesh", process_mesh_asset);
+}
+
+#[test]
+fn deleted_scene_images_get_rebaked() {
+    check_deleted_images_get_rebaked("scene", process_scene_asset);
+}
@@ -0,0 +1,142 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use glam::Quat;
+use kajiya_asset::{ktx2::write_ktx2, mesh::GpuImage};
+use kajiya_asset_pipe::{process_mesh_asset, MeshAssetProcessParams, NormalGeneration};
+use kajiya_backend::{ash::vk, set_vfs_mount_point};
+
+// One textured triangle, with the texture referenced relative to the document
+const SCENE_GLTF: &str = r#"{
+    "asset": { "version": "2.0" },
+    "scene": 0,
+    "scenes": [{ "nodes": [0] }],
+    "nodes": [{ "mesh": 0 }],
+    "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "TEXCOORD_0": 1 }, "material": 0 }] }],
+    "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } }],
+    "textures": [{ "source": 0 }],
+    "images": [{ "uri": "textures/albedo.ktx2" }],
+    "buffers": [{ "uri": "scene.bin", "byteLength": 60 }],
+    "bufferViews": [
+        { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+        { "buffer": 0, "byteOffset": 36, "byteLength": 24 }
+    ],
+    "accessors": [
+        { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+        { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }
+    ]
+}"#;
+
+// The same triangle and texture, with the texture referenced relative to the MTL library
+const SCENE_OBJ: &str = "mtllib materials/scene.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0 1
+vt 1 1
+vt 0 0
+usemtl albedo
+f 1/1 2/2 3/3
+";
+
+const SCENE_MTL: &str = "newmtl albedo
+Kd 1 1 1
+map_Kd ../textures/albedo.ktx2
+";
+
+fn write_scene(root: &Path) {
+    std::fs::create_dir_all(root.join("textures")).unwrap();
+    std::fs::create_dir_all(root.join("materials")).unwrap();
+    std::fs::write(root.join("scene.gltf"), SCENE_GLTF).unwrap();
+    std::fs::write(root.join("scene.obj"), SCENE_OBJ).unwrap();
+    std::fs::write(root.join("materials/scene.mtl"), SCENE_MTL).unwrap();
+
+    let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+    let uvs = [0.0f32, 0.0, 1.0, 0.0, 0.0, 1.0];
+    let buffer: Vec<u8> = positions
+        .iter()
+        .chain(uvs.iter())
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    std::fs::write(root.join("scene.bin"), buffer).unwrap();
+
+    let image = GpuImage::Proto {
+        format: vk::Format::R8G8B8A8_SRGB,
+        extent: [2, 2, 1],
+        array_layers: 1,
+        cube: false,
+        mips: vec![(0..16).collect(), vec![1, 2, 3, 4]],
+    };
+    let mut ktx2 = Vec::new();
+    write_ktx2(&image, &mut ktx2).unwrap();
+    std::fs::write(root.join("textures/albedo.ktx2"), ktx2).unwrap();
+}
+
+/// Bakes `scene_file` with `mount_point` mounted at `root`. Returns the contents of the baked files.
+fn bake_under(mount_point: &str, root: &Path, scene_file: &str) -> BTreeMap<String, Vec<u8>> {
+    set_vfs_mount_point(mount_point, root);
+
+    let output_dir = root.join("cache");
+    process_mesh_asset(MeshAssetProcessParams {
+        path: PathBuf::from(mount_point).join(scene_file),
+        output_name: "scene".to_owned(),
+        output_dir: output_dir.clone(),
+        scale: 1.0,
+        rotation: Quat::IDENTITY,
+        normal_generation: NormalGeneration::Flat,
+        tex_settings: Default::default(),
+        optimize_mesh: false,
+        generate_lods: false,
+        force_opaque: false,
+        export_ktx2: false,
+    })
+    .unwrap();
+
+    std::fs::read_dir(&output_dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, std::fs::read(&path).unwrap())
+        })
+        .filter(|(name, _)| name.ends_with(".mesh") || name.ends_with(".image"))
+        .collect()
+}
+
+/// Bakes `scene_file` under two different roots, and checks that the results are the same.
+fn check_mount_root_independence(mount_point: &str, scene_file: &str) {
+    let temp_dir = std::env::temp_dir().join(format!(
+        "kajiya-mount-roots-{}-{}",
+        scene_file,
+        std::process::id()
+    ));
+    let root_a = temp_dir.join("a");
+    let root_b = temp_dir.join("checkout-b");
+    write_scene(&root_a);
+    write_scene(&root_b);
+
+    let baked_a = bake_under(mount_point, &root_a, scene_file);
+    let baked_b = bake_under(mount_point, &root_b, scene_file);
+    let _ = std::fs::remove_dir_all(&temp_dir);
+
+    // The meshes refer to their images by identity, which is also the name of each image file
+    assert!(baked_a.keys().any(|name| name.ends_with(".image")));
+    assert_eq!(
+        baked_a.keys().collect::<Vec<_>>(),
+        baked_b.keys().collect::<Vec<_>>()
+    );
+    assert!(baked_a == baked_b);
+}
+
+// Each test uses its own mount point, since they run in parallel
+#[test]
+fn baked_assets_dont_depend_on_the_mount_root() {
+    check_mount_root_independence("/mount-roots-test", "scene.gltf");
+}
+
+#[test]
+fn baked_obj_assets_dont_depend_on_the_mount_root() {
+    check_mount_root_independence("/mount-roots-obj-test", "scene.obj");
+}
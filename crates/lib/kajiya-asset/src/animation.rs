@@ -0,0 +1,371 @@
+use glam::{Mat4, Quat, Vec3, Vec4};
+
+use crate::mesh::{impl_archive_as_self, PackedAnimationSet, TriangleMesh};
+
+/// Marks a joint with no parent joint. Its `parent_xform` takes the place of a parent.
+pub const NO_PARENT_JOINT: u32 = !0;
+
+#[derive(Clone, Copy, bytecheck::CheckBytes)]
+#[repr(C)]
+pub struct SkeletonJoint {
+    pub parent: u32,
+    // Rest pose, relative to the parent joint
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    // Accumulated transform of any non-joint ancestors; only used by root joints
+    pub parent_xform: [f32; 16],
+    // Maps from the space the mesh vertices are stored in to the joint's bind space
+    pub inverse_bind: [f32; 16],
+}
+
+impl SkeletonJoint {
+    fn rest_translation(&self) -> Vec3 {
+        Vec3::from(self.translation)
+    }
+
+    fn rest_rotation(&self) -> Quat {
+        Quat::from_xyzw(
+            self.rotation[0],
+            self.rotation[1],
+            self.rotation[2],
+            self.rotation[3],
+        )
+    }
+
+    fn rest_scale(&self) -> Vec3 {
+        Vec3::from(self.scale)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bytecheck::CheckBytes)]
+#[repr(u32)]
+pub enum AnimationProperty {
+    Translation = 0,
+    Rotation = 1,
+    Scale = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bytecheck::CheckBytes)]
+#[repr(u32)]
+pub enum AnimationInterpolation {
+    Linear = 0,
+    Step = 1,
+    // Each keyframe holds three values: in-tangent, value, out-tangent
+    CubicSpline = 2,
+}
+
+#[derive(Clone)]
+pub struct AnimationChannel {
+    pub joint: u32,
+    pub property: AnimationProperty,
+    pub interpolation: AnimationInterpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<[f32; 4]>,
+}
+
+impl AnimationChannel {
+    pub fn as_ref(&self) -> AnimationChannelRef<'_> {
+        AnimationChannelRef {
+            joint: self.joint,
+            property: self.property,
+            interpolation: self.interpolation,
+            times: &self.times,
+            values: &self.values,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<AnimationChannel>,
+}
+
+impl AnimationClip {
+    /// Sample the clip at time `t` (in seconds), and return one skinning matrix per joint.
+    /// The time is clamped to the clip's range; looping is up to the caller.
+    pub fn sample(&self, skeleton: &[SkeletonJoint], t: f32) -> anyhow::Result<Vec<Mat4>> {
+        sample_skinning_matrices(skeleton, self.channels.iter().map(|ch| ch.as_ref()), t)
+    }
+}
+
+/// Borrowed view of an animation channel, shared by the in-memory and baked representations.
+#[derive(Clone, Copy)]
+pub struct AnimationChannelRef<'a> {
+    pub joint: u32,
+    pub property: AnimationProperty,
+    pub interpolation: AnimationInterpolation,
+    pub times: &'a [f32],
+    pub values: &'a [[f32; 4]],
+}
+
+impl<'a> AnimationChannelRef<'a> {
+    fn sample(&self, t: f32) -> Vec4 {
+        let key_count = self.times.len();
+        if key_count == 0 {
+            return Vec4::ZERO;
+        }
+
+        let value = |key: usize| -> Vec4 {
+            match self.interpolation {
+                AnimationInterpolation::CubicSpline => Vec4::from(self.values[key * 3 + 1]),
+                _ => Vec4::from(self.values[key]),
+            }
+        };
+
+        // A NaN time would fail both clamps, and the key search below
+        if t.is_nan() || t <= self.times[0] {
+            return value(0);
+        }
+        if t >= self.times[key_count - 1] {
+            return value(key_count - 1);
+        }
+
+        // Index of the last key at or before `t`
+        let k0 = self.times.partition_point(|&key_t| key_t <= t) - 1;
+        let k1 = k0 + 1;
+
+        let t0 = self.times[k0];
+        let dt = self.times[k1] - t0;
+        let s = if dt > 0.0 { (t - t0) / dt } else { 0.0 };
+
+        match self.interpolation {
+            AnimationInterpolation::Step => value(k0),
+            AnimationInterpolation::Linear => {
+                if self.property == AnimationProperty::Rotation {
+                    Vec4::from(quat_from_vec4(value(k0)).slerp(quat_from_vec4(value(k1)), s))
+                } else {
+                    value(k0).lerp(value(k1), s)
+                }
+            }
+            AnimationInterpolation::CubicSpline => {
+                let p0 = value(k0);
+                let m0 = Vec4::from(self.values[k0 * 3 + 2]) * dt;
+                let p1 = value(k1);
+                let m1 = Vec4::from(self.values[k1 * 3]) * dt;
+
+                let s2 = s * s;
+                let s3 = s2 * s;
+
+                let res = p0 * (2.0 * s3 - 3.0 * s2 + 1.0)
+                    + m0 * (s3 - 2.0 * s2 + s)
+                    + p1 * (-2.0 * s3 + 3.0 * s2)
+                    + m1 * (s3 - s2);
+
+                if self.property == AnimationProperty::Rotation {
+                    res.normalize()
+                } else {
+                    res
+                }
+            }
+        }
+    }
+}
+
+fn quat_from_vec4(v: Vec4) -> Quat {
+    Quat::from_xyzw(v.x, v.y, v.z, v.w)
+}
+
+/// Evaluate the animated pose of `skeleton` at time `t`, and return one skinning matrix per joint.
+/// The matrices transform mesh vertices from the space they were baked in to their animated position.
+///
+/// Fails unless the joints are ordered such that parents precede their children.
+pub fn sample_skinning_matrices<'a>(
+    skeleton: &[SkeletonJoint],
+    channels: impl Iterator<Item = AnimationChannelRef<'a>>,
+    t: f32,
+) -> anyhow::Result<Vec<Mat4>> {
+    for (joint_idx, joint) in skeleton.iter().enumerate() {
+        if joint.parent != NO_PARENT_JOINT && joint.parent as usize >= joint_idx {
+            anyhow::bail!(
+                "Joint {} has the parent {}, which isn't stored before it",
+                joint_idx,
+                joint.parent
+            );
+        }
+    }
+
+    let mut translations: Vec<Vec3> = skeleton.iter().map(|j| j.rest_translation()).collect();
+    let mut rotations: Vec<Quat> = skeleton.iter().map(|j| j.rest_rotation()).collect();
+    let mut scales: Vec<Vec3> = skeleton.iter().map(|j| j.rest_scale()).collect();
+
+    for channel in channels {
+        let joint = channel.joint as usize;
+        if joint >= skeleton.len() {
+            continue;
+        }
+
+        let v = channel.sample(t);
+        match channel.property {
+            AnimationProperty::Translation => translations[joint] = v.truncate(),
+            AnimationProperty::Rotation => rotations[joint] = quat_from_vec4(v).normalize(),
+            AnimationProperty::Scale => scales[joint] = v.truncate(),
+        }
+    }
+
+    let mut global_xforms: Vec<Mat4> = Vec::with_capacity(skeleton.len());
+    for (joint_idx, joint) in skeleton.iter().enumerate() {
+        let local = Mat4::from_scale_rotation_translation(
+            scales[joint_idx],
+            rotations[joint_idx],
+            translations[joint_idx],
+        );
+
+        let parent = if joint.parent == NO_PARENT_JOINT {
+            Mat4::from_cols_array(&joint.parent_xform)
+        } else {
+            global_xforms[joint.parent as usize]
+        };
+
+        global_xforms.push(parent * local);
+    }
+
+    Ok(global_xforms
+        .iter()
+        .zip(skeleton)
+        .map(|(global, joint)| *global * Mat4::from_cols_array(&joint.inverse_bind))
+        .collect())
+}
+
+#[derive(Clone, Copy, bytecheck::CheckBytes)]
+#[repr(C)]
+pub struct PackedAnimationClip {
+    pub duration: f32,
+    pub first_channel: u32,
+    pub channel_count: u32,
+}
+
+#[derive(Clone, Copy, bytecheck::CheckBytes)]
+#[repr(C)]
+pub struct PackedAnimationChannel {
+    pub joint: u32,
+    pub property: AnimationProperty,
+    pub interpolation: AnimationInterpolation,
+    pub first_key: u32,
+    pub key_count: u32,
+    pub first_value: u32,
+    pub value_count: u32,
+}
+
+impl_archive_as_self!(SkeletonJoint, PackedAnimationClip, PackedAnimationChannel);
+
+impl PackedAnimationSet::Flat {
+    pub fn clip_count(&self) -> usize {
+        self.clips.len()
+    }
+
+    pub fn clip_name(&self, clip_idx: usize) -> &str {
+        std::str::from_utf8(self.clip_names[clip_idx].as_slice()).unwrap_or_default()
+    }
+
+    pub fn clip_duration(&self, clip_idx: usize) -> f32 {
+        self.clips[clip_idx].duration
+    }
+
+    pub fn find_clip(&self, name: &str) -> Option<usize> {
+        (0..self.clip_count()).find(|&clip_idx| self.clip_name(clip_idx) == name)
+    }
+
+    /// Sample the clip at time `t` (in seconds), and return one skinning matrix per joint.
+    /// The time is clamped to the clip's range; looping is up to the caller.
+    ///
+    /// Only the structure of the archive is validated on load, so the ranges and joint
+    /// order read from the file are checked here; a corrupt file results in an error.
+    pub fn sample(&self, clip_idx: usize, t: f32) -> anyhow::Result<Vec<Mat4>> {
+        let clip = self
+            .clips
+            .get(clip_idx)
+            .ok_or_else(|| anyhow::anyhow!("No animation clip {}", clip_idx))?;
+        let channels = packed_range(
+            self.channels.as_slice(),
+            clip.first_channel,
+            clip.channel_count,
+        )
+        .ok_or_else(|| anyhow::anyhow!("Clip {} has out-of-range channels", clip_idx))?;
+
+        let times = self.keyframe_times.as_slice();
+        let values = self.keyframe_values.as_slice();
+
+        let channels = channels
+            .iter()
+            .enumerate()
+            .map(|(channel_idx, ch)| {
+                let times = packed_range(times, ch.first_key, ch.key_count);
+                let values = packed_range(values, ch.first_value, ch.value_count);
+
+                let values_per_key = match ch.interpolation {
+                    AnimationInterpolation::CubicSpline => 3,
+                    _ => 1,
+                };
+
+                match (times, values) {
+                    (Some(times), Some(values)) if values.len() >= times.len() * values_per_key => {
+                        Ok(AnimationChannelRef {
+                            joint: ch.joint,
+                            property: ch.property,
+                            interpolation: ch.interpolation,
+                            times,
+                            values,
+                        })
+                    }
+                    _ => Err(anyhow::anyhow!(
+                        "Channel {} of clip {} has out-of-range keyframes, or too few values",
+                        channel_idx,
+                        clip_idx
+                    )),
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        sample_skinning_matrices(self.skeleton.as_slice(), channels.into_iter(), t)
+    }
+}
+
+/// `count` elements of `items` starting at `first`, if they're all there.
+fn packed_range<T>(items: &[T], first: u32, count: u32) -> Option<&[T]> {
+    let first = first as usize;
+    items.get(first..first.checked_add(count as usize)?)
+}
+
+pub fn pack_animation_set(mesh: &TriangleMesh) -> PackedAnimationSet::Proto {
+    let mut clips = Vec::with_capacity(mesh.animations.len());
+    let mut clip_names = Vec::with_capacity(mesh.animations.len());
+    let mut channels = Vec::new();
+    let mut keyframe_times = Vec::new();
+    let mut keyframe_values = Vec::new();
+
+    for clip in &mesh.animations {
+        clips.push(PackedAnimationClip {
+            duration: clip.duration,
+            first_channel: channels.len() as u32,
+            channel_count: clip.channels.len() as u32,
+        });
+        clip_names.push(clip.name.as_bytes().to_vec());
+
+        for ch in &clip.channels {
+            channels.push(PackedAnimationChannel {
+                joint: ch.joint,
+                property: ch.property,
+                interpolation: ch.interpolation,
+                first_key: keyframe_times.len() as u32,
+                key_count: ch.times.len() as u32,
+                first_value: keyframe_values.len() as u32,
+                value_count: ch.values.len() as u32,
+            });
+
+            keyframe_times.extend_from_slice(&ch.times);
+            keyframe_values.extend_from_slice(&ch.values);
+        }
+    }
+
+    PackedAnimationSet::Proto {
+        skeleton: mesh.skeleton.clone(),
+        clips,
+        clip_names,
+        channels,
+        keyframe_times,
+        keyframe_values,
+    }
+}
@@ -0,0 +1,182 @@
+use std::{io::Read, path::Path};
+
+use anyhow::Context as _;
+use bytecheck::CheckBytes;
+use rkyv::validation::validators::DefaultValidator;
+
+use crate::mesh::MESH_MATERIAL_LAYOUT_VERSION;
+
+pub const ASSET_MAGIC: [u8; 4] = *b"KJYA";
+
+// Bump whenever the layout of any baked asset changes
+const CONTAINER_VERSION: u32 = 4;
+
+/// Version of the baked asset format. Includes the material layout, which is baked as-is.
+pub const ASSET_FORMAT_VERSION: u32 = (CONTAINER_VERSION << 16) | MESH_MATERIAL_LAYOUT_VERSION;
+
+// Written in native byte order; reads back differently on a machine of the other endianness
+const ENDIANNESS_MARKER: u32 = 0x0102_0304;
+
+/// Implemented by the archived representation of baked assets.
+pub trait BakedAsset: for<'a> CheckBytes<DefaultValidator<'a>> {
+    /// Identifies the kind of asset, so that e.g. an image can't be loaded as a mesh.
+    const KIND: &'static str;
+
+    /// The type which gets archived as `Self`.
+    type Source: rkyv::Archive<Archived = Self>;
+}
+
+/// Prepended to every baked asset file. Its size keeps the payload aligned to 32 bytes,
+/// which is more than any archived type needs.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct AssetHeader {
+    pub magic: [u8; 4],
+    pub endianness: u32,
+    pub version: u32,
+    pub kind: u32,
+    // Including the header
+    pub total_len: u64,
+    reserved: u64,
+}
+
+const HEADER_SIZE: usize = std::mem::size_of::<AssetHeader>();
+
+const fn fnv1a_hash(s: &str) -> u32 {
+    let bytes = s.as_bytes();
+    let mut hash = 0x811c_9dc5u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+impl AssetHeader {
+    pub fn new<T: BakedAsset>(payload_len: usize) -> Self {
+        Self {
+            magic: ASSET_MAGIC,
+            endianness: ENDIANNESS_MARKER,
+            version: ASSET_FORMAT_VERSION,
+            kind: fnv1a_hash(T::KIND),
+            total_len: (HEADER_SIZE + payload_len) as u64,
+            reserved: 0,
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() < HEADER_SIZE {
+            anyhow::bail!(
+                "File too small for an asset header: {} bytes; expected at least {}",
+                bytes.len(),
+                HEADER_SIZE
+            );
+        }
+
+        Ok(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Self) })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self as *const Self as *const u8, HEADER_SIZE) }
+    }
+
+    /// Checks that the header describes an asset of type `T`, in the current format,
+    /// and spanning `file_len` bytes.
+    pub fn validate<T: BakedAsset>(&self, file_len: u64) -> anyhow::Result<()> {
+        if self.magic != ASSET_MAGIC {
+            anyhow::bail!("Not a baked asset: bad magic {:x?}", self.magic);
+        }
+
+        if self.endianness != ENDIANNESS_MARKER {
+            anyhow::bail!("The asset was baked on a machine of different endianness");
+        }
+
+        if self.version != ASSET_FORMAT_VERSION {
+            anyhow::bail!(
+                "Stale asset: format version {:#x}; expected {:#x}. It needs to be re-baked",
+                self.version,
+                ASSET_FORMAT_VERSION
+            );
+        }
+
+        if self.kind != fnv1a_hash(T::KIND) {
+            anyhow::bail!("The asset is not a {}", T::KIND);
+        }
+
+        if self.total_len != file_len {
+            anyhow::bail!(
+                "Truncated or corrupt asset: header says {} bytes, but the file has {}",
+                self.total_len,
+                file_len
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the header followed by the `payload`: an archived asset of type `T`.
+pub fn write_baked_asset<T: BakedAsset>(
+    writer: &mut impl std::io::Write,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    writer.write_all(AssetHeader::new::<T>(payload.len()).as_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Validates the header of a baked asset held in memory, and returns its payload.
+pub fn baked_asset_payload<T: BakedAsset>(data: &[u8]) -> anyhow::Result<&[u8]> {
+    AssetHeader::from_bytes(data)?.validate::<T>(data.len() as u64)?;
+    Ok(&data[HEADER_SIZE..])
+}
+
+/// Validates the header and the whole archive of a baked asset held in memory.
+///
+/// `data` must be aligned at least as much as `T`; memory-mapped files are page-aligned.
+pub fn baked_asset<T: BakedAsset>(data: &[u8]) -> anyhow::Result<&T> {
+    let payload = baked_asset_payload::<T>(data)?;
+    rkyv::check_archived_root::<T::Source>(payload)
+        .map_err(|err| anyhow::anyhow!("Corrupt {} asset: {}", T::KIND, err))
+}
+
+/// Like `baked_asset`, but only validates the header.
+///
+/// # Safety
+///
+/// The archive must have already been validated via `baked_asset`.
+pub unsafe fn baked_asset_unchecked<T: BakedAsset>(data: &[u8]) -> anyhow::Result<&T> {
+    let payload = baked_asset_payload::<T>(data)?;
+    Ok(rkyv::archived_root::<T::Source>(payload))
+}
+
+/// Validates the header of a baked asset file without loading the rest of it.
+pub fn validate_baked_asset_file<T: BakedAsset>(path: &Path) -> anyhow::Result<()> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Opening baked asset {:?}", path))?;
+    let file_len = file.metadata()?.len();
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    file.take(HEADER_SIZE as u64).read_to_end(&mut header)?;
+
+    AssetHeader::from_bytes(&header)?
+        .validate::<T>(file_len)
+        .with_context(|| format!("Validating baked asset {:?}", path))
+}
+
+/// Reads and fully validates a baked asset file, and passes the asset to `inspect`.
+pub fn inspect_baked_asset_file<T: BakedAsset, R>(
+    path: &Path,
+    inspect: impl FnOnce(&T) -> R,
+) -> anyhow::Result<R> {
+    // Aligned like a memory-mapped file would be
+    let mut data = rkyv::AlignedVec::new();
+    data.extend_from_slice(
+        &std::fs::read(path).with_context(|| format!("Reading baked asset {:?}", path))?,
+    );
+
+    let asset =
+        baked_asset::<T>(&data).with_context(|| format!("Invalid baked asset {:?}", path))?;
+    Ok(inspect(asset))
+}
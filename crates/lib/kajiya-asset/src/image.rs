@@ -1,27 +1,67 @@
+use anyhow::Context as _;
 use std::path::PathBuf;
 
 use bytes::Bytes;
-use image::{imageops::FilterType, DynamicImage, GenericImageView as _, ImageBuffer, Rgba};
-use intel_tex_2::{bc5, bc7};
-use kajiya_backend::{ash::vk, file::LoadFile, ImageDesc};
+use glam::Vec3;
+use image::{
+    imageops::FilterType, ColorType, DynamicImage, GenericImageView, ImageBuffer, ImageFormat,
+    Primitive, Rgb, Rgba, RgbaImage,
+};
+use intel_tex_2::{bc1, bc3, bc4, bc5, bc6h, bc7};
+use kajiya_backend::{ash::vk, canonical_path_from_vfs, file::LoadFile, ImageDesc};
 use turbosloth::*;
 
-use crate::mesh::TexCompressionMode;
+use crate::mesh::{TexCompressionMode, TexCompressionQuality, TexGamma, TexParams};
 
 #[derive(Clone, Hash, PartialEq, Eq)]
 pub enum ImageSource {
+    // Can start with a VFS mount point
     File(PathBuf),
     Memory(Bytes),
 }
 
+impl ImageSource {
+    /// The encoded image, read without going through the lazy cache.
+    pub fn read(&self) -> anyhow::Result<Bytes> {
+        match self {
+            ImageSource::File(path) => {
+                let path = canonical_path_from_vfs(path)
+                    .with_context(|| format!("Resolving image path {:?}", path))?;
+                let bytes =
+                    std::fs::read(&path).with_context(|| format!("Reading image {:?}", path))?;
+                Ok(Bytes::from(bytes))
+            }
+            ImageSource::Memory(bytes) => Ok(bytes.clone()),
+        }
+    }
+}
+
 pub struct RawRgba8Image {
     pub data: Bytes,
     pub dimensions: [u32; 2],
 }
 
+pub struct RawRgba16Image {
+    pub data: Vec<u16>,
+    pub dimensions: [u32; 2],
+}
+
+pub struct RawRgba32fImage {
+    pub data: Vec<f32>,
+    pub dimensions: [u32; 2],
+}
+
+type RgbaF32Image = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
 pub enum RawImage {
     Rgba8(RawRgba8Image),
+    // Encoded like `Rgba8`, with more precision
+    Rgba16(RawRgba16Image),
+    // Linear, possibly HDR
+    Rgba32f(RawRgba32fImage),
     Dds(ddsfile::Dds),
+    // Already in a GPU format
+    Ktx2(super::mesh::GpuImage::Proto),
 }
 
 #[derive(Clone, Hash)]
@@ -32,17 +72,510 @@ pub enum LoadImage {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum BcMode {
+    Bc1,
+    Bc3,
+    Bc4,
     Bc5,
+    Bc6h,
     Bc7,
 }
 
 impl BcMode {
+    fn new(compression: TexCompressionMode) -> Option<Self> {
+        match compression {
+            TexCompressionMode::None => None,
+            TexCompressionMode::Rgba => Some(BcMode::Bc7),
+            TexCompressionMode::Rg => Some(BcMode::Bc5),
+            TexCompressionMode::Bc1 => Some(BcMode::Bc1),
+            TexCompressionMode::Bc3 => Some(BcMode::Bc3),
+            TexCompressionMode::Bc4 => Some(BcMode::Bc4),
+            TexCompressionMode::Bc6h => Some(BcMode::Bc6h),
+        }
+    }
+
     fn block_bytes(self) -> usize {
         match self {
+            BcMode::Bc1 => 8,
+            BcMode::Bc3 => 16,
+            BcMode::Bc4 => 8,
             BcMode::Bc5 => 16,
+            BcMode::Bc6h => 16,
             BcMode::Bc7 => 16,
         }
     }
+
+    fn vk_format(self, gamma: TexGamma) -> anyhow::Result<vk::Format> {
+        Ok(match (self, gamma) {
+            (BcMode::Bc1, TexGamma::Linear) => vk::Format::BC1_RGB_UNORM_BLOCK,
+            (BcMode::Bc1, TexGamma::Srgb) => vk::Format::BC1_RGB_SRGB_BLOCK,
+            (BcMode::Bc3, TexGamma::Linear) => vk::Format::BC3_UNORM_BLOCK,
+            (BcMode::Bc3, TexGamma::Srgb) => vk::Format::BC3_SRGB_BLOCK,
+            (BcMode::Bc4, TexGamma::Linear) => vk::Format::BC4_UNORM_BLOCK,
+            (BcMode::Bc5, TexGamma::Linear) => vk::Format::BC5_UNORM_BLOCK,
+            // Colors are converted to linear before compression
+            (BcMode::Bc6h, _) => vk::Format::BC6H_UFLOAT_BLOCK,
+            (BcMode::Bc7, TexGamma::Linear) => vk::Format::BC7_UNORM_BLOCK,
+            (BcMode::Bc7, TexGamma::Srgb) => vk::Format::BC7_SRGB_BLOCK,
+            (BcMode::Bc4, TexGamma::Srgb) | (BcMode::Bc5, TexGamma::Srgb) => {
+                anyhow::bail!("{:?} does not support sRGB textures", self)
+            }
+        })
+    }
+}
+
+fn bc7_settings(quality: TexCompressionQuality, needs_alpha: bool) -> bc7::EncodeSettings {
+    match (quality, needs_alpha) {
+        (TexCompressionQuality::UltraFast, false) => bc7::opaque_ultra_fast_settings(),
+        (TexCompressionQuality::VeryFast, false) => bc7::opaque_very_fast_settings(),
+        (TexCompressionQuality::Fast, false) => bc7::opaque_fast_settings(),
+        (TexCompressionQuality::Basic, false) => bc7::opaque_basic_settings(),
+        (TexCompressionQuality::Slow, false) => bc7::opaque_slow_settings(),
+        (TexCompressionQuality::UltraFast, true) => bc7::alpha_ultra_fast_settings(),
+        (TexCompressionQuality::VeryFast, true) => bc7::alpha_very_fast_settings(),
+        (TexCompressionQuality::Fast, true) => bc7::alpha_fast_settings(),
+        (TexCompressionQuality::Basic, true) => bc7::alpha_basic_settings(),
+        (TexCompressionQuality::Slow, true) => bc7::alpha_slow_settings(),
+    }
+}
+
+fn bc6h_settings(quality: TexCompressionQuality) -> bc6h::EncodeSettings {
+    // BC6H has no preset faster than "very fast"
+    match quality {
+        TexCompressionQuality::UltraFast | TexCompressionQuality::VeryFast => {
+            bc6h::very_fast_settings()
+        }
+        // Misnamed in intel_tex_2; this is its "fast" preset
+        TexCompressionQuality::Fast => bc6h::very_settings(),
+        TexCompressionQuality::Basic => bc6h::basic_settings(),
+        TexCompressionQuality::Slow => bc6h::slow_settings(),
+    }
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Whether a float image has colors outside of the [0, 1] range of unorm formats.
+fn is_hdr(image: &RawRgba32fImage) -> bool {
+    image
+        .data
+        .chunks_exact(4)
+        .any(|px| px[..3].iter().any(|&x| !(0.0..=1.0).contains(&x)))
+}
+
+fn quantize_rgba16(image: &RawRgba16Image) -> RawRgba8Image {
+    let data: Vec<u8> = image
+        .data
+        .iter()
+        .map(|&x| (x as f32 / 257.0).round() as u8)
+        .collect();
+
+    RawRgba8Image {
+        data: Bytes::from(data),
+        dimensions: image.dimensions,
+    }
+}
+
+/// Quantizes linear LDR colors to 8 bits, encoding them with `gamma`.
+fn quantize_rgba32f(image: &RawRgba32fImage, gamma: TexGamma) -> RawRgba8Image {
+    let encode = |x: f32| match gamma {
+        TexGamma::Linear => x,
+        TexGamma::Srgb => linear_to_srgb(x),
+    };
+    let quantize = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+    let data: Vec<u8> = image
+        .data
+        .chunks_exact(4)
+        .flat_map(|px| {
+            [
+                quantize(encode(px[0])),
+                quantize(encode(px[1])),
+                quantize(encode(px[2])),
+                quantize(px[3]),
+            ]
+        })
+        .collect();
+
+    RawRgba8Image {
+        data: Bytes::from(data),
+        dimensions: image.dimensions,
+    }
+}
+
+// From the [0, 1] range normal maps are stored in
+fn decode_unorm_normal(r: f32, g: f32, b: f32) -> Vec3 {
+    Vec3::new(r, g, b) * 2.0 - Vec3::ONE
+}
+
+fn decode_normal(px: &Rgba<u8>) -> Vec3 {
+    let unorm = |x: u8| x as f32 / 255.0;
+    decode_unorm_normal(unorm(px.0[0]), unorm(px.0[1]), unorm(px.0[2]))
+}
+
+fn encode_normal_channel(x: f32) -> u8 {
+    ((x * 0.5 + 0.5) * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Half-float bytes, as used by `R16G16B16A16_SFLOAT` images and the BC6H encoder.
+/// Values out of the half-float range are clamped rather than becoming infinite.
+fn to_f16_bytes(values: impl Iterator<Item = f32>) -> Vec<u8> {
+    let max = half::f16::MAX.to_f32();
+    values
+        .flat_map(|x| {
+            half::f16::from_f32(x.clamp(-max, max))
+                .to_bits()
+                .to_ne_bytes()
+        })
+        .collect()
+}
+
+fn compress_bc6h(
+    width: u32,
+    height: u32,
+    rgb: impl Iterator<Item = [f32; 3]>,
+    quality: TexCompressionQuality,
+) -> Vec<u8> {
+    // The encoder takes half-float RGBA, and the format can't store negative values
+    let hdr = to_f16_bytes(rgb.flat_map(|[r, g, b]| [r.max(0.0), g.max(0.0), b.max(0.0), 1.0]));
+
+    let surface = intel_tex_2::RgbaSurface {
+        width,
+        height,
+        stride: width * 8,
+        data: &hdr,
+    };
+
+    let block_count = intel_tex_2::divide_up_by_multiple(width * height, 16);
+    let mut compressed_bytes = vec![0u8; block_count as usize * BcMode::Bc6h.block_bytes()];
+    bc6h::compress_blocks_into(&bc6h_settings(quality), &surface, &mut compressed_bytes);
+
+    compressed_bytes
+}
+
+/// The size to downscale a `width` x `height` image to so that it fits `max_size`, if it doesn't.
+fn fit_max_size(width: u32, height: u32, max_size: Option<u32>) -> Option<(u32, u32)> {
+    let max_size = max_size?;
+    if width <= max_size && height <= max_size {
+        return None;
+    }
+
+    let scale = max_size as f64 / width.max(height) as f64;
+    let scaled_width = ((width as f64 * scale).round() as u32).max(1);
+    let scaled_height = ((height as f64 * scale).round() as u32).max(1);
+
+    log::info!(
+        "Downscaling the {}x{} image to {}x{} to fit the max texture size of {}",
+        width,
+        height,
+        scaled_width,
+        scaled_height,
+        max_size
+    );
+
+    Some((scaled_width, scaled_height))
+}
+
+/// Resizes a texture the way its `params` require: sRGB data is filtered in linear space,
+/// and normal maps are renormalized afterwards.
+pub fn resize_texture(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    params: &TexParams,
+) -> DynamicImage {
+    DynamicImage::ImageRgba8(resize_rgba8(image, width, height, params))
+}
+
+fn resize_rgba8<I: GenericImageView<Pixel = Rgba<u8>>>(
+    image: &I,
+    width: u32,
+    height: u32,
+    params: &TexParams,
+) -> RgbaImage {
+    let mut resized: RgbaImage = match params.gamma {
+        TexGamma::Linear => image::imageops::resize(image, width, height, FilterType::Lanczos3),
+        TexGamma::Srgb => {
+            // 16 bits are plenty to not lose precision in the dark tones
+            let to_linear: Vec<u16> = (0..=255u8)
+                .map(|x| (srgb_to_linear(x as f32 / 255.0) * 65535.0).round() as u16)
+                .collect();
+
+            let linear = ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+                let Rgba([r, g, b, a]) = image.get_pixel(x, y);
+                Rgba([
+                    to_linear[r as usize],
+                    to_linear[g as usize],
+                    to_linear[b as usize],
+                    a as u16 * 257,
+                ])
+            });
+
+            let linear = image::imageops::resize(&linear, width, height, FilterType::Lanczos3);
+
+            let to_srgb = |x: u16| (linear_to_srgb(x as f32 / 65535.0) * 255.0).round() as u8;
+            ImageBuffer::from_fn(width, height, |x, y| {
+                let Rgba([r, g, b, a]) = *linear.get_pixel(x, y);
+                Rgba([
+                    to_srgb(r),
+                    to_srgb(g),
+                    to_srgb(b),
+                    (a as f32 / 257.0).round() as u8,
+                ])
+            })
+        }
+    };
+
+    if params.normal_map {
+        for px in resized.pixels_mut() {
+            let n = decode_normal(px).normalize_or_zero();
+            let n = if n == Vec3::ZERO { Vec3::Z } else { n };
+
+            px.0[0] = encode_normal_channel(n.x);
+            px.0[1] = encode_normal_channel(n.y);
+            px.0[2] = encode_normal_channel(n.z);
+        }
+    }
+
+    resized
+}
+
+/// Like `resize_texture`, for linear float data.
+fn resize_texture_f32(
+    image: &RgbaF32Image,
+    width: u32,
+    height: u32,
+    params: &TexParams,
+) -> RgbaF32Image {
+    let mut resized = image::imageops::resize(image, width, height, FilterType::Lanczos3);
+
+    if params.normal_map {
+        for px in resized.pixels_mut() {
+            let n = decode_unorm_normal(px.0[0], px.0[1], px.0[2]).normalize_or_zero();
+            let n = if n == Vec3::ZERO { Vec3::Z } else { n };
+
+            px.0[0] = n.x * 0.5 + 0.5;
+            px.0[1] = n.y * 0.5 + 0.5;
+            px.0[2] = n.z * 0.5 + 0.5;
+        }
+    }
+
+    resized
+}
+
+/// Unit normals decoded from a normal map, for measuring their variance.
+struct NormalVariance {
+    normals: Vec<Vec3>,
+    dimensions: [u32; 2],
+}
+
+impl NormalVariance {
+    /// `None` for pre-compressed normal maps, which would have to be decoded first.
+    fn new(src: &RawImage) -> Option<Self> {
+        let (normals, dimensions): (Vec<Vec3>, _) = match src {
+            RawImage::Rgba8(src) => (
+                src.data
+                    .chunks_exact(4)
+                    .map(|px| decode_normal(&Rgba([px[0], px[1], px[2], px[3]])))
+                    .collect(),
+                src.dimensions,
+            ),
+            RawImage::Rgba16(src) => (
+                src.data
+                    .chunks_exact(4)
+                    .map(|px| {
+                        let unorm = |x: u16| x as f32 / 65535.0;
+                        decode_unorm_normal(unorm(px[0]), unorm(px[1]), unorm(px[2]))
+                    })
+                    .collect(),
+                src.dimensions,
+            ),
+            RawImage::Rgba32f(src) => (
+                src.data
+                    .chunks_exact(4)
+                    .map(|px| decode_unorm_normal(px[0], px[1], px[2]))
+                    .collect(),
+                src.dimensions,
+            ),
+            RawImage::Dds(_) | RawImage::Ktx2(_) => return None,
+        };
+
+        Some(Self {
+            normals: normals.into_iter().map(Vec3::normalize_or_zero).collect(),
+            dimensions,
+        })
+    }
+
+    /// Length of the average normal in the part of the normal map covered by texel `[x, y]`
+    /// of an image of the given `dimensions`. Shorter the more the normals vary.
+    fn average_normal_length(&self, x: u32, y: u32, dimensions: [u32; 2]) -> f32 {
+        let footprint = |i: u32, axis: usize| -> std::ops::Range<usize> {
+            let size = self.dimensions[axis] as u64;
+            let start = i as u64 * size / dimensions[axis] as u64;
+            let end = ((i as u64 + 1) * size / dimensions[axis] as u64).max(start + 1);
+            start as usize..end.min(size) as usize
+        };
+
+        let (xs, ys) = (footprint(x, 0), footprint(y, 1));
+        let count = xs.len() * ys.len();
+        if count <= 1 {
+            return 1.0;
+        }
+
+        let row_len = self.dimensions[0] as usize;
+        let sum: Vec3 = ys
+            .flat_map(|y| self.normals[y * row_len..][xs.clone()].iter())
+            .sum();
+
+        (sum.length() / count as f32).min(1.0)
+    }
+
+    /// Widens the `roughness` of texel `[x, y]` of an image of the given `dimensions` by the
+    /// variance of the normals under it (Toksvig), so that distant bumpy surfaces don't sparkle.
+    fn widen_roughness_at(&self, roughness: f32, x: u32, y: u32, dimensions: [u32; 2]) -> f32 {
+        let len = self.average_normal_length(x, y, dimensions);
+        if len >= 1.0 {
+            return roughness;
+        }
+
+        let variance = (1.0 - len) / len.max(1e-4);
+
+        // Perceptual roughness, squared into the GGX alpha
+        let alpha_sq = (roughness.powi(4) + variance).min(1.0);
+        alpha_sq.sqrt().sqrt()
+    }
+
+    /// Widens the roughness in the red channel of `mip`; see `widen_roughness_at`.
+    fn widen_roughness(&self, mip: &mut RgbaImage) {
+        let dimensions = [mip.width(), mip.height()];
+
+        for (x, y, px) in mip.enumerate_pixels_mut() {
+            let roughness = self.widen_roughness_at(px.0[0] as f32 / 255.0, x, y, dimensions);
+            px.0[0] = (roughness * 255.0).round() as u8;
+        }
+    }
+
+    fn widen_roughness_f32(&self, mip: &mut RgbaF32Image) {
+        let dimensions = [mip.width(), mip.height()];
+
+        for (x, y, px) in mip.enumerate_pixels_mut() {
+            px.0[0] = self.widen_roughness_at(px.0[0], x, y, dimensions);
+        }
+    }
+}
+
+/// Pixel storage which `CreateGpuImage::build_mips` can produce mip chains of.
+trait MipImage: Sized {
+    fn extent(&self) -> [u32; 2];
+    /// See `resize_texture`.
+    fn resize(&self, width: u32, height: u32, params: &TexParams) -> Self;
+    fn swizzle(&mut self, swizzle: [usize; 4]);
+    fn widen_roughness(&mut self, normal_variance: &NormalVariance);
+}
+
+fn swizzle_channels<T: Primitive + 'static>(
+    image: &mut ImageBuffer<Rgba<T>, Vec<T>>,
+    swizzle: [usize; 4],
+) {
+    for px in image.pixels_mut() {
+        let src = px.0;
+        px.0 = swizzle.map(|channel| src[channel]);
+    }
+}
+
+impl MipImage for RgbaImage {
+    fn extent(&self) -> [u32; 2] {
+        [self.width(), self.height()]
+    }
+
+    fn resize(&self, width: u32, height: u32, params: &TexParams) -> Self {
+        resize_rgba8(self, width, height, params)
+    }
+
+    fn swizzle(&mut self, swizzle: [usize; 4]) {
+        swizzle_channels(self, swizzle);
+    }
+
+    fn widen_roughness(&mut self, normal_variance: &NormalVariance) {
+        normal_variance.widen_roughness(self);
+    }
+}
+
+impl MipImage for RgbaF32Image {
+    fn extent(&self) -> [u32; 2] {
+        [self.width(), self.height()]
+    }
+
+    fn resize(&self, width: u32, height: u32, params: &TexParams) -> Self {
+        resize_texture_f32(self, width, height, params)
+    }
+
+    fn swizzle(&mut self, swizzle: [usize; 4]) {
+        swizzle_channels(self, swizzle);
+    }
+
+    fn widen_roughness(&mut self, normal_variance: &NormalVariance) {
+        normal_variance.widen_roughness_f32(self);
+    }
+}
+
+fn load_exr(bytes: &[u8]) -> anyhow::Result<RawRgba32fImage> {
+    use exr::prelude::*;
+
+    let image = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgba_channels(
+            |resolution, _channels: &RgbaChannels| -> Vec<Vec<[f32; 4]>> {
+                vec![vec![[0.0; 4]; resolution.width()]; resolution.height()]
+            },
+            |rows: &mut Vec<Vec<[f32; 4]>>,
+             position: Vec2<usize>,
+             (r, g, b, a): (f32, f32, f32, f32)| {
+                rows[position.y()][position.x()] = [r, g, b, a];
+            },
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(std::io::Cursor::new(bytes))?;
+
+    let rows = image.layer_data.channel_data.pixels;
+    let dimensions = [rows.first().map_or(0, Vec::len) as u32, rows.len() as u32];
+
+    Ok(RawRgba32fImage {
+        data: rows.into_iter().flatten().flatten().collect(),
+        dimensions,
+    })
+}
+
+fn load_hdr(bytes: &[u8]) -> anyhow::Result<RawRgba32fImage> {
+    let decoder = image::codecs::hdr::HdrDecoder::new(std::io::Cursor::new(bytes))?;
+    let metadata = decoder.metadata();
+
+    Ok(RawRgba32fImage {
+        data: decoder
+            .read_image_hdr()?
+            .into_iter()
+            .flat_map(|Rgb([r, g, b])| [r, g, b, 1.0])
+            .collect(),
+        dimensions: [metadata.width, metadata.height],
+    })
+}
+
+fn is_exr(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x76, 0x2f, 0x31, 0x01])
 }
 
 impl LoadImage {
@@ -69,7 +602,18 @@ impl LazyWorker for LoadImage {
             LoadImage::Immediate(bytes) => bytes,
         };
 
-        if let Ok(dds) = ddsfile::Dds::read(&mut std::io::Cursor::new(&bytes)) {
+        if crate::ktx2::is_ktx2(&bytes) {
+            let image = crate::ktx2::read_ktx2(&bytes)?;
+            log::info!(
+                "Loaded KTX2 image: {}x{} {:?}, {} layers",
+                image.extent[0],
+                image.extent[1],
+                image.format,
+                image.array_layers
+            );
+
+            Ok(RawImage::Ktx2(image))
+        } else if let Ok(dds) = ddsfile::Dds::read(&mut std::io::Cursor::new(&bytes)) {
             log::info!(
                 "Loaded DDS image: {}x{}x{} {}",
                 dds.get_width(),
@@ -82,17 +626,34 @@ impl LazyWorker for LoadImage {
             );
 
             Ok(RawImage::Dds(dds))
+        } else if is_exr(&bytes) || image::guess_format(&bytes).ok() == Some(ImageFormat::Hdr) {
+            let image = if is_exr(&bytes) {
+                load_exr(&bytes)?
+            } else {
+                load_hdr(&bytes)?
+            };
+            log::info!("Loaded HDR image: {:?}", image.dimensions);
+
+            Ok(RawImage::Rgba32f(image))
         } else {
             let image = image::load_from_memory(&bytes)?;
             let image_dimensions = image.dimensions();
             log::info!("Loaded image: {:?} {:?}", image_dimensions, image.color());
 
-            let image = image.to_rgba8();
+            let dimensions = [image_dimensions.0, image_dimensions.1];
 
-            Ok(RawImage::Rgba8(RawRgba8Image {
-                data: image.into_raw().into(),
-                dimensions: [image_dimensions.0, image_dimensions.1],
-            }))
+            match image.color() {
+                ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+                    Ok(RawImage::Rgba16(RawRgba16Image {
+                        data: image.to_rgba16().into_raw(),
+                        dimensions,
+                    }))
+                }
+                _ => Ok(RawImage::Rgba8(RawRgba8Image {
+                    data: image.to_rgba8().into_raw().into(),
+                    dimensions,
+                })),
+            }
         }
     }
 }
@@ -124,53 +685,129 @@ impl LazyWorker for CreatePlaceholderImage {
 pub struct CreateGpuImage {
     pub image: Lazy<RawImage>,
     pub params: super::mesh::TexParams,
+    // For spec maps: the normal map used alongside, whose variance is added to the roughness
+    pub roughness_normal_map: Option<Lazy<RawImage>>,
 }
 
 impl CreateGpuImage {
-    fn process_rgba8(&self, src: &RawRgba8Image) -> anyhow::Result<super::mesh::GpuImage::Proto> {
-        let mut format = match self.params.gamma {
-            crate::mesh::TexGamma::Linear => vk::Format::R8G8B8A8_UNORM,
-            crate::mesh::TexGamma::Srgb => vk::Format::R8G8B8A8_SRGB,
+    /// Downscales `image` to the max size, then encodes it and, if enabled, its mip chain.
+    /// `block_dim` is the block size of the `format`, which every mip is padded to.
+    fn build_mips<I: MipImage>(
+        &self,
+        mut image: I,
+        format: vk::Format,
+        block_dim: u32,
+        normal_variance: Option<&NormalVariance>,
+        encode: impl Fn(I) -> Vec<u8>,
+    ) -> super::mesh::GpuImage::Proto {
+        let [width, height] = image.extent();
+        if let Some((width, height)) = fit_max_size(width, height, self.params.max_size) {
+            image = image.resize(width, height, &self.params);
+        }
+
+        let mut desc =
+            ImageDesc::new_2d(format, image.extent()).usage(vk::ImageUsageFlags::SAMPLED);
+
+        let round_up_to_block =
+            |x: u32| -> u32 { (((x + block_dim - 1) / block_dim) * block_dim).max(block_dim) };
+
+        let process_mip = |mip: I| -> Vec<u8> {
+            let [width, height] = mip.extent();
+            let mut mip = if width % block_dim != 0 || height % block_dim != 0 {
+                mip.resize(
+                    round_up_to_block(width),
+                    round_up_to_block(height),
+                    &self.params,
+                )
+            } else {
+                mip
+            };
+
+            if let Some(swizzle) = self.params.channel_swizzle {
+                mip.swizzle(swizzle);
+            }
+
+            if let Some(normal_variance) = normal_variance {
+                mip.widen_roughness(normal_variance);
+            }
+
+            encode(mip)
         };
 
-        let mut image = image::DynamicImage::ImageRgba8(
-            image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(
-                src.dimensions[0],
-                src.dimensions[1],
-                src.data.to_vec(),
-            )
-            .unwrap(),
-        );
+        let mips: Vec<Vec<u8>> = if self.params.use_mips {
+            desc = desc.all_mip_levels();
 
-        let should_compress = self.params.compression != TexCompressionMode::None
-            && image.width() >= 4
-            && image.height() >= 4;
+            let downsample = |image: &I| {
+                let [width, height] = image.extent();
+                image.resize(
+                    round_up_to_block(width / 2),
+                    round_up_to_block(height / 2),
+                    &self.params,
+                )
+            };
 
-        const MAX_SIZE: u32 = 2048;
+            let mut mips;
+            image = {
+                let next = downsample(&image);
+                mips = vec![process_mip(image)];
+                next
+            };
 
-        if image.dimensions().0 > MAX_SIZE || image.dimensions().1 > MAX_SIZE {
-            image = image.resize_exact(
-                image.dimensions().0.min(MAX_SIZE),
-                image.dimensions().1.min(MAX_SIZE),
-                FilterType::Lanczos3,
-            );
+            for _ in 1..desc.mip_levels {
+                let next = downsample(&image);
+                let mip = std::mem::replace(&mut image, next);
+                mips.push(process_mip(mip));
+            }
+
+            mips
+        } else {
+            vec![process_mip(image)]
+        };
+
+        super::mesh::GpuImage::Proto {
+            format,
+            extent: desc.extent,
+            array_layers: 1,
+            cube: false,
+            mips,
         }
+    }
 
-        let mut desc = ImageDesc::new_2d(format, [image.dimensions().0, image.dimensions().1])
-            .usage(vk::ImageUsageFlags::SAMPLED);
+    /// Whether LDR sources of more than 8 bits are quantized and compressed with the
+    /// requested 8-bit BC mode, rather than kept as float data.
+    fn quantizes_ldr_sources(&self) -> bool {
+        !matches!(
+            BcMode::new(self.params.compression),
+            None | Some(BcMode::Bc6h)
+        )
+    }
 
-        let mut compress = |mip: ImageBuffer<Rgba<u8>, Vec<u8>>| -> Vec<u8> {
+    fn process_rgba8(
+        &self,
+        src: &RawRgba8Image,
+        normal_variance: Option<&NormalVariance>,
+    ) -> anyhow::Result<super::mesh::GpuImage::Proto> {
+        let image = RgbaImage::from_raw(src.dimensions[0], src.dimensions[1], src.data.to_vec())
+            .ok_or_else(|| anyhow::anyhow!("Image data doesn't match its dimensions"))?;
+
+        let bc_mode = BcMode::new(self.params.compression)
+            .filter(|_| image.width() >= 4 && image.height() >= 4);
+
+        let format = if let Some(bc_mode) = bc_mode {
+            bc_mode.vk_format(self.params.gamma)?
+        } else {
+            match self.params.gamma {
+                TexGamma::Linear => vk::Format::R8G8B8A8_UNORM,
+                TexGamma::Srgb => vk::Format::R8G8B8A8_SRGB,
+            }
+        };
+
+        let compress = |mip: RgbaImage, bc_mode: BcMode| -> Vec<u8> {
             let block_count = intel_tex_2::divide_up_by_multiple(mip.width() * mip.height(), 16);
 
             let needs_alpha =
                 self.params.compression.supports_alpha() && mip.pixels().any(|px| px.0[3] != 255);
 
-            let bc_mode = match self.params.compression {
-                TexCompressionMode::None => unreachable!(),
-                TexCompressionMode::Rgba => BcMode::Bc7,
-                TexCompressionMode::Rg => BcMode::Bc5,
-            };
-
             let block_bytes = bc_mode.block_bytes();
 
             let surface = intel_tex_2::RgbaSurface {
@@ -182,28 +819,37 @@ impl CreateGpuImage {
 
             let mut compressed_bytes = vec![0u8; block_count as usize * block_bytes];
 
-            log::info!("Compressing to {:?}...", bc_mode);
+            log::info!(
+                "Compressing to {:?} ({:?})...",
+                bc_mode,
+                self.params.compression_quality
+            );
             match bc_mode {
-                BcMode::Bc5 => {
-                    format = match self.params.gamma {
-                        crate::mesh::TexGamma::Linear => vk::Format::BC5_UNORM_BLOCK,
-                        crate::mesh::TexGamma::Srgb => unimplemented!(),
+                BcMode::Bc1 => bc1::compress_blocks_into(&surface, &mut compressed_bytes),
+                BcMode::Bc3 => bc3::compress_blocks_into(&surface, &mut compressed_bytes),
+                BcMode::Bc4 => bc4::compress_blocks_into(&surface, &mut compressed_bytes),
+                BcMode::Bc5 => bc5::compress_blocks_into(&surface, &mut compressed_bytes),
+                BcMode::Bc6h => {
+                    let linearize = |x: u8| -> f32 {
+                        let x = x as f32 / 255.0;
+                        match self.params.gamma {
+                            TexGamma::Linear => x,
+                            TexGamma::Srgb => srgb_to_linear(x),
+                        }
                     };
 
-                    bc5::compress_blocks_into(&surface, &mut compressed_bytes)
+                    compressed_bytes = compress_bc6h(
+                        mip.width(),
+                        mip.height(),
+                        mip.pixels().map(|px| {
+                            let Rgba([r, g, b, _]) = *px;
+                            [linearize(r), linearize(g), linearize(b)]
+                        }),
+                        self.params.compression_quality,
+                    );
                 }
                 BcMode::Bc7 => {
-                    format = match self.params.gamma {
-                        crate::mesh::TexGamma::Linear => vk::Format::BC7_UNORM_BLOCK,
-                        crate::mesh::TexGamma::Srgb => vk::Format::BC7_SRGB_BLOCK,
-                    };
-
-                    let settings = if needs_alpha {
-                        bc7::alpha_basic_settings()
-                    } else {
-                        bc7::opaque_basic_settings()
-                    };
-
+                    let settings = bc7_settings(self.params.compression_quality, needs_alpha);
                     bc7::compress_blocks_into(&settings, &surface, &mut compressed_bytes);
                 }
             }
@@ -211,125 +857,131 @@ impl CreateGpuImage {
             compressed_bytes
         };
 
-        let swizzle = |mip: &mut ImageBuffer<Rgba<u8>, Vec<u8>>| {
-            if let Some(swizzle) = self.params.channel_swizzle {
-                for px in mip.pixels_mut() {
-                    px.0[0] = px.0[swizzle[0]];
-                    px.0[1] = px.0[swizzle[1]];
-                    px.0[2] = px.0[swizzle[2]];
-                    px.0[3] = px.0[swizzle[3]];
-                }
-            }
-        };
-
-        let min_img_dim = if should_compress { 4 } else { 1 };
-
-        let round_up_to_block = |x: u32| -> u32 {
-            (((x + min_img_dim - 1) / min_img_dim) * min_img_dim).max(min_img_dim)
-        };
-
-        let mut process_mip = |mip: DynamicImage| -> Vec<u8> {
-            let mip = if mip.width() % min_img_dim != 0 || mip.height() % min_img_dim != 0 {
-                let width = round_up_to_block(mip.width());
-                let height = round_up_to_block(mip.height());
-                mip.resize_exact(width, height, FilterType::Lanczos3)
-            } else {
-                mip
-            };
-
-            let mut mip = mip.into_rgba8();
-
-            swizzle(&mut mip);
-
-            if should_compress {
-                compress(mip)
-            } else {
-                mip.into_raw()
-            }
-        };
+        let block_dim = if bc_mode.is_some() { 4 } else { 1 };
 
-        let mips: Vec<Vec<u8>> = if self.params.use_mips {
-            desc = desc.all_mip_levels();
+        Ok(self.build_mips(
+            image,
+            format,
+            block_dim,
+            normal_variance,
+            |mip| match bc_mode {
+                Some(bc_mode) => compress(mip, bc_mode),
+                None => mip.into_raw(),
+            },
+        ))
+    }
 
-            let downsample = |image: &DynamicImage| {
-                // TODO: gamma-correct resize
-                image.resize_exact(
-                    round_up_to_block(image.dimensions().0 / 2),
-                    round_up_to_block(image.dimensions().1 / 2),
-                    FilterType::Lanczos3,
-                )
-            };
+    /// Keeps the precision of 16-bit and float sources, producing `R16G16B16A16_SFLOAT`,
+    /// or BC6H when compressing. `image` must be linear.
+    ///
+    /// Only used for HDR data, uncompressed textures, and explicit BC6H requests;
+    /// see `quantizes_ldr_sources`.
+    fn process_rgba_f32(
+        &self,
+        image: RgbaF32Image,
+        normal_variance: Option<&NormalVariance>,
+    ) -> anyhow::Result<super::mesh::GpuImage::Proto> {
+        // BC6H has no alpha, so the texture stays uncompressed if it needs one
+        let alpha_channel = self.params.channel_swizzle.map_or(3, |swizzle| swizzle[3]);
+        let needs_alpha = self.params.compression.supports_alpha()
+            && image.pixels().any(|px| px.0[alpha_channel] != 1.0);
 
-            let mut mips;
-            image = {
-                let next = downsample(&image);
-                mips = vec![process_mip(image)];
-                next
-            };
-
-            for _ in 1..desc.mip_levels {
-                let next = downsample(&image);
-                let mip = std::mem::replace(&mut image, next);
-                mips.push(process_mip(mip));
-            }
+        let should_compress = self.params.compression != TexCompressionMode::None
+            && !needs_alpha
+            && image.width() >= 4
+            && image.height() >= 4;
 
-            mips
+        let format = if should_compress {
+            BcMode::Bc6h.vk_format(TexGamma::Linear)?
         } else {
-            vec![process_mip(image)]
+            vk::Format::R16G16B16A16_SFLOAT
         };
 
-        Ok(super::mesh::GpuImage::Proto {
-            format,
-            extent: desc.extent,
-            mips,
-        })
+        let block_dim = if should_compress { 4 } else { 1 };
+
+        Ok(
+            self.build_mips(image, format, block_dim, normal_variance, |mip| {
+                if should_compress {
+                    log::info!(
+                        "Compressing to {:?} ({:?})...",
+                        BcMode::Bc6h,
+                        self.params.compression_quality
+                    );
+
+                    compress_bc6h(
+                        mip.width(),
+                        mip.height(),
+                        mip.pixels().map(|px| [px.0[0], px.0[1], px.0[2]]),
+                        self.params.compression_quality,
+                    )
+                } else {
+                    to_f16_bytes(mip.into_raw().into_iter())
+                }
+            }),
+        )
     }
 
     fn process_dds(&self, dds: &ddsfile::Dds) -> anyhow::Result<super::mesh::GpuImage::Proto> {
+        let format = dds_util::get_vk_format(dds)?;
+
+        if dds.get_depth() > 1 {
+            anyhow::bail!("Volume DDS images are not supported");
+        }
+
         if dds_util::get_pitch(dds, dds.get_width()).is_none() {
             anyhow::bail!("Not pitch available for DDS image");
         }
 
-        let dds_data = dds.get_data(0).unwrap();
-        let mut byte_offset = 0usize;
+        let cube = dds_util::is_cube(dds);
+        let array_layers = dds_util::get_array_size(dds) * if cube { 6 } else { 1 };
+        let mip_levels = dds.get_num_mipmap_levels().max(1);
 
         // 1 for regular, 4 for BC
         let pitch_height = dds.get_pitch_height();
 
-        let mips: Vec<Vec<u8>> = (0..dds.get_num_mipmap_levels())
-            .map(|mip| -> Vec<u8> {
+        // Stored like `GpuImage` expects it: all the mips of one layer, then the next layer
+        let mut byte_offset = 0usize;
+        let mut mips: Vec<Vec<u8>> = Vec::with_capacity((array_layers * mip_levels) as usize);
+
+        for _layer in 0..array_layers {
+            for mip in 0..mip_levels {
                 let width = (dds.get_width() >> mip).max(pitch_height);
                 let height = (dds.get_height() >> mip).max(pitch_height);
                 let pitch = dds_util::get_pitch(dds, width).unwrap();
 
                 let mip_size_bytes = dds_util::get_texture_size(pitch, pitch_height, height, 1);
 
-                let mip_data = &dds_data[byte_offset..byte_offset + mip_size_bytes];
+                let mip_data = dds
+                    .data
+                    .get(byte_offset..byte_offset + mip_size_bytes)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Truncated DDS image: {} bytes of data for {} layers of {} mips",
+                            dds.data.len(),
+                            array_layers,
+                            mip_levels
+                        )
+                    })?;
 
                 byte_offset += mip_size_bytes;
 
-                mip_data.to_owned()
-            })
-            .collect();
-
-        assert_eq!(byte_offset, dds_data.len());
-
-        let format = match dds.get_dxgi_format() {
-            Some(ddsfile::DxgiFormat::BC1_UNorm_sRGB) => vk::Format::BC1_RGB_SRGB_BLOCK,
-            Some(ddsfile::DxgiFormat::BC3_UNorm) => vk::Format::BC3_UNORM_BLOCK,
-            Some(ddsfile::DxgiFormat::BC3_UNorm_sRGB) => vk::Format::BC3_SRGB_BLOCK,
-            Some(ddsfile::DxgiFormat::BC5_UNorm) => vk::Format::BC5_UNORM_BLOCK,
-            Some(ddsfile::DxgiFormat::BC5_SNorm) => vk::Format::BC5_SNORM_BLOCK,
-            _ => todo!(
-                "DDS format dxgi:{:?} d3d:{:?} not supported yet",
-                dds.get_dxgi_format(),
-                dds.get_d3d_format()
-            ),
-        };
+                mips.push(mip_data.to_owned());
+            }
+        }
+
+        if byte_offset != dds.data.len() {
+            anyhow::bail!(
+                "Unexpected DDS data size: {} bytes; expected {}",
+                dds.data.len(),
+                byte_offset
+            );
+        }
 
         Ok(super::mesh::GpuImage::Proto {
             format,
-            extent: [dds.get_width(), dds.get_height(), dds.get_depth()],
+            extent: [dds.get_width(), dds.get_height(), 1],
+            array_layers,
+            cube,
             mips,
         })
     }
@@ -337,6 +989,59 @@ impl CreateGpuImage {
 
 // From `ddsfile`, with some modifications
 mod dds_util {
+    use ddsfile::{Caps2, D3DFormat, DxgiFormat};
+    use kajiya_backend::ash::vk;
+
+    pub fn get_vk_format(dds: &ddsfile::Dds) -> anyhow::Result<vk::Format> {
+        if let Some(format) = dds.get_dxgi_format() {
+            return Ok(match format {
+                // The RGBA variants keep BC1's 1-bit punch-through alpha, e.g. of cutouts
+                DxgiFormat::BC1_UNorm => vk::Format::BC1_RGBA_UNORM_BLOCK,
+                DxgiFormat::BC1_UNorm_sRGB => vk::Format::BC1_RGBA_SRGB_BLOCK,
+                DxgiFormat::BC2_UNorm => vk::Format::BC2_UNORM_BLOCK,
+                DxgiFormat::BC2_UNorm_sRGB => vk::Format::BC2_SRGB_BLOCK,
+                DxgiFormat::BC3_UNorm => vk::Format::BC3_UNORM_BLOCK,
+                DxgiFormat::BC3_UNorm_sRGB => vk::Format::BC3_SRGB_BLOCK,
+                DxgiFormat::BC4_UNorm => vk::Format::BC4_UNORM_BLOCK,
+                DxgiFormat::BC4_SNorm => vk::Format::BC4_SNORM_BLOCK,
+                DxgiFormat::BC5_UNorm => vk::Format::BC5_UNORM_BLOCK,
+                DxgiFormat::BC5_SNorm => vk::Format::BC5_SNORM_BLOCK,
+                DxgiFormat::BC6H_UF16 => vk::Format::BC6H_UFLOAT_BLOCK,
+                DxgiFormat::BC6H_SF16 => vk::Format::BC6H_SFLOAT_BLOCK,
+                DxgiFormat::BC7_UNorm => vk::Format::BC7_UNORM_BLOCK,
+                DxgiFormat::BC7_UNorm_sRGB => vk::Format::BC7_SRGB_BLOCK,
+                DxgiFormat::R8G8B8A8_UNorm => vk::Format::R8G8B8A8_UNORM,
+                DxgiFormat::R8G8B8A8_UNorm_sRGB => vk::Format::R8G8B8A8_SRGB,
+                DxgiFormat::R16G16B16A16_Float => vk::Format::R16G16B16A16_SFLOAT,
+                DxgiFormat::R32G32B32A32_Float => vk::Format::R32G32B32A32_SFLOAT,
+                _ => anyhow::bail!("DDS format dxgi:{:?} not supported", format),
+            });
+        }
+
+        // Legacy files without the DX10 header carry no color space; assume linear
+        match dds.get_d3d_format() {
+            Some(D3DFormat::DXT1) => Ok(vk::Format::BC1_RGBA_UNORM_BLOCK),
+            Some(D3DFormat::DXT3) => Ok(vk::Format::BC2_UNORM_BLOCK),
+            Some(D3DFormat::DXT5) => Ok(vk::Format::BC3_UNORM_BLOCK),
+            Some(D3DFormat::A8B8G8R8) => Ok(vk::Format::R8G8B8A8_UNORM),
+            Some(D3DFormat::A16B16G16R16F) => Ok(vk::Format::R16G16B16A16_SFLOAT),
+            Some(D3DFormat::A32B32G32R32F) => Ok(vk::Format::R32G32B32A32_SFLOAT),
+            format => anyhow::bail!("DDS format d3d:{:?} not supported", format),
+        }
+    }
+
+    // ddsfile doesn't expose the DX10 header's cube flag, but writers set the legacy one as well
+    pub fn is_cube(dds: &ddsfile::Dds) -> bool {
+        dds.header.caps2.contains(Caps2::CUBEMAP)
+    }
+
+    /// Number of images, or cubes for cube maps
+    pub fn get_array_size(dds: &ddsfile::Dds) -> u32 {
+        dds.header10
+            .as_ref()
+            .map_or(1, |header10| header10.array_size.max(1))
+    }
+
     pub fn get_texture_size(pitch: u32, pitch_height: u32, height: u32, depth: u32) -> usize {
         let row_height = (height + (pitch_height - 1)) / pitch_height;
         pitch as usize * row_height as usize * depth as usize
@@ -365,9 +1070,65 @@ impl LazyWorker for CreateGpuImage {
     async fn run(self, ctx: RunContext) -> Self::Output {
         let src = self.image.eval(&ctx).await?;
 
+        let normal_variance = match (&*src, &self.roughness_normal_map) {
+            (RawImage::Dds(_) | RawImage::Ktx2(_), _) | (_, None) => None,
+            (_, Some(normal_map)) => {
+                let normal_variance = NormalVariance::new(&*normal_map.eval(&ctx).await?);
+                if normal_variance.is_none() {
+                    log::warn!("Can't adjust roughness for pre-compressed normal maps");
+                }
+                normal_variance
+            }
+        };
+
         match &*src {
-            RawImage::Rgba8(src) => self.process_rgba8(src),
+            RawImage::Rgba8(src) => self.process_rgba8(src, normal_variance.as_ref()),
+            // 16-bit sources are LDR, so the 8-bit BC formats fit them better than BC6H
+            RawImage::Rgba16(src) if self.quantizes_ldr_sources() => {
+                self.process_rgba8(&quantize_rgba16(src), normal_variance.as_ref())
+            }
+            RawImage::Rgba16(src) => {
+                let to_linear = |x: u16| -> f32 {
+                    let x = x as f32 / 65535.0;
+                    match self.params.gamma {
+                        TexGamma::Linear => x,
+                        TexGamma::Srgb => srgb_to_linear(x),
+                    }
+                };
+
+                let data = src
+                    .data
+                    .chunks_exact(4)
+                    .flat_map(|px| {
+                        [
+                            to_linear(px[0]),
+                            to_linear(px[1]),
+                            to_linear(px[2]),
+                            px[3] as f32 / 65535.0,
+                        ]
+                    })
+                    .collect();
+
+                let image = RgbaF32Image::from_raw(src.dimensions[0], src.dimensions[1], data)
+                    .ok_or_else(|| anyhow::anyhow!("Image data doesn't match its dimensions"))?;
+
+                self.process_rgba_f32(image, normal_variance.as_ref())
+            }
+            RawImage::Rgba32f(src) if self.quantizes_ldr_sources() && !is_hdr(src) => {
+                let image = quantize_rgba32f(src, self.params.gamma);
+                self.process_rgba8(&image, normal_variance.as_ref())
+            }
+            RawImage::Rgba32f(src) => {
+                let image =
+                    RgbaF32Image::from_raw(src.dimensions[0], src.dimensions[1], src.data.clone())
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("Image data doesn't match its dimensions")
+                        })?;
+
+                self.process_rgba_f32(image, normal_variance.as_ref())
+            }
             RawImage::Dds(src) => self.process_dds(src),
+            RawImage::Ktx2(src) => Ok(src.clone()),
         }
     }
 }
@@ -1,15 +1,49 @@
 // Based on `import.rs` in the `gltf` crate, but modified not to load images (we do that separately).
 
 use bytes::Bytes;
-use gltf::{buffer, image, Document, Error, Gltf, Result};
-use std::{fs, io, path::Path};
+use gltf::{buffer, image, json, Document, Error, Gltf, Result};
+use kajiya_backend::{normalized_path_from_vfs, vfs_path_from_canonical};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use crate::image::ImageSource;
 
 type BufferBytes = Bytes;
 
 /// Return type of `import`.
-type Import = (Document, Vec<BufferBytes>, Vec<ImageSource>);
+type Import = (Document, Vec<BufferBytes>, Vec<ImageSource>, RawJson);
+
+/// The document's JSON, for extensions which the `gltf` crate doesn't expose.
+pub struct RawJson(json::Value);
+
+impl RawJson {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let json: std::borrow::Cow<[u8]> = if data.starts_with(b"glTF") {
+            gltf::binary::Glb::from_slice(data)?.json
+        } else {
+            data.into()
+        };
+
+        Ok(Self(json::deserialize::from_slice(&json)?))
+    }
+
+    pub fn material(&self, index: usize) -> Option<&json::Value> {
+        self.0.get("materials")?.get(index)
+    }
+
+    /// Buffers standing in for the uncompressed data of `EXT_meshopt_compression` views.
+    /// They usually have no data of their own.
+    pub(crate) fn is_meshopt_fallback_buffer(&self, index: usize) -> bool {
+        self.0
+            .get("buffers")
+            .and_then(|buffers| buffers.get(index))
+            .and_then(|buffer| buffer.pointer("/extensions/EXT_meshopt_compression/fallback"))
+            .and_then(json::Value::as_bool)
+            .unwrap_or(false)
+    }
+}
 
 /// Represents the set of URI schemes the importer supports.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -17,42 +51,35 @@ enum Scheme<'a> {
     /// `data:[<media type>];base64,<data>`.
     Data(Option<&'a str>, &'a str),
 
-    /// `file:[//]<absolute file path>`.
-    ///
-    /// Note: The file scheme does not implement authority.
-    File(&'a str),
+    /// `file:[//[localhost]]<absolute file path>`, percent-decoded.
+    File(PathBuf),
 
-    /// `../foo`, etc.
-    Relative,
+    /// `../foo`, etc., percent-decoded.
+    Relative(PathBuf),
 
-    /// Placeholder for an unsupported URI scheme identifier.
-    Unsupported,
+    /// Remote and other unsupported schemes; nothing is fetched over the network.
+    Unsupported(&'a str),
 }
 
 impl<'a> Scheme<'a> {
     fn parse(uri: &str) -> Scheme<'_> {
-        if uri.contains(':') {
-            #[allow(clippy::manual_strip)]
-            #[allow(clippy::iter_nth_zero)]
-            if uri.starts_with("data:") {
-                let match0 = &uri["data:".len()..].split(";base64,").nth(0);
-                let match1 = &uri["data:".len()..].split(";base64,").nth(1);
-                if match1.is_some() {
-                    Scheme::Data(Some(match0.unwrap()), match1.unwrap())
-                } else if match0.is_some() {
-                    Scheme::Data(None, match0.unwrap())
-                } else {
-                    Scheme::Unsupported
+        match uri_scheme(uri) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("data") => {
+                let mut parts = uri[scheme.len() + 1..].splitn(2, ";base64,");
+                match (parts.next(), parts.next()) {
+                    (Some(mime_type), Some(base64)) => Scheme::Data(Some(mime_type), base64),
+                    (Some(data), None) => Scheme::Data(None, data),
+                    _ => Scheme::Unsupported(scheme),
                 }
-            } else if uri.starts_with("file://") {
-                Scheme::File(&uri["file://".len()..])
-            } else if uri.starts_with("file:") {
-                Scheme::File(&uri["file:".len()..])
-            } else {
-                Scheme::Unsupported
             }
-        } else {
-            Scheme::Relative
+            Some(scheme) if scheme.eq_ignore_ascii_case("file") => {
+                match file_uri_path(&uri[scheme.len() + 1..]) {
+                    Some(path) => Scheme::File(path),
+                    None => Scheme::Unsupported(scheme),
+                }
+            }
+            Some(scheme) => Scheme::Unsupported(scheme),
+            None => Scheme::Relative(percent_decode(uri).into()),
         }
     }
 
@@ -61,14 +88,74 @@ impl<'a> Scheme<'a> {
             // The path may be unused in the Scheme::Data case
             // Example: "uri" : "data:application/octet-stream;base64,wsVHPgA...."
             Scheme::Data(_, base64) => base64::decode(&base64).map_err(Error::Base64),
-            Scheme::File(path) if base.is_some() => read_to_end(path),
-            Scheme::Relative if base.is_some() => read_to_end(base.unwrap().join(uri)),
-            Scheme::Unsupported => Err(Error::UnsupportedScheme),
+            Scheme::File(path) if base.is_some() => read_to_end(filesystem_path(&path)),
+            Scheme::Relative(path) if base.is_some() => {
+                read_to_end(filesystem_path(&base.unwrap().join(path)))
+            }
+            Scheme::Unsupported(scheme) => Err(unsupported_scheme(scheme)),
             _ => Err(Error::ExternalReferenceInSliceImport),
         }
     }
 }
 
+/// The scheme of an absolute URI, e.g. `https`. `None` for relative references.
+fn uri_scheme(uri: &str) -> Option<&str> {
+    let (scheme, _) = uri.split_once(':')?;
+
+    let mut chars = scheme.chars();
+    let is_scheme = chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+
+    // Single letters are Windows drive letters rather than schemes
+    (is_scheme && scheme.len() > 1).then(|| scheme)
+}
+
+/// Invalid UTF-8 leaves the string as-is; some exporters don't encode the paths at all.
+fn percent_decode(s: &str) -> String {
+    urlencoding::decode(s).map_or_else(|_| s.to_owned(), |s| s.into_owned())
+}
+
+/// The path of a `file:` URI, given the part after the scheme. Remote hosts are not supported.
+fn file_uri_path(uri: &str) -> Option<PathBuf> {
+    let path = if let Some(uri) = uri.strip_prefix("//") {
+        let (authority, path) = uri.split_at(uri.find('/').unwrap_or(uri.len()));
+        if authority.is_empty() || authority.eq_ignore_ascii_case("localhost") {
+            path
+        } else if authority.len() == 2 && authority.ends_with(':') {
+            // Drive letter in place of the authority: `file://C:/foo`
+            uri
+        } else {
+            return None;
+        }
+    } else {
+        uri
+    };
+
+    let path = percent_decode(path);
+
+    // `file:///C:/foo` on Windows
+    let is_drive_path = {
+        let bytes = path.as_bytes();
+        bytes.len() >= 3 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && bytes[2] == b':'
+    };
+
+    Some(if is_drive_path {
+        PathBuf::from(&path[1..])
+    } else {
+        PathBuf::from(path)
+    })
+}
+
+fn unsupported_scheme(scheme: &str) -> Error {
+    log::error!("Unsupported URI scheme {:?} in glTF document", scheme);
+    Error::UnsupportedScheme
+}
+
+/// Where to read `path` from, which can start with a VFS mount point.
+pub(crate) fn filesystem_path(path: &Path) -> PathBuf {
+    normalized_path_from_vfs(path).unwrap_or_else(|_| path.to_owned())
+}
+
 fn read_to_end<P>(path: P) -> Result<Vec<u8>>
 where
     P: AsRef<Path>,
@@ -86,17 +173,26 @@ where
 }
 
 /// Import the buffer data referenced by a glTF document.
+///
+/// Buffer views compressed with `EXT_meshopt_compression` are decoded into their
+/// fallback buffers, so that accessors can read them like any other.
 pub fn import_buffer_data(
     document: &Document,
+    raw_json: &RawJson,
     base: Option<&Path>,
     mut blob: Option<Vec<u8>>,
 ) -> Result<Vec<Bytes>> {
     let mut buffers = Vec::new();
     for buffer in document.buffers() {
-        let mut data = match buffer.source() {
-            buffer::Source::Uri(uri) => Scheme::read(base, uri),
-            buffer::Source::Bin => blob.take().ok_or(Error::MissingBlob),
-        }?;
+        let mut data = if raw_json.is_meshopt_fallback_buffer(buffer.index()) {
+            // Filled in by `decode_meshopt_buffer_views`
+            vec![0; buffer.length()]
+        } else {
+            match buffer.source() {
+                buffer::Source::Uri(uri) => Scheme::read(base, uri),
+                buffer::Source::Bin => blob.take().ok_or(Error::MissingBlob),
+            }?
+        };
         if data.len() < buffer.length() {
             return Err(Error::BufferLength {
                 buffer: buffer.index(),
@@ -107,9 +203,301 @@ pub fn import_buffer_data(
         while data.len() % 4 != 0 {
             data.push(0);
         }
-        buffers.push(Bytes::from(data));
+        buffers.push(data);
     }
-    Ok(buffers)
+
+    decode_meshopt_buffer_views(raw_json, &mut buffers)?;
+
+    Ok(buffers.into_iter().map(Bytes::from).collect())
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// `EXT_meshopt_compression` parameters of a buffer view.
+struct MeshoptBufferView {
+    // Where the compressed data is
+    buffer: usize,
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: usize,
+    count: usize,
+    mode: String,
+    filter: String,
+}
+
+impl MeshoptBufferView {
+    fn parse(ext: &json::Value) -> Option<Self> {
+        let usize_field = |name: &str| ext.get(name)?.as_u64().map(|x| x as usize);
+        let str_field = |name: &str| ext.get(name).and_then(json::Value::as_str);
+
+        Some(Self {
+            buffer: usize_field("buffer")?,
+            byte_offset: usize_field("byteOffset").unwrap_or(0),
+            byte_length: usize_field("byteLength")?,
+            byte_stride: usize_field("byteStride")?,
+            count: usize_field("count")?,
+            mode: str_field("mode")?.to_owned(),
+            filter: str_field("filter").unwrap_or("NONE").to_owned(),
+        })
+    }
+
+    /// Whether the decoders accept the strides; they assert rather than fail otherwise.
+    fn is_valid(&self) -> bool {
+        let stride = self.byte_stride;
+        let mode_ok = match self.mode.as_str() {
+            "ATTRIBUTES" => stride % 4 == 0 && stride <= 256,
+            "TRIANGLES" => (stride == 2 || stride == 4) && self.count % 3 == 0,
+            "INDICES" => stride == 2 || stride == 4,
+            _ => true,
+        };
+        let filter_ok = match self.filter.as_str() {
+            "OCTAHEDRAL" => stride == 4 || stride == 8,
+            "QUATERNION" => stride == 8,
+            "EXPONENTIAL" => stride % 4 == 0,
+            _ => true,
+        };
+
+        stride > 0 && mode_ok && filter_ok
+    }
+
+    /// Decodes into at most `max_len` bytes, the length of the buffer view being filled in.
+    fn decode(&self, encoded: &[u8], max_len: usize) -> Result<Vec<u8>> {
+        use meshopt::ffi;
+
+        if !self.is_valid() {
+            return Err(invalid_data(format!(
+                "Invalid EXT_meshopt_compression stride {} for mode {} and filter {}",
+                self.byte_stride, self.mode, self.filter
+            )));
+        }
+
+        // Checked before allocating, since both come straight from the JSON
+        let decoded_len = self
+            .count
+            .checked_mul(self.byte_stride)
+            .filter(|&len| len <= max_len)
+            .ok_or_else(|| {
+                invalid_data(format!(
+                    "{} elements of {} bytes don't fit in a buffer view of {} bytes",
+                    self.count, self.byte_stride, max_len
+                ))
+            })?;
+
+        let mut decoded = vec![0u8; decoded_len];
+
+        // Safety: `decoded` holds `count` elements of `byte_stride` bytes, which is all the
+        // decoders write; they validate the encoded data against that size.
+        let status = unsafe {
+            let dst = decoded.as_mut_ptr().cast();
+            match self.mode.as_str() {
+                "ATTRIBUTES" => ffi::meshopt_decodeVertexBuffer(
+                    dst,
+                    self.count,
+                    self.byte_stride,
+                    encoded.as_ptr(),
+                    encoded.len(),
+                ),
+                "TRIANGLES" => ffi::meshopt_decodeIndexBuffer(
+                    dst,
+                    self.count,
+                    self.byte_stride,
+                    encoded.as_ptr(),
+                    encoded.len(),
+                ),
+                "INDICES" => ffi::meshopt_decodeIndexSequence(
+                    dst,
+                    self.count,
+                    self.byte_stride,
+                    encoded.as_ptr(),
+                    encoded.len(),
+                ),
+                mode => {
+                    return Err(invalid_data(format!(
+                        "Unknown EXT_meshopt_compression mode {:?}",
+                        mode
+                    )))
+                }
+            }
+        };
+
+        if status != 0 {
+            return Err(invalid_data(format!(
+                "Could not decode a {} meshopt buffer view (error {})",
+                self.mode, status
+            )));
+        }
+
+        // The filters work in place
+        match self.filter.as_str() {
+            "NONE" => {}
+            "OCTAHEDRAL" => decode_filter_oct(&mut decoded, self.byte_stride),
+            "QUATERNION" => decode_filter_quat(&mut decoded),
+            "EXPONENTIAL" => decode_filter_exp(&mut decoded),
+            filter => {
+                return Err(invalid_data(format!(
+                    "Unknown EXT_meshopt_compression filter {:?}",
+                    filter
+                )))
+            }
+        }
+
+        Ok(decoded)
+    }
+}
+
+// The filters below mirror meshoptimizer's `vertexfilter.cpp`, which the `meshopt` crate
+// doesn't build. Strides are checked by `MeshoptBufferView::is_valid`.
+
+fn round_to_int(x: f32) -> i32 {
+    (x + if x >= 0.0 { 0.5 } else { -0.5 }) as i32
+}
+
+/// Octahedral normals or tangents of 4 signed 8- or 16-bit components. Z stores 1.0,
+/// and W is passed through.
+fn decode_filter_oct(data: &mut [u8], byte_stride: usize) {
+    let component_size = byte_stride / 4;
+    let max = ((1 << (component_size * 8 - 1)) - 1) as f32;
+
+    let read = |element: &[u8], i: usize| -> f32 {
+        if component_size == 1 {
+            element[i] as i8 as f32
+        } else {
+            i16::from_le_bytes([element[i * 2], element[i * 2 + 1]]) as f32
+        }
+    };
+
+    for element in data.chunks_exact_mut(byte_stride) {
+        let mut x = read(element, 0);
+        let mut y = read(element, 1);
+        let z = read(element, 2) - x.abs() - y.abs();
+
+        // Fix up the octahedral coordinates for z < 0
+        let t = z.min(0.0);
+        x += if x >= 0.0 { t } else { -t };
+        y += if y >= 0.0 { t } else { -t };
+
+        let s = max / (x * x + y * y + z * z).sqrt();
+        for (i, v) in [x, y, z].into_iter().enumerate() {
+            let v = round_to_int(v * s);
+            if component_size == 1 {
+                element[i] = v as i8 as u8;
+            } else {
+                element[i * 2..i * 2 + 2].copy_from_slice(&(v as i16).to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Quaternions of 3 signed 16-bit components, with the 4th one reconstructed. The low two bits
+/// of the last component give the index of the reconstructed one, and the rest the scale.
+fn decode_filter_quat(data: &mut [u8]) {
+    let scale = std::f32::consts::FRAC_1_SQRT_2;
+
+    for element in data.chunks_exact_mut(8) {
+        let c: [i16; 4] =
+            [0, 1, 2, 3].map(|i| i16::from_le_bytes([element[i * 2], element[i * 2 + 1]]));
+
+        let ss = scale / (c[3] | 3) as f32;
+        let x = c[0] as f32 * ss;
+        let y = c[1] as f32 * ss;
+        let z = c[2] as f32 * ss;
+
+        // Clamped to avoid NaN due to precision errors
+        let w = (1.0 - x * x - y * y - z * z).max(0.0).sqrt();
+
+        let qc = (c[3] & 3) as usize;
+        let mut write = |i: usize, v: f32| {
+            let i = (qc + i) & 3;
+            element[i * 2..i * 2 + 2]
+                .copy_from_slice(&(round_to_int(v * 32767.0) as i16).to_le_bytes());
+        };
+        write(1, x);
+        write(2, y);
+        write(3, z);
+        write(0, w);
+    }
+}
+
+/// 32-bit floats encoded as an 8-bit exponent and a 24-bit mantissa
+fn decode_filter_exp(data: &mut [u8]) {
+    for value in data.chunks_exact_mut(4) {
+        let v = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+
+        let m = ((v << 8) as i32) >> 8;
+        let e = (v as i32) >> 24;
+
+        // ldexp(m, e), like the reference implementation
+        let decoded = f32::from_bits(((e + 127) as u32) << 23) * m as f32;
+        value.copy_from_slice(&decoded.to_le_bytes());
+    }
+}
+
+fn decode_meshopt_buffer_views(raw_json: &RawJson, buffers: &mut [Vec<u8>]) -> Result<()> {
+    let views = match raw_json
+        .0
+        .get("bufferViews")
+        .and_then(json::Value::as_array)
+    {
+        Some(views) => views,
+        None => return Ok(()),
+    };
+
+    for (view_idx, view) in views.iter().enumerate() {
+        let ext = match view.pointer("/extensions/EXT_meshopt_compression") {
+            Some(ext) => ext,
+            None => continue,
+        };
+
+        let compressed = MeshoptBufferView::parse(ext).ok_or_else(|| {
+            invalid_data(format!(
+                "Malformed EXT_meshopt_compression in buffer view {}",
+                view_idx
+            ))
+        })?;
+
+        let target_buffer = view.get("buffer").and_then(json::Value::as_u64);
+        let target_offset = view
+            .get("byteOffset")
+            .and_then(json::Value::as_u64)
+            .unwrap_or(0) as usize;
+        let target_length = view
+            .get("byteLength")
+            .and_then(json::Value::as_u64)
+            .map(|length| length as usize)
+            .ok_or_else(|| invalid_data(format!("Buffer view {} has no byteLength", view_idx)))?;
+
+        let decoded = {
+            let encoded = buffers
+                .get(compressed.buffer)
+                .zip(compressed.byte_offset.checked_add(compressed.byte_length))
+                .and_then(|(buffer, end)| buffer.get(compressed.byte_offset..end))
+                .ok_or_else(|| {
+                    invalid_data(format!(
+                        "The compressed data of buffer view {} is out of bounds",
+                        view_idx
+                    ))
+                })?;
+
+            compressed.decode(encoded, target_length)?
+        };
+
+        let target = target_buffer
+            .and_then(|buffer| buffers.get_mut(buffer as usize))
+            .zip(target_offset.checked_add(decoded.len()))
+            .and_then(|(buffer, end)| buffer.get_mut(target_offset..end))
+            .ok_or_else(|| {
+                invalid_data(format!(
+                    "Buffer view {} is too small for its decompressed data",
+                    view_idx
+                ))
+            })?;
+
+        target.copy_from_slice(&decoded);
+    }
+
+    Ok(())
 }
 
 /// Import the image data referenced by a glTF document.
@@ -123,21 +511,21 @@ pub fn import_image_data(
     for image in document.images() {
         match image.source() {
             image::Source::Uri { uri, mime_type: _ } if base.is_some() => {
-                let uri = urlencoding::decode(uri).map_err(|_| Error::UnsupportedScheme)?;
-                let uri = uri.as_ref();
-
                 match Scheme::parse(uri) {
                     Scheme::Data(Some(_mime_type), base64) => {
                         let bytes = base64::decode(&base64).map_err(Error::Base64)?;
                         images.push(ImageSource::Memory(Bytes::from(bytes)));
                     }
                     Scheme::Data(None, ..) => return Err(Error::ExternalReferenceInSliceImport),
-                    Scheme::Unsupported => return Err(Error::UnsupportedScheme),
-                    Scheme::File(path) => images.push(ImageSource::File(path.into())),
-                    Scheme::Relative if base.is_some() => {
-                        images.push(ImageSource::File(base.unwrap().join(uri)))
+                    Scheme::Unsupported(scheme) => return Err(unsupported_scheme(scheme)),
+                    // Kept in terms of the VFS, so that image identities don't depend on where
+                    // the assets are checked out
+                    Scheme::File(path) => {
+                        images.push(ImageSource::File(vfs_path_from_canonical(path)))
+                    }
+                    Scheme::Relative(path) => {
+                        images.push(ImageSource::File(base.unwrap().join(path)))
                     }
-                    Scheme::Relative => return Err(Error::UnsupportedScheme),
                 }
             }
             image::Source::View { view, mime_type: _ } => {
@@ -154,21 +542,54 @@ pub fn import_image_data(
     Ok(images)
 }
 
-fn import_impl(Gltf { document, blob }: Gltf, base: Option<&Path>) -> Result<Import> {
-    let buffer_data = import_buffer_data(&document, base, blob)?;
+fn import_impl(
+    Gltf { document, blob }: Gltf,
+    raw_json: RawJson,
+    base: Option<&Path>,
+) -> Result<Import> {
+    let buffer_data = import_buffer_data(&document, &raw_json, base, blob)?;
     let image_data = import_image_data(&document, base, &buffer_data)?;
-    let import = (document, buffer_data, image_data);
+    let import = (document, buffer_data, image_data, raw_json);
     Ok(import)
 }
 
+/// The file a URI refers to, resolved for reading; `None` for embedded data.
+/// Fails with the scheme of unsupported URIs.
+pub(crate) fn uri_file_path<'a>(
+    base: &Path,
+    uri: &'a str,
+) -> std::result::Result<Option<PathBuf>, &'a str> {
+    match Scheme::parse(uri) {
+        Scheme::Data(..) => Ok(None),
+        Scheme::File(path) => Ok(Some(filesystem_path(&path))),
+        Scheme::Relative(path) => Ok(Some(filesystem_path(&base.join(path)))),
+        Scheme::Unsupported(scheme) => Err(scheme),
+    }
+}
+
+/// Parses a document without loading anything it references. Also returns the base
+/// path of its relative references.
+pub(crate) fn load_document(path: &Path) -> Result<(Gltf, RawJson, PathBuf)> {
+    let fs_path = filesystem_path(path);
+
+    // Relative references resolve against the VFS path of the document, which images keep
+    let vfs_path = vfs_path_from_canonical(&fs_path);
+    let base = vfs_path
+        .parent()
+        .unwrap_or_else(|| Path::new("./"))
+        .to_owned();
+
+    let data = read_to_end(&fs_path)?;
+    let raw_json = RawJson::parse(&data)?;
+    Ok((Gltf::from_slice_without_validation(&data)?, raw_json, base))
+}
+
 fn import_path(path: &Path) -> Result<Import> {
-    let base = path.parent().unwrap_or_else(|| Path::new("./"));
-    let file = fs::File::open(path).map_err(Error::Io)?;
-    let reader = io::BufReader::new(file);
-    import_impl(Gltf::from_reader_without_validation(reader)?, Some(base))
+    let (gltf, raw_json, base) = load_document(path)?;
+    import_impl(gltf, raw_json, Some(&base))
 }
 
-/// Import some glTF 2.0 from the file system.
+/// Import some glTF 2.0 from the file system. The path can start with a VFS mount point.
 pub fn import<P>(path: P) -> Result<Import>
 where
     P: AsRef<Path>,
@@ -0,0 +1,533 @@
+// Wavefront OBJ scenes, with materials from their MTL libraries.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::File,
+    hash::Hash,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use glam::{Mat4, Quat, Vec3};
+use turbosloth::*;
+
+use crate::{
+    image::ImageSource,
+    import_gltf::filesystem_path,
+    mesh::{
+        generate_normals, generate_tangents, unweld_vertices, MeshMaterial, MeshMaterialFlags,
+        MeshMaterialMap, NormalGeneration, TexCompressionMode, TexGamma, TexParams, TriangleMesh,
+        DEFAULT_MAP_TRANSFORM,
+    },
+};
+
+// Each with the folder of its MTL library, which its texture paths are relative to.
+// In VFS terms, like the path of the OBJ file.
+type ObjMaterials = Vec<(PathBuf, tobj::Material)>;
+
+/// Loads the models of an OBJ file, and the materials of its MTL libraries.
+/// `path` can start with a VFS mount point.
+fn load_obj(path: &Path) -> anyhow::Result<(Vec<tobj::Model>, ObjMaterials)> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let material_dirs: RefCell<Vec<PathBuf>> = Default::default();
+
+    let (models, materials) = tobj::load_obj_buf(
+        &mut BufReader::new(File::open(filesystem_path(path))?),
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        |lib| {
+            let lib = base_dir.join(lib);
+            let (materials, names) = tobj::load_mtl(filesystem_path(&lib))?;
+
+            let lib_dir = lib.parent().unwrap_or_else(|| Path::new("")).to_owned();
+            material_dirs
+                .borrow_mut()
+                .extend(std::iter::repeat(lib_dir).take(materials.len()));
+
+            Ok((materials, names))
+        },
+    )?;
+
+    let materials = materials.unwrap_or_else(|err| {
+        log::warn!(
+            "Could not load the materials of {:?}: {}; using defaults",
+            path,
+            err
+        );
+        Vec::new()
+    });
+
+    Ok((
+        models,
+        material_dirs
+            .into_inner()
+            .into_iter()
+            .zip(materials)
+            .collect(),
+    ))
+}
+
+/// The file of a texture statement, which can start with options such as `-bm 1`.
+fn texture_path(base_dir: &Path, statement: &str) -> PathBuf {
+    let file = if statement.starts_with('-') {
+        statement.split_whitespace().last().unwrap_or_default()
+    } else {
+        statement.trim()
+    };
+
+    // Exported on Windows more often than not
+    base_dir.join(file.replace('\\', "/"))
+}
+
+/// The texture maps a material uses, with `texture_path` applied. Relative to the folder
+/// of the material library.
+fn material_textures(base_dir: &Path, mat: &tobj::Material) -> BTreeMap<&'static str, PathBuf> {
+    let known = [
+        ("map_Kd", mat.diffuse_texture.as_str()),
+        ("map_Ks", mat.specular_texture.as_str()),
+        ("map_Ka", mat.ambient_texture.as_str()),
+        ("map_Ns", mat.shininess_texture.as_str()),
+        ("map_d", mat.dissolve_texture.as_str()),
+        ("map_Bump", mat.normal_texture.as_str()),
+    ];
+
+    let unknown = ["map_Ke", "map_Pr", "map_Pm", "norm"]
+        .iter()
+        .map(|&key| (key, mat.unknown_param.get(key).map_or("", String::as_str)));
+
+    known
+        .into_iter()
+        .chain(unknown)
+        .filter(|(_, statement)| !statement.trim().is_empty())
+        .map(|(key, statement)| (key, texture_path(base_dir, statement)))
+        .collect()
+}
+
+/// Whether a texture file has one of the usual names of normal maps, e.g. `brick_normal.png`
+/// or `brick_nrm.png`.
+fn is_named_like_normal_map(path: &Path) -> bool {
+    let stem = path
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_lowercase());
+
+    stem.contains("normal")
+        || stem.contains("nrm")
+        || stem.ends_with("_n")
+        || stem.ends_with("_norm")
+}
+
+fn parse_floats<const N: usize>(value: Option<&String>) -> Option<[f32; N]> {
+    let mut res = [0.0; N];
+    let mut values = value?.split_whitespace();
+    for x in &mut res {
+        *x = values.next()?.parse().ok()?;
+    }
+    Some(res)
+}
+
+fn image_map(path: PathBuf, params: TexParams) -> MeshMaterialMap {
+    MeshMaterialMap::Image {
+        source: ImageSource::File(path),
+        params,
+        uv_set: 0,
+    }
+}
+
+fn color_tex_params() -> TexParams {
+    TexParams {
+        gamma: TexGamma::Srgb,
+        use_mips: true,
+        compression: TexCompressionMode::Rgba,
+        compression_quality: Default::default(),
+        channel_swizzle: None,
+        normal_map: false,
+        max_size: None,
+    }
+}
+
+/// Maps the Phong-era MTL parameters onto the metalness-roughness model, in the same
+/// map slots as glTF materials: normal, spec, albedo, emissive.
+fn load_obj_material(
+    base_dir: &Path,
+    mat: &tobj::Material,
+) -> (Vec<MeshMaterialMap>, MeshMaterial) {
+    let textures = material_textures(base_dir, mat);
+    let scalar = |key: &str| parse_floats::<1>(mat.unknown_param.get(key)).map(|[x]| x);
+
+    let albedo_map = textures
+        .get("map_Kd")
+        .map_or(MeshMaterialMap::Placeholder([255, 255, 255, 255]), |path| {
+            image_map(path.clone(), color_tex_params())
+        });
+
+    // The PBR extension's `norm` is unambiguous. `map_Bump` is a normal map in some exports,
+    // but a grayscale height map in classic scenes, so it's only used if named like a normal map.
+    let bump_map = textures.get("map_Bump");
+    let normal_map_path = textures
+        .get("norm")
+        .or_else(|| bump_map.filter(|path| is_named_like_normal_map(path)));
+
+    if let (None, Some(bump_map)) = (normal_map_path, bump_map) {
+        log::warn!(
+            "Material {:?} has the bump map {:?}, which is taken to be a height map; \
+            height maps are not supported, so it's ignored",
+            mat.name,
+            bump_map
+        );
+    }
+
+    let normal_map =
+        normal_map_path.map_or(MeshMaterialMap::Placeholder([127, 127, 255, 255]), |path| {
+            image_map(
+                path.clone(),
+                TexParams {
+                    gamma: TexGamma::Linear,
+                    use_mips: true,
+                    compression: TexCompressionMode::Rg,
+                    compression_quality: Default::default(),
+                    channel_swizzle: None,
+                    normal_map: true,
+                    max_size: None,
+                },
+            )
+        });
+
+    // A grayscale roughness map; the metalness comes from the opaque alpha, times the factor
+    let spec_map =
+        textures
+            .get("map_Pr")
+            .map_or(MeshMaterialMap::Placeholder([255, 255, 127, 255]), |path| {
+                image_map(
+                    path.clone(),
+                    TexParams {
+                        gamma: TexGamma::Linear,
+                        use_mips: true,
+                        compression: TexCompressionMode::Rg,
+                        compression_quality: Default::default(),
+                        channel_swizzle: Some([0, 3, 2, 3]),
+                        normal_map: false,
+                        max_size: None,
+                    },
+                )
+            });
+
+    let emissive_map = textures
+        .get("map_Ke")
+        .map_or(MeshMaterialMap::Placeholder([255, 255, 255, 255]), |path| {
+            image_map(path.clone(), color_tex_params())
+        });
+
+    // The alpha of the albedo map is the only coverage mask; `map_d` most often refers
+    // to the same texture, but it can't come from a separate one.
+    let alpha_map = textures.get("map_d");
+    let alpha_in_albedo = alpha_map.is_some() && alpha_map == textures.get("map_Kd");
+
+    let unsupported: Vec<&str> = ["map_Ks", "map_Ka", "map_Ns", "map_d", "map_Pm"]
+        .iter()
+        .copied()
+        .filter(|&key| textures.contains_key(key) && !(key == "map_d" && alpha_in_albedo))
+        .collect();
+    if !unsupported.is_empty() {
+        log::warn!(
+            "Material {:?} uses unsupported maps {:?}; ignoring them",
+            mat.name,
+            unsupported
+        );
+    }
+
+    let diffuse = Vec3::from(mat.diffuse);
+    let specular = Vec3::from(mat.specular);
+
+    // Dark diffuse with a colored specular reflection is what metals look like in MTL
+    let looks_metallic = diffuse.max_element() < 0.04 && specular.max_element() > 0.04;
+    let metalness_factor = scalar("Pm").unwrap_or(if looks_metallic { 1.0 } else { 0.0 });
+    let base_color = if looks_metallic && scalar("Pm").is_none() {
+        specular
+    } else {
+        diffuse
+    };
+
+    // The Phong exponent converted to a Beckmann alpha, and then to perceptual roughness.
+    // Materials without a specular reflection are Lambertian.
+    let roughness_mult = scalar("Pr").unwrap_or_else(|| {
+        if specular == Vec3::ZERO && !textures.contains_key("map_Ks") {
+            1.0
+        } else {
+            (2.0 / (mat.shininess.max(0.0) + 2.0)).sqrt().sqrt()
+        }
+    });
+
+    let emissive = parse_floats::<3>(mat.unknown_param.get("Ke")).unwrap_or(
+        if textures.contains_key("map_Ke") {
+            [1.0; 3]
+        } else {
+            [0.0; 3]
+        },
+    );
+
+    // `Tr` is the inverse of `d`, and written instead of it by some exporters
+    let opacity = match scalar("Tr") {
+        Some(tr) if mat.dissolve >= 1.0 => 1.0 - tr,
+        _ => mat.dissolve,
+    }
+    .clamp(0.0, 1.0);
+
+    // The illumination models with refraction or ray-traced transparency are glass;
+    // otherwise, the opacity is coverage, like glTF's alpha.
+    let is_glass = matches!(mat.illumination_model, Some(4 | 6 | 7 | 9));
+    let (transmission, alpha) = if is_glass {
+        (1.0 - opacity, 1.0)
+    } else {
+        (0.0, opacity)
+    };
+
+    let mut flags = 0;
+    if alpha_in_albedo && !is_glass {
+        flags |= MeshMaterialFlags::MESH_MATERIAL_FLAG_ALPHA_MASK;
+    }
+    if alpha < 1.0 {
+        flags |= MeshMaterialFlags::MESH_MATERIAL_FLAG_ALPHA_BLEND;
+    }
+
+    (
+        vec![normal_map, spec_map, albedo_map, emissive_map],
+        MeshMaterial {
+            base_color_mult: base_color.extend(alpha).into(),
+            maps: [0, 1, 2, 3],
+            roughness_mult,
+            metalness_factor,
+            emissive,
+            flags,
+            map_transforms: [DEFAULT_MAP_TRANSFORM; 4],
+            map_uv_sets: [0; 4],
+            alpha_cutoff: 0.5,
+            transmission,
+            ior: mat.optical_density.max(1.0),
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            sheen_color: [0.0; 3],
+            sheen_roughness: 0.0,
+        },
+    )
+}
+
+fn default_obj_material() -> (Vec<MeshMaterialMap>, MeshMaterial) {
+    load_obj_material(
+        Path::new(""),
+        &tobj::Material {
+            diffuse: [0.8; 3],
+            dissolve: 1.0,
+            ..Default::default()
+        },
+    )
+}
+
+/// Like `LoadGltfScene`, for Wavefront OBJ files.
+#[derive(Clone)]
+pub struct LoadObjScene {
+    pub path: PathBuf,
+    pub scale: f32,
+    pub rotation: Quat,
+    pub normal_generation: NormalGeneration,
+}
+
+impl Hash for LoadObjScene {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.scale.to_ne_bytes().hash(state);
+        self.rotation.x.to_ne_bytes().hash(state);
+        self.rotation.y.to_ne_bytes().hash(state);
+        self.rotation.z.to_ne_bytes().hash(state);
+        self.rotation.w.to_ne_bytes().hash(state);
+        self.normal_generation.hash(state);
+    }
+}
+
+#[async_trait]
+impl LazyWorker for LoadObjScene {
+    type Output = anyhow::Result<TriangleMesh>;
+
+    async fn run(self, _ctx: RunContext) -> Self::Output {
+        let (models, obj_materials) = load_obj(&self.path)
+            .with_context(|| format!("Loading OBJ scene from {:?}", self.path))?;
+
+        let xform = Mat4::from_scale_rotation_translation(
+            Vec3::splat(self.scale),
+            self.rotation,
+            Vec3::ZERO,
+        );
+
+        let mut res = TriangleMesh::default();
+
+        for (mtl_dir, mat) in &obj_materials {
+            let (mut maps, mut material) = load_obj_material(mtl_dir, mat);
+            let map_base = res.maps.len() as u32;
+            for id in material.maps.iter_mut() {
+                *id += map_base;
+            }
+
+            res.materials.push(material);
+            res.maps.append(&mut maps);
+        }
+
+        // For models without a material, added on first use
+        let mut default_material: Option<u32> = None;
+
+        for model in &models {
+            let mesh = &model.mesh;
+            if mesh.indices.is_empty() {
+                continue;
+            }
+
+            let material_id = match mesh.material_id.filter(|&id| id < obj_materials.len()) {
+                Some(id) => id as u32,
+                None => *default_material.get_or_insert_with(|| {
+                    let (mut maps, mut material) = default_obj_material();
+                    let map_base = res.maps.len() as u32;
+                    for id in material.maps.iter_mut() {
+                        *id += map_base;
+                    }
+
+                    res.materials.push(material);
+                    res.maps.append(&mut maps);
+                    res.materials.len() as u32 - 1
+                }),
+            };
+
+            let mut positions: Vec<[f32; 3]> = mesh
+                .positions
+                .chunks_exact(3)
+                .map(|p| [p[0], p[1], p[2]])
+                .collect();
+
+            let uvs_found = !mesh.texcoords.is_empty();
+            let mut uvs: Vec<[f32; 2]> = if uvs_found {
+                // OBJ puts the origin of the UVs at the bottom left
+                mesh.texcoords
+                    .chunks_exact(2)
+                    .map(|uv| [uv[0], 1.0 - uv[1]])
+                    .collect()
+            } else {
+                vec![[0.0, 0.0]; positions.len()]
+            };
+
+            let mut colors: Vec<[f32; 4]> = if mesh.vertex_color.is_empty() {
+                vec![[1.0, 1.0, 1.0, 1.0]; positions.len()]
+            } else {
+                mesh.vertex_color
+                    .chunks_exact(3)
+                    .map(|c| [c[0], c[1], c[2], 1.0])
+                    .collect()
+            };
+
+            let mut indices = mesh.indices.clone();
+
+            let normals: Vec<[f32; 3]> = if mesh.normals.is_empty() {
+                log::debug!(
+                    "Model {:?} has no normals. Generating {:?} normals...",
+                    model.name,
+                    self.normal_generation
+                );
+
+                if self.normal_generation == NormalGeneration::Flat {
+                    // Flat shading needs unique vertices per face
+                    unweld_vertices(&mut positions, &indices);
+                    unweld_vertices(&mut uvs, &indices);
+                    unweld_vertices(&mut colors, &indices);
+                    indices = (0..indices.len() as u32).collect();
+                }
+
+                generate_normals(&positions, &indices, self.normal_generation)
+            } else {
+                mesh.normals
+                    .chunks_exact(3)
+                    .map(|n| [n[0], n[1], n[2]])
+                    .collect()
+            };
+
+            let mut tangents = vec![[1.0, 0.0, 0.0, 1.0]; positions.len()];
+            generate_tangents(
+                &model.name,
+                &positions,
+                &normals,
+                uvs_found.then(|| uvs.as_slice()),
+                &indices,
+                &mut tangents,
+                &mut res.tangent_stats,
+            );
+
+            let base_index = res.positions.len() as u32;
+            res.indices.extend(indices.iter().map(|i| i + base_index));
+            res.material_ids
+                .extend(std::iter::repeat(material_id).take(positions.len()));
+            res.colors.append(&mut colors);
+            res.uvs.append(&mut uvs);
+
+            for v in positions {
+                let pos = (xform * Vec3::from(v).extend(1.0)).truncate();
+                res.positions.push(pos.into());
+            }
+
+            for v in normals {
+                let norm = (xform * Vec3::from(v).extend(0.0)).truncate().normalize();
+                res.normals.push(norm.into());
+            }
+
+            for [x, y, z, w] in tangents {
+                let t = (xform * Vec3::new(x, y, z).extend(0.0))
+                    .truncate()
+                    .normalize();
+                res.tangents.push(t.extend(w).into());
+            }
+        }
+
+        if res.indices.is_empty() {
+            anyhow::bail!("No triangles found in {:?}", self.path);
+        }
+
+        Ok(res)
+    }
+}
+
+/// Like `hash_gltf_sources`, for OBJ files: hashes the contents of the OBJ file,
+/// and of its material libraries and textures.
+pub fn hash_obj_sources(path: &Path, state: &mut impl std::hash::Hasher) -> anyhow::Result<()> {
+    let obj = std::fs::read(filesystem_path(path))?;
+    obj.hash(state);
+
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    for line in String::from_utf8_lossy(&obj).lines() {
+        if let Some(libs) = line.trim().strip_prefix("mtllib ") {
+            for lib in libs.split_whitespace() {
+                let lib = base_dir.join(lib);
+
+                // Missing libraries are only warned about on load
+                if let Ok(contents) = std::fs::read(filesystem_path(&lib)) {
+                    contents.hash(state);
+                }
+
+                if let Ok((materials, _)) = tobj::load_mtl(filesystem_path(&lib)) {
+                    let lib_dir = lib.parent().unwrap_or_else(|| Path::new(""));
+                    for mat in &materials {
+                        for texture in material_textures(lib_dir, mat).values() {
+                            ImageSource::File(texture.clone()).read()?.hash(state);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn is_obj_path(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("obj"))
+}
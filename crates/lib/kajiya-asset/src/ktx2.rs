@@ -0,0 +1,481 @@
+use std::io::Read as _;
+
+use kajiya_backend::ash::vk;
+
+use crate::mesh::GpuImage;
+
+const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+
+// Identifier, format, type size, width, height, depth, layers, faces, levels, supercompression
+const HEADER_SIZE: usize = 12 + 9 * 4;
+// DFD, KVD, and SGD offsets and sizes
+const INDEX_SIZE: usize = 4 * 4 + 2 * 8;
+// Byte offset, byte length, and uncompressed byte length
+const LEVEL_INDEX_ENTRY_SIZE: usize = 3 * 8;
+
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+const SUPERCOMPRESSION_ZLIB: u32 = 3;
+
+// Sample qualifiers of the Khronos Data Format
+const SAMPLE_SIGNED: u8 = 0x40;
+const SAMPLE_FLOAT: u8 = 0x80;
+const SAMPLE_LINEAR: u8 = 0x10;
+
+const F32_MINUS_ONE: u32 = 0xbf80_0000;
+const F32_ONE: u32 = 0x3f80_0000;
+
+struct Sample {
+    bit_offset: u16,
+    bit_length: u8,
+    // Channel id, and the sample qualifiers
+    channel_type: u8,
+    lower: u32,
+    upper: u32,
+}
+
+/// What's needed to describe one of the formats `GpuImage` can hold.
+struct FormatInfo {
+    // Bytes per texel, or per 4x4 block of compressed formats
+    block_bytes: u32,
+    compressed: bool,
+    // Size of the data type, for endianness conversion
+    type_size: u32,
+    // The `KHR_DF_MODEL_*` color model
+    color_model: u8,
+    srgb: bool,
+    samples: Vec<Sample>,
+}
+
+fn uncompressed_samples(bits: u8, float: bool) -> Vec<Sample> {
+    // R, G, B, A
+    [0u8, 1, 2, 15]
+        .iter()
+        .enumerate()
+        .map(|(i, &channel)| {
+            if float {
+                Sample {
+                    bit_offset: i as u16 * bits as u16,
+                    bit_length: bits,
+                    channel_type: channel | SAMPLE_FLOAT | SAMPLE_SIGNED,
+                    lower: F32_MINUS_ONE,
+                    upper: F32_ONE,
+                }
+            } else {
+                Sample {
+                    bit_offset: i as u16 * bits as u16,
+                    bit_length: bits,
+                    channel_type: channel,
+                    lower: 0,
+                    upper: (1u32 << bits) - 1,
+                }
+            }
+        })
+        .collect()
+}
+
+fn block_sample(bit_offset: u16, bit_length: u8, channel: u8, signed: bool) -> Sample {
+    Sample {
+        bit_offset,
+        bit_length,
+        channel_type: channel | if signed { SAMPLE_SIGNED } else { 0 },
+        lower: if signed { 0x8000_0000 } else { 0 },
+        upper: if signed { 0x7fff_ffff } else { u32::MAX },
+    }
+}
+
+fn format_info(format: vk::Format) -> Option<FormatInfo> {
+    let uncompressed = |block_bytes: u32, type_size: u32, srgb: bool, samples: Vec<Sample>| {
+        FormatInfo {
+            block_bytes,
+            compressed: false,
+            type_size,
+            // RGBSDA
+            color_model: 1,
+            srgb,
+            samples,
+        }
+    };
+
+    let block = |block_bytes: u32, color_model: u8, srgb: bool, samples: Vec<Sample>| FormatInfo {
+        block_bytes,
+        compressed: true,
+        type_size: 1,
+        color_model,
+        srgb,
+        samples,
+    };
+
+    let srgb_rgba8 = || {
+        let mut samples = uncompressed_samples(8, false);
+        samples[3].channel_type |= SAMPLE_LINEAR;
+        samples
+    };
+
+    let bc6h = |signed: bool| Sample {
+        bit_offset: 0,
+        bit_length: 128,
+        channel_type: SAMPLE_FLOAT | if signed { SAMPLE_SIGNED } else { 0 },
+        lower: if signed { F32_MINUS_ONE } else { 0 },
+        upper: F32_ONE,
+    };
+
+    Some(match format {
+        vk::Format::R8G8B8A8_UNORM => uncompressed(4, 1, false, uncompressed_samples(8, false)),
+        vk::Format::R8G8B8A8_SRGB => uncompressed(4, 1, true, srgb_rgba8()),
+        vk::Format::R16G16B16A16_SFLOAT => {
+            uncompressed(8, 2, false, uncompressed_samples(16, true))
+        }
+        vk::Format::R32G32B32A32_SFLOAT => {
+            uncompressed(16, 4, false, uncompressed_samples(32, true))
+        }
+        vk::Format::BC1_RGB_UNORM_BLOCK => {
+            block(8, 128, false, vec![block_sample(0, 64, 0, false)])
+        }
+        vk::Format::BC1_RGB_SRGB_BLOCK => block(8, 128, true, vec![block_sample(0, 64, 0, false)]),
+        vk::Format::BC1_RGBA_UNORM_BLOCK => {
+            block(8, 128, false, vec![block_sample(0, 64, 1, false)])
+        }
+        vk::Format::BC1_RGBA_SRGB_BLOCK => block(8, 128, true, vec![block_sample(0, 64, 1, false)]),
+        vk::Format::BC2_UNORM_BLOCK | vk::Format::BC2_SRGB_BLOCK => block(
+            16,
+            129,
+            format == vk::Format::BC2_SRGB_BLOCK,
+            vec![
+                block_sample(0, 64, 15, false),
+                block_sample(64, 64, 0, false),
+            ],
+        ),
+        vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK => block(
+            16,
+            130,
+            format == vk::Format::BC3_SRGB_BLOCK,
+            vec![
+                block_sample(0, 64, 15, false),
+                block_sample(64, 64, 0, false),
+            ],
+        ),
+        vk::Format::BC4_UNORM_BLOCK => block(8, 131, false, vec![block_sample(0, 64, 0, false)]),
+        vk::Format::BC4_SNORM_BLOCK => block(8, 131, false, vec![block_sample(0, 64, 0, true)]),
+        vk::Format::BC5_UNORM_BLOCK | vk::Format::BC5_SNORM_BLOCK => {
+            let signed = format == vk::Format::BC5_SNORM_BLOCK;
+            block(
+                16,
+                132,
+                false,
+                vec![
+                    block_sample(0, 64, 0, signed),
+                    block_sample(64, 64, 1, signed),
+                ],
+            )
+        }
+        vk::Format::BC6H_UFLOAT_BLOCK => block(16, 133, false, vec![bc6h(false)]),
+        vk::Format::BC6H_SFLOAT_BLOCK => block(16, 133, false, vec![bc6h(true)]),
+        vk::Format::BC7_UNORM_BLOCK => block(16, 134, false, vec![block_sample(0, 128, 0, false)]),
+        vk::Format::BC7_SRGB_BLOCK => block(16, 134, true, vec![block_sample(0, 128, 0, false)]),
+        _ => return None,
+    })
+}
+
+/// Builds the basic Data Format Descriptor KTX2 requires, including its total size.
+fn data_format_descriptor(info: &FormatInfo) -> Vec<u8> {
+    let block_size = 24 + 16 * info.samples.len() as u32;
+    let block_dimension = if info.compressed { 3 } else { 0 };
+    // BT.709 primaries; linear or sRGB transfer function
+    let primaries = 1u32;
+    let transfer = if info.srgb { 2u32 } else { 1 };
+
+    let mut words: Vec<u32> = vec![
+        4 + block_size,
+        // Khronos vendor id, basic descriptor type
+        0,
+        // Version 1.3
+        2 | (block_size << 16),
+        info.color_model as u32 | (primaries << 8) | (transfer << 16),
+        block_dimension | (block_dimension << 8),
+        info.block_bytes,
+        0,
+    ];
+
+    for sample in &info.samples {
+        words.push(
+            sample.bit_offset as u32
+                | ((sample.bit_length as u32 - 1) << 16)
+                | ((sample.channel_type as u32) << 24),
+        );
+        // Sample position
+        words.push(0);
+        words.push(sample.lower);
+        words.push(sample.upper);
+    }
+
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Size of one subresource of a mip level, or `None` if it doesn't fit a `usize`.
+fn level_size(info: &FormatInfo, extent: [u32; 2], level: usize) -> Option<usize> {
+    let level_extent = |x: u32| (x >> level).max(1) as usize;
+    let (width, height) = (level_extent(extent[0]), level_extent(extent[1]));
+
+    let blocks = if info.compressed {
+        ((width + 3) / 4).checked_mul((height + 3) / 4)?
+    } else {
+        width.checked_mul(height)?
+    };
+
+    blocks.checked_mul(info.block_bytes as usize)
+}
+
+fn align_up(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+pub fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(&IDENTIFIER)
+}
+
+/// Writes a baked image as a KTX2 file, for inspection with standard tools.
+pub fn write_ktx2(image: &GpuImage::Proto, writer: &mut impl std::io::Write) -> anyhow::Result<()> {
+    let info = format_info(image.format)
+        .ok_or_else(|| anyhow::anyhow!("Format {:?} can't be written as KTX2", image.format))?;
+
+    let subresource_count = image.array_layers.max(1) as usize;
+    let level_count = image.mips.len() / subresource_count;
+    let face_count = if image.cube { 6 } else { 1 };
+    let layer_count = subresource_count as u32 / face_count;
+
+    let dfd = data_format_descriptor(&info);
+
+    // Key/value pairs are a length, and a NUL-terminated key and value, padded to 4 bytes
+    let writer_kv = b"KTXwriter\0kajiya\0";
+    let mut kvd = (writer_kv.len() as u32).to_le_bytes().to_vec();
+    kvd.extend_from_slice(writer_kv);
+    kvd.resize(align_up(kvd.len(), 4), 0);
+
+    let dfd_offset = HEADER_SIZE + INDEX_SIZE + level_count * LEVEL_INDEX_ENTRY_SIZE;
+    let kvd_offset = dfd_offset + dfd.len();
+
+    // Levels are stored smallest first, each aligned to a whole texel block and to 4 bytes
+    let level_alignment = align_up(info.block_bytes as usize, 4);
+    let mut level_ranges = vec![(0usize, 0usize); level_count];
+    let mut offset = kvd_offset + kvd.len();
+    for level in (0..level_count).rev() {
+        offset = align_up(offset, level_alignment);
+
+        // All the array layers and cube faces of a level are stored together
+        let len: usize = (0..subresource_count)
+            .map(|subresource| image.mips[subresource * level_count + level].len())
+            .sum();
+
+        level_ranges[level] = (offset, len);
+        offset += len;
+    }
+
+    let mut out = Vec::with_capacity(offset);
+    out.extend_from_slice(&IDENTIFIER);
+    for value in [
+        image.format.as_raw() as u32,
+        info.type_size,
+        image.extent[0],
+        image.extent[1],
+        // Depth, only for volume textures
+        0,
+        // Zero for non-array textures
+        if layer_count > 1 { layer_count } else { 0 },
+        face_count,
+        level_count as u32,
+        SUPERCOMPRESSION_NONE,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for value in [
+        dfd_offset as u32,
+        dfd.len() as u32,
+        kvd_offset as u32,
+        kvd.len() as u32,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // No supercompression global data
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+
+    for &(offset, len) in &level_ranges {
+        out.extend_from_slice(&(offset as u64).to_le_bytes());
+        out.extend_from_slice(&(len as u64).to_le_bytes());
+        out.extend_from_slice(&(len as u64).to_le_bytes());
+    }
+
+    out.extend_from_slice(&dfd);
+    out.extend_from_slice(&kvd);
+
+    for level in (0..level_count).rev() {
+        out.resize(level_ranges[level].0, 0);
+        for subresource in 0..subresource_count {
+            out.extend_from_slice(&image.mips[subresource * level_count + level]);
+        }
+    }
+
+    writer.write_all(&out)?;
+    Ok(())
+}
+
+/// Reads a KTX2 file in one of the formats `GpuImage` supports.
+///
+/// Zstandard and zlib supercompression are supported; Basis Universal textures aren't.
+pub fn read_ktx2(bytes: &[u8]) -> anyhow::Result<GpuImage::Proto> {
+    if !is_ktx2(bytes) {
+        anyhow::bail!("Not a KTX2 file");
+    }
+
+    let read_u32 = |offset: usize| -> anyhow::Result<u32> {
+        let word = bytes
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow::anyhow!("Truncated KTX2 file"))?;
+        Ok(u32::from_le_bytes(word.try_into().unwrap()))
+    };
+
+    let read_u64 = |offset: usize| -> anyhow::Result<u64> {
+        let word = bytes
+            .get(offset..offset + 8)
+            .ok_or_else(|| anyhow::anyhow!("Truncated KTX2 file"))?;
+        Ok(u64::from_le_bytes(word.try_into().unwrap()))
+    };
+
+    let vk_format = read_u32(12)?;
+    let width = read_u32(20)?;
+    let height = read_u32(24)?.max(1);
+    let depth = read_u32(28)?;
+    let layer_count = read_u32(32)?.max(1);
+    let face_count = read_u32(36)?;
+    // Zero asks the loader to generate mips; we just use the first level
+    let level_count = read_u32(40)?.max(1) as usize;
+    let supercompression = read_u32(44)?;
+
+    if vk_format == 0 {
+        anyhow::bail!("Basis Universal KTX2 textures are not supported; transcode them to BCn");
+    }
+
+    let format = vk::Format::from_raw(vk_format as i32);
+    let info = format_info(format)
+        .ok_or_else(|| anyhow::anyhow!("KTX2 format {:?} not supported", format))?;
+
+    if width == 0 {
+        anyhow::bail!("Invalid KTX2 width: 0");
+    }
+
+    if depth > 1 {
+        anyhow::bail!("Volume KTX2 textures are not supported");
+    }
+
+    if face_count != 1 && face_count != 6 {
+        anyhow::bail!("Invalid KTX2 face count: {}", face_count);
+    }
+
+    // The full mip chain ends at 1x1
+    let max_level_count = 32 - width.max(height).leading_zeros() as usize;
+    if level_count > max_level_count {
+        anyhow::bail!(
+            "Invalid KTX2 level count: {} for {}x{}",
+            level_count,
+            width,
+            height
+        );
+    }
+
+    // The header is untrusted; sizes derived from it are validated against the format
+    // and extent, rather than being used to allocate memory up front.
+    let subresource_count = layer_count
+        .checked_mul(face_count)
+        .ok_or_else(|| anyhow::anyhow!("Invalid KTX2 layer count: {}", layer_count))?
+        as usize;
+
+    let mut mips = Vec::new();
+
+    for level in 0..level_count {
+        let entry = HEADER_SIZE + INDEX_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+        let offset = read_u64(entry)? as usize;
+        let len = read_u64(entry + 8)? as usize;
+        let uncompressed_len = read_u64(entry + 16)? as usize;
+
+        let expected_len = level_size(&info, [width, height], level)
+            .and_then(|size| size.checked_mul(subresource_count))
+            .filter(|&expected_len| expected_len == uncompressed_len)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Corrupt KTX2 file: level {} has {} bytes, which doesn't match a {}x{} {:?} image with {} layers and faces",
+                    level,
+                    uncompressed_len,
+                    width,
+                    height,
+                    format,
+                    subresource_count
+                )
+            })?;
+
+        let data = bytes
+            .get(offset..offset.saturating_add(len))
+            .ok_or_else(|| {
+                anyhow::anyhow!("Truncated KTX2 file: level {} is out of bounds", level)
+            })?;
+
+        let data = match supercompression {
+            SUPERCOMPRESSION_NONE => data.to_vec(),
+            SUPERCOMPRESSION_ZSTD => {
+                let mut source = data;
+                let decoder = ruzstd::StreamingDecoder::new(&mut source)
+                    .map_err(|err| anyhow::anyhow!("Invalid Zstandard data: {:?}", err))?;
+
+                // One byte over the limit is enough to notice the level is too large
+                let mut decoded = Vec::new();
+                decoder
+                    .take(expected_len as u64 + 1)
+                    .read_to_end(&mut decoded)?;
+                decoded
+            }
+            SUPERCOMPRESSION_ZLIB => {
+                miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, expected_len)
+                    .map_err(|err| anyhow::anyhow!("Invalid zlib data: {:?}", err))?
+            }
+            SUPERCOMPRESSION_BASIS_LZ => {
+                anyhow::bail!("BasisLZ supercompressed KTX2 textures are not supported")
+            }
+            _ => anyhow::bail!("Unknown KTX2 supercompression scheme {}", supercompression),
+        };
+
+        if data.len() != expected_len {
+            anyhow::bail!(
+                "Corrupt KTX2 file: level {} has {} bytes; expected {}",
+                level,
+                data.len(),
+                expected_len
+            );
+        }
+
+        // Only allocated once the data of a level confirms the subresource count
+        if mips.is_empty() {
+            mips = vec![Vec::new(); subresource_count * level_count];
+        }
+
+        // KTX2 stores levels of all subresources together; `GpuImage` all levels of each subresource
+        for (subresource, image) in data
+            .chunks_exact(data.len() / subresource_count)
+            .enumerate()
+        {
+            mips[subresource * level_count + level] = image.to_vec();
+        }
+    }
+
+    Ok(GpuImage::Proto {
+        format,
+        extent: [width, height, 1],
+        array_layers: subresource_count as u32,
+        cube: face_count == 6,
+        mips,
+    })
+}
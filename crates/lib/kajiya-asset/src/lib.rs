@@ -1,4 +1,10 @@
+pub mod animation;
+pub mod asset_header;
 pub mod image;
+pub mod ktx2;
 pub mod mesh;
+pub mod optimize;
+pub mod validate;
 
 mod import_gltf;
+mod import_obj;
@@ -1,9 +1,13 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
-use byteorder::{ByteOrder, NativeEndian, WriteBytesExt};
-use glam::{Mat4, Quat, Vec3, Vec4};
-use gltf::texture::TextureTransform;
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+use gltf::{
+    accessor::{DataType, Item, Iter as AccessorIter},
+    texture::TextureTransform,
+    Semantic,
+};
+use kajiya_backend::ash::vk;
 use kajiya_backend::bytes::into_byte_vec;
 /*use render_core::{
     constants::MAX_VERTEX_STREAMS,
@@ -17,13 +21,23 @@ use kajiya_backend::bytes::into_byte_vec;
 };*/
 use anyhow::Context as _;
 use std::{
+    collections::HashMap,
     hash::Hash,
     mem::size_of,
     path::{Path, PathBuf},
 };
 use turbosloth::*;
 
-use crate::image::ImageSource;
+pub use crate::import_obj::{hash_obj_sources, is_obj_path, LoadObjScene};
+
+use crate::{
+    animation::{
+        AnimationChannel, AnimationClip, AnimationInterpolation, AnimationProperty,
+        PackedAnimationChannel, PackedAnimationClip, SkeletonJoint, NO_PARENT_JOINT,
+    },
+    image::ImageSource,
+    import_gltf::RawJson,
+};
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum TexGamma {
@@ -34,8 +48,18 @@ pub enum TexGamma {
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum TexCompressionMode {
     None,
+    // BC7
     Rgba,
+    // BC5
     Rg,
+    // Opaque RGB at half the size of BC7
+    Bc1,
+    // RGB of BC1 quality, with a separate alpha channel
+    Bc3,
+    // Single channel
+    Bc4,
+    // Unsigned HDR RGB; always stored linear
+    Bc6h,
 }
 
 impl TexCompressionMode {
@@ -44,6 +68,64 @@ impl TexCompressionMode {
             TexCompressionMode::None => true,
             TexCompressionMode::Rgba => true,
             TexCompressionMode::Rg => false,
+            TexCompressionMode::Bc1 => false,
+            TexCompressionMode::Bc3 => true,
+            TexCompressionMode::Bc4 => false,
+            TexCompressionMode::Bc6h => false,
+        }
+    }
+}
+
+impl std::str::FromStr for TexCompressionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "bc1" => Ok(Self::Bc1),
+            "bc3" => Ok(Self::Bc3),
+            "bc4" => Ok(Self::Bc4),
+            "bc5" => Ok(Self::Rg),
+            "bc6h" => Ok(Self::Bc6h),
+            "bc7" => Ok(Self::Rgba),
+            _ => Err(anyhow::anyhow!(
+                "Unknown texture compression {:?}; expected none, bc1, bc3, bc4, bc5, bc6h, or bc7",
+                s
+            )),
+        }
+    }
+}
+
+/// Speed versus quality of the BC6H and BC7 encoders. The other formats have a single mode.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum TexCompressionQuality {
+    UltraFast,
+    VeryFast,
+    Fast,
+    Basic,
+    Slow,
+}
+
+impl Default for TexCompressionQuality {
+    fn default() -> Self {
+        Self::Basic
+    }
+}
+
+impl std::str::FromStr for TexCompressionQuality {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ultrafast" => Ok(Self::UltraFast),
+            "veryfast" => Ok(Self::VeryFast),
+            "fast" => Ok(Self::Fast),
+            "basic" => Ok(Self::Basic),
+            "slow" => Ok(Self::Slow),
+            _ => Err(anyhow::anyhow!(
+                "Unknown texture compression quality {:?}; expected ultrafast, veryfast, fast, basic, or slow",
+                s
+            )),
         }
     }
 }
@@ -53,7 +135,59 @@ pub struct TexParams {
     pub gamma: TexGamma,
     pub use_mips: bool,
     pub compression: TexCompressionMode,
+    pub compression_quality: TexCompressionQuality,
     pub channel_swizzle: Option<[usize; 4]>,
+    // Tangent-space normals in RGB; renormalized after downsampling
+    pub normal_map: bool,
+    // Larger textures are downscaled to fit, preserving the aspect ratio. `None` keeps the full resolution.
+    pub max_size: Option<u32>,
+}
+
+pub const DEFAULT_MAX_TEXTURE_SIZE: u32 = 2048;
+
+/// Bake-time overrides of the `TexParams` picked by the importer
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct TexBakeSettings {
+    // When false, no mips are generated, even for textures which would normally get them
+    pub use_mips: bool,
+    // When false, textures are stored uncompressed
+    pub compress: bool,
+    pub compression_quality: TexCompressionQuality,
+    // Per material map slot, in the order of `MeshMaterial::maps`: normal, spec, albedo, emissive
+    pub slot_compression: [Option<TexCompressionMode>; 4],
+    // See `TexParams::max_size`
+    pub max_size: Option<u32>,
+}
+
+impl Default for TexBakeSettings {
+    fn default() -> Self {
+        Self {
+            use_mips: true,
+            compress: true,
+            compression_quality: Default::default(),
+            slot_compression: [None; 4],
+            max_size: Some(DEFAULT_MAX_TEXTURE_SIZE),
+        }
+    }
+}
+
+impl TexBakeSettings {
+    /// `slot` is the index in `MeshMaterial::maps` the texture is used at, if any.
+    pub fn apply(&self, mut params: TexParams, slot: Option<usize>) -> TexParams {
+        params.use_mips &= self.use_mips;
+        params.compression_quality = self.compression_quality;
+        params.max_size = self.max_size;
+
+        if let Some(compression) = slot.and_then(|slot| self.slot_compression[slot]) {
+            params.compression = compression;
+        }
+
+        if !self.compress {
+            params.compression = TexCompressionMode::None;
+        }
+
+        params
+    }
 }
 
 #[derive(Clone, Hash, PartialEq, Eq)]
@@ -61,6 +195,8 @@ pub enum MeshMaterialMap {
     Image {
         source: ImageSource,
         params: TexParams,
+        // Index of the UV set the map is sampled with
+        uv_set: u32,
     },
     Placeholder([u8; 4]),
 }
@@ -68,18 +204,40 @@ pub enum MeshMaterialMap {
 pub struct MeshMaterialFlags;
 impl MeshMaterialFlags {
     pub const MESH_MATERIAL_FLAG_EMISSIVE_USED_AS_LIGHT: u32 = 1;
+    // Alpha-tested against `MeshMaterial::alpha_cutoff`
+    pub const MESH_MATERIAL_FLAG_ALPHA_MASK: u32 = 2;
+    // Blending is not supported by the deferred passes; alpha-tested at the default cutoff instead
+    pub const MESH_MATERIAL_FLAG_ALPHA_BLEND: u32 = 4;
+    pub const MESH_MATERIAL_FLAG_DOUBLE_SIDED: u32 = 8;
+
+    pub const MESH_MATERIAL_FLAGS_ALPHA_TESTED: u32 =
+        Self::MESH_MATERIAL_FLAG_ALPHA_MASK | Self::MESH_MATERIAL_FLAG_ALPHA_BLEND;
 }
 
-#[derive(Clone, Copy)]
+/// Increment when changing the layout of `MeshMaterial`, which is baked into meshes as-is.
+pub const MESH_MATERIAL_LAYOUT_VERSION: u32 = 2;
+
+#[derive(Clone, Copy, bytecheck::CheckBytes)]
 #[repr(C)]
 pub struct MeshMaterial {
     pub base_color_mult: [f32; 4],
     pub maps: [u32; 4],
     pub roughness_mult: f32,
     pub metalness_factor: f32,
+    // Radiance; includes `KHR_materials_emissive_strength`
     pub emissive: [f32; 3],
     pub flags: u32,
+    // UV transforms and UV set indices, in the order: albedo, normal, spec, emissive
     pub map_transforms: [[f32; 6]; 4],
+    pub map_uv_sets: [u32; 4],
+    pub alpha_cutoff: f32,
+    // Parameters of the KHR PBR extensions; textures aren't imported
+    pub transmission: f32,
+    pub ior: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub sheen_color: [f32; 3],
+    pub sheen_roughness: f32,
 }
 
 #[derive(Clone, Default)]
@@ -88,12 +246,53 @@ pub struct TriangleMesh {
     pub normals: Vec<[f32; 3]>,
     pub colors: Vec<[f32; 4]>,
     pub uvs: Vec<[f32; 2]>,
+    // Second UV set; empty unless the mesh has primitives with `TEXCOORD_1`
+    pub uvs1: Vec<[f32; 2]>,
     pub tangents: Vec<[f32; 4]>,
     pub material_ids: Vec<u32>, // per index, but can be flat shaded
     pub indices: Vec<u32>,
     pub materials: Vec<MeshMaterial>, // global
     pub maps: Vec<MeshMaterialMap>,   // global
     pub images: Vec<ImageSource>,
+
+    // Skinning data; empty unless the mesh has skinned primitives
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub skeleton: Vec<SkeletonJoint>,
+    pub animations: Vec<AnimationClip>,
+
+    // Simplified versions of `indices`, coarsest last; empty unless generated when baking
+    pub lods: Vec<TriangleMeshLod>,
+
+    pub tangent_stats: TangentStats,
+}
+
+/// A simplified version of a mesh, sharing its vertices.
+#[derive(Clone, Default)]
+pub struct TriangleMeshLod {
+    pub indices: Vec<u32>,
+    // How far the simplified surface strays from the full one, in the units of the mesh
+    pub error: f32,
+}
+
+/// How the tangents of a `TriangleMesh` were obtained, for bake reports.
+/// Vertices whose tangents came from the source file aren't counted.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TangentStats {
+    // Generated with mikktspace
+    pub generated_from_uvs: usize,
+    // Arbitrary basis around the normal, for primitives without UVs
+    pub synthesized_without_uvs: usize,
+    // Triangles whose UVs don't span an area, leaving the tangents undefined
+    pub degenerate_uv_triangles: usize,
+}
+
+impl std::ops::AddAssign for TangentStats {
+    fn add_assign(&mut self, other: Self) {
+        self.generated_from_uvs += other.generated_from_uvs;
+        self.synthesized_without_uvs += other.synthesized_without_uvs;
+        self.degenerate_uv_triangles += other.degenerate_uv_triangles;
+    }
 }
 
 fn iter_gltf_node_tree<F: FnMut(&gltf::scene::Node, Mat4)>(
@@ -117,43 +316,128 @@ fn get_gltf_texture_source(tex: gltf::texture::Texture) -> Option<String> {
     }
 }
 
+pub(crate) const DEFAULT_MAP_TRANSFORM: [f32; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+// Only `TEXCOORD_0` and `TEXCOORD_1` are imported
+const MAX_UV_SETS: u32 = 2;
+
+fn uv_transform_to_matrix(offset: [f32; 2], rotation: f32, scale: [f32; 2]) -> [f32; 6] {
+    let r = rotation;
+    let s = scale;
+    let o = offset;
+
+    [
+        r.cos() * s[0],
+        r.sin() * s[1],
+        -r.sin() * s[0],
+        r.cos() * s[1],
+        o[0],
+        o[1],
+    ]
+}
+
+fn validate_uv_set(uv_set: u32) -> u32 {
+    if uv_set < MAX_UV_SETS {
+        uv_set
+    } else {
+        log::warn!(
+            "Texture uses TEXCOORD_{}, but only {} UV sets are supported; using TEXCOORD_0",
+            uv_set,
+            MAX_UV_SETS
+        );
+        0
+    }
+}
+
+// Returns the UV transform and UV set of a texture reference, honoring `KHR_texture_transform`
+fn texture_info_uvs(tex: &gltf::texture::Info) -> ([f32; 6], u32) {
+    let xform = tex.texture_transform();
+    let uv_set = xform
+        .as_ref()
+        .and_then(TextureTransform::tex_coord)
+        .unwrap_or_else(|| tex.tex_coord());
+    let matrix = xform.map_or(DEFAULT_MAP_TRANSFORM, |xform| {
+        uv_transform_to_matrix(xform.offset(), xform.rotation(), xform.scale())
+    });
+
+    (matrix, validate_uv_set(uv_set))
+}
+
+// Material extensions which the `gltf` crate doesn't expose are read from the JSON
+fn material_extension<'a>(
+    raw_material: Option<&'a gltf::json::Value>,
+    name: &str,
+) -> Option<&'a gltf::json::Value> {
+    raw_material?.get("extensions")?.get(name)
+}
+
+fn json_f32(value: Option<&gltf::json::Value>, key: &str, default: f32) -> f32 {
+    value
+        .and_then(|value| value.get(key)?.as_f64())
+        .map_or(default, |v| v as f32)
+}
+
+// The `gltf` crate doesn't expose `KHR_texture_transform` on normal textures,
+// so it's read from the material's JSON instead.
+fn normal_texture_uvs(
+    tex: &gltf::material::NormalTexture,
+    raw_material: Option<&gltf::json::Value>,
+) -> ([f32; 6], u32) {
+    let xform = raw_material.and_then(|mat| {
+        mat.get("normalTexture")?
+            .get("extensions")?
+            .get("KHR_texture_transform")
+    });
+
+    let xform = if let Some(xform) = xform {
+        xform
+    } else {
+        return (DEFAULT_MAP_TRANSFORM, validate_uv_set(tex.tex_coord()));
+    };
+
+    let vec2 = |name: &str, default: [f32; 2]| -> [f32; 2] {
+        xform
+            .get(name)
+            .and_then(|v| Some([v.get(0)?.as_f64()? as f32, v.get(1)?.as_f64()? as f32]))
+            .unwrap_or(default)
+    };
+
+    let offset = vec2("offset", [0.0, 0.0]);
+    let scale = vec2("scale", [1.0, 1.0]);
+    let rotation = xform
+        .get("rotation")
+        .and_then(|v| v.as_f64())
+        .unwrap_or(0.0) as f32;
+    let uv_set = xform
+        .get("texCoord")
+        .and_then(|v| v.as_u64())
+        .map_or_else(|| tex.tex_coord(), |v| v as u32);
+
+    (
+        uv_transform_to_matrix(offset, rotation, scale),
+        validate_uv_set(uv_set),
+    )
+}
+
 fn load_gltf_material(
     mat: &gltf::material::Material,
+    raw_json: &RawJson,
     document_images: &[ImageSource],
 ) -> (Vec<MeshMaterialMap>, MeshMaterial) {
-    const DEFAULT_MAP_TRANSFORM: [f32; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
     let mut map_transforms: [[f32; 6]; 4] = [DEFAULT_MAP_TRANSFORM; 4];
+    let mut map_uv_sets: [u32; 4] = [0; 4];
 
-    fn texture_transform_to_matrix(xform: Option<TextureTransform>) -> [f32; 6] {
-        if let Some(xform) = xform {
-            let r = xform.rotation();
-            let s = xform.scale();
-            let o = xform.offset();
-
-            [
-                r.cos() * s[0],
-                r.sin() * s[1],
-                -r.sin() * s[0],
-                r.cos() * s[1],
-                o[0],
-                o[1],
-            ]
-        } else {
-            DEFAULT_MAP_TRANSFORM
-        }
-    }
-
-    let (albedo_map, albedo_map_transform) = mat
+    let (albedo_map, (albedo_map_transform, albedo_uv_set)) = mat
         .pbr_metallic_roughness()
         .base_color_texture()
         .or_else(|| mat.pbr_specular_glossiness()?.diffuse_texture())
         .map_or(
             (
                 MeshMaterialMap::Placeholder([255, 255, 255, 255]),
-                DEFAULT_MAP_TRANSFORM,
+                (DEFAULT_MAP_TRANSFORM, 0),
             ),
             |tex| {
-                let transform = texture_transform_to_matrix(tex.texture_transform());
+                let uvs = texture_info_uvs(&tex);
 
                 (
                     MeshMaterialMap::Image {
@@ -162,32 +446,44 @@ fn load_gltf_material(
                             gamma: TexGamma::Srgb,
                             use_mips: true,
                             compression: TexCompressionMode::Rgba,
+                            compression_quality: Default::default(),
                             channel_swizzle: None,
+                            normal_map: false,
+                            max_size: None,
                         },
+                        uv_set: uvs.1,
                     },
-                    transform,
+                    uvs,
                 )
             },
         );
 
     map_transforms[0] = albedo_map_transform;
+    map_uv_sets[0] = albedo_uv_set;
 
-    // TODO: add texture transform to the normal map in the `gltf` crate
-    let normal_map =
-        mat.normal_texture()
-            .map_or(MeshMaterialMap::Placeholder([127, 127, 255, 255]), |tex| {
-                MeshMaterialMap::Image {
-                    source: document_images[tex.texture().source().index()].clone(),
-                    params: TexParams {
-                        gamma: TexGamma::Linear,
-                        use_mips: true,
-                        compression: TexCompressionMode::Rg,
-                        channel_swizzle: None,
-                    },
-                }
-            });
+    let raw_material = mat.index().and_then(|idx| raw_json.material(idx));
+
+    let mut normal_map = MeshMaterialMap::Placeholder([127, 127, 255, 255]);
+    if let Some(tex) = mat.normal_texture() {
+        let (transform, uv_set) = normal_texture_uvs(&tex, raw_material);
+        map_transforms[1] = transform;
+        map_uv_sets[1] = uv_set;
+        normal_map = MeshMaterialMap::Image {
+            source: document_images[tex.texture().source().index()].clone(),
+            params: TexParams {
+                gamma: TexGamma::Linear,
+                use_mips: true,
+                compression: TexCompressionMode::Rg,
+                compression_quality: Default::default(),
+                channel_swizzle: None,
+                normal_map: true,
+                max_size: None,
+            },
+            uv_set,
+        }
+    }
 
-    let (spec_map, spec_map_transform) = mat
+    let (spec_map, (spec_map_transform, spec_uv_set)) = mat
         .pbr_metallic_roughness()
         .metallic_roughness_texture()
         .map_or_else(
@@ -196,10 +492,12 @@ fn load_gltf_material(
                 let metalness = 255;
                 (
                     MeshMaterialMap::Placeholder([roughness, metalness, 127, 255]),
-                    DEFAULT_MAP_TRANSFORM,
+                    (DEFAULT_MAP_TRANSFORM, 0),
                 )
             },
             |tex| {
+                let uvs = texture_info_uvs(&tex);
+
                 (
                     MeshMaterialMap::Image {
                         source: document_images[tex.texture().source().index()].clone(),
@@ -207,37 +505,106 @@ fn load_gltf_material(
                             gamma: TexGamma::Linear,
                             use_mips: true,
                             compression: TexCompressionMode::Rg,
+                            compression_quality: Default::default(),
                             channel_swizzle: Some([1, 2, 0, 3]),
+                            normal_map: false,
+                            max_size: None,
                         },
+                        uv_set: uvs.1,
                     },
-                    texture_transform_to_matrix(tex.texture_transform()),
+                    uvs,
                 )
             },
         );
 
     map_transforms[2] = spec_map_transform;
+    map_uv_sets[2] = spec_uv_set;
 
     let mut emissive_map = MeshMaterialMap::Placeholder([255, 255, 255, 255]);
     if let Some(tex) = mat.emissive_texture() {
-        map_transforms[3] = texture_transform_to_matrix(tex.texture_transform());
+        let (transform, uv_set) = texture_info_uvs(&tex);
+        map_transforms[3] = transform;
+        map_uv_sets[3] = uv_set;
         emissive_map = MeshMaterialMap::Image {
             source: document_images[tex.texture().source().index()].clone(),
             params: TexParams {
                 gamma: TexGamma::Srgb,
                 use_mips: true,
                 compression: TexCompressionMode::Rgba,
+                compression_quality: Default::default(),
                 channel_swizzle: None,
+                normal_map: false,
+                max_size: None,
             },
+            uv_set,
         }
     }
 
-    let emissive = mat.emissive_factor();
+    let emissive_strength = json_f32(
+        material_extension(raw_material, "KHR_materials_emissive_strength"),
+        "emissiveStrength",
+        1.0,
+    );
+    let emissive = mat.emissive_factor().map(|v| v * emissive_strength);
+
+    let transmission = mat
+        .transmission()
+        .map_or(0.0, |transmission| transmission.transmission_factor());
+    let ior = mat.ior().unwrap_or(1.5);
+
+    let clearcoat_ext = material_extension(raw_material, "KHR_materials_clearcoat");
+    let clearcoat = json_f32(clearcoat_ext, "clearcoatFactor", 0.0);
+    let clearcoat_roughness = json_f32(clearcoat_ext, "clearcoatRoughnessFactor", 0.0);
+
+    let sheen_ext = material_extension(raw_material, "KHR_materials_sheen");
+    let sheen_color = sheen_ext
+        .and_then(|sheen| {
+            let color = sheen.get("sheenColorFactor")?;
+            Some([
+                color.get(0)?.as_f64()? as f32,
+                color.get(1)?.as_f64()? as f32,
+                color.get(2)?.as_f64()? as f32,
+            ])
+        })
+        .unwrap_or([0.0; 3]);
+    let sheen_roughness = json_f32(sheen_ext, "sheenRoughnessFactor", 0.0);
+
+    let has_extension_textures = [
+        (clearcoat_ext, "clearcoatTexture"),
+        (clearcoat_ext, "clearcoatRoughnessTexture"),
+        (clearcoat_ext, "clearcoatNormalTexture"),
+        (sheen_ext, "sheenColorTexture"),
+        (sheen_ext, "sheenRoughnessTexture"),
+    ]
+    .iter()
+    .any(|(ext, tex)| ext.and_then(|ext| ext.get(tex)).is_some())
+        || mat
+            .transmission()
+            .and_then(|transmission| transmission.transmission_texture())
+            .is_some();
+
+    if has_extension_textures {
+        log::warn!(
+            "Material {:?} uses textures of PBR extensions, which are not supported; using the factors only",
+            mat.name().unwrap_or_default()
+        );
+    }
 
     let base_color_mult = mat.pbr_metallic_roughness().base_color_factor();
     let roughness_mult = mat.pbr_metallic_roughness().roughness_factor();
     let metalness_factor = mat.pbr_metallic_roughness().metallic_factor();
 
-    //mata.normal_texture().and_then(|tex| tex.transform())
+    let mut flags = match mat.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => 0,
+        gltf::material::AlphaMode::Mask => MeshMaterialFlags::MESH_MATERIAL_FLAG_ALPHA_MASK,
+        gltf::material::AlphaMode::Blend => MeshMaterialFlags::MESH_MATERIAL_FLAG_ALPHA_BLEND,
+    };
+
+    if mat.double_sided() {
+        flags |= MeshMaterialFlags::MESH_MATERIAL_FLAG_DOUBLE_SIDED;
+    }
+
+    let alpha_cutoff = mat.alpha_cutoff().unwrap_or(0.5);
 
     (
         vec![normal_map, spec_map, albedo_map, emissive_map],
@@ -247,461 +614,1105 @@ fn load_gltf_material(
             roughness_mult,
             metalness_factor,
             emissive,
-            flags: 0,
+            flags,
             map_transforms,
+            map_uv_sets,
+            alpha_cutoff,
+            transmission,
+            ior,
+            clearcoat,
+            clearcoat_roughness,
+            sheen_color,
+            sheen_roughness,
         },
     )
 }
 
-#[derive(Clone)]
-pub struct LoadGltfScene {
-    pub path: PathBuf,
-    pub scale: f32,
-    pub rotation: Quat,
+struct GltfNodeGraph {
+    root_xform: Mat4,
+    parents: HashMap<usize, usize>,
+    xforms: HashMap<usize, Mat4>,
+    // Depth-first visitation order; parents always come before children
+    dfs_order: HashMap<usize, usize>,
 }
 
-impl Hash for LoadGltfScene {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.path.hash(state);
-        self.scale.to_ne_bytes().hash(state);
-        self.rotation.x.to_ne_bytes().hash(state);
-        self.rotation.y.to_ne_bytes().hash(state);
-        self.rotation.z.to_ne_bytes().hash(state);
-        self.rotation.w.to_ne_bytes().hash(state);
+impl GltfNodeGraph {
+    fn new(scene: &gltf::Scene, root_xform: Mat4) -> Self {
+        let mut res = Self {
+            root_xform,
+            parents: Default::default(),
+            xforms: Default::default(),
+            dfs_order: Default::default(),
+        };
+
+        for node in scene.nodes() {
+            iter_gltf_node_tree(
+                &node,
+                root_xform,
+                &mut |node: &gltf::scene::Node, xform: Mat4| {
+                    for child in node.children() {
+                        res.parents.insert(child.index(), node.index());
+                    }
+
+                    res.xforms.insert(node.index(), xform);
+
+                    let order = res.dfs_order.len();
+                    res.dfs_order.insert(node.index(), order);
+                },
+            );
+        }
+
+        res
     }
 }
 
-#[async_trait]
-impl LazyWorker for LoadGltfScene {
-    type Output = anyhow::Result<TriangleMesh>;
+// Append the joints of `skin` to `skeleton`, and return the skeleton index of each skin joint.
+//
+// Vertices of skinned meshes are baked with `mesh_xform` applied, just like static ones,
+// so its inverse is folded into the inverse bind matrices.
+fn load_gltf_skin(
+    skin: &gltf::Skin,
+    mesh_xform: Mat4,
+    node_graph: &GltfNodeGraph,
+    buffers: &[bytes::Bytes],
+    skeleton: &mut Vec<SkeletonJoint>,
+    joints_by_node: &mut HashMap<usize, Vec<u32>>,
+) -> Vec<u32> {
+    let joint_nodes: Vec<gltf::Node> = skin.joints().collect();
+
+    let inverse_binds: Vec<Mat4> = skin
+        .reader(|buffer| Some(&buffers[buffer.index()]))
+        .read_inverse_bind_matrices()
+        .map_or_else(
+            || vec![Mat4::IDENTITY; joint_nodes.len()],
+            |iter| iter.map(|m| Mat4::from_cols_array_2d(&m)).collect(),
+        );
 
-    async fn run(self, _ctx: RunContext) -> Self::Output {
-        let (gltf, buffers, imgs) = crate::import_gltf::import(&self.path)
-            .with_context(|| format!("Loading GLTF scene from {:?}", self.path))?;
+    let inv_mesh_xform = mesh_xform.inverse();
+
+    // Sort the joints parent-first, so that poses can be evaluated in one pass
+    let mut sorted: Vec<usize> = (0..joint_nodes.len()).collect();
+    sorted.sort_by_key(|&j| {
+        node_graph
+            .dfs_order
+            .get(&joint_nodes[j].index())
+            .copied()
+            .unwrap_or(usize::MAX)
+    });
+
+    let joint_base = skeleton.len() as u32;
+    let mut skin_joints = vec![0u32; joint_nodes.len()];
+    for (sorted_idx, &j) in sorted.iter().enumerate() {
+        skin_joints[j] = joint_base + sorted_idx as u32;
+    }
 
-        if let Some(scene) = gltf.default_scene().or_else(|| gltf.scenes().next()) {
-            let mut res: TriangleMesh = TriangleMesh::default();
+    let node_to_joint: HashMap<usize, u32> = joint_nodes
+        .iter()
+        .enumerate()
+        .map(|(j, node)| (node.index(), skin_joints[j]))
+        .collect();
 
-            let mut process_node = |node: &gltf::scene::Node, xform: Mat4| {
-                if let Some(mesh) = node.mesh() {
-                    let flip_winding_order = xform.determinant() < 0.0;
-
-                    for prim in mesh.primitives() {
-                        let reader = prim.reader(|buffer| Some(&buffers[buffer.index()]));
-
-                        let res_material_index = res.materials.len() as u32;
-
-                        {
-                            let (mut maps, mut material) =
-                                load_gltf_material(&prim.material(), imgs.as_slice());
-
-                            let map_base = res.maps.len() as u32;
-                            for id in material.maps.iter_mut() {
-                                *id += map_base;
-                            }
-
-                            res.materials.push(material);
-                            res.maps.append(&mut maps);
-                        }
-
-                        // Collect positions (required)
-                        let positions = if let Some(iter) = reader.read_positions() {
-                            iter.collect::<Vec<_>>()
-                        } else {
-                            return;
-                        };
-
-                        // Collect normals (required)
-                        let normals = if let Some(iter) = reader.read_normals() {
-                            iter.collect::<Vec<_>>()
-                        } else {
-                            return;
-                        };
-
-                        // Collect tangents (optional)
-                        let (mut tangents, tangents_found) =
-                            if let Some(iter) = reader.read_tangents() {
-                                (iter.collect::<Vec<_>>(), true)
-                            } else {
-                                (vec![[1.0, 0.0, 0.0, 0.0]; positions.len()], false)
-                            };
-
-                        // Collect uvs (optional)
-                        let (mut uvs, uvs_found) = if let Some(iter) = reader.read_tex_coords(0) {
-                            (iter.into_f32().collect::<Vec<_>>(), true)
-                        } else {
-                            (vec![[0.0, 0.0]; positions.len()], false)
-                        };
-
-                        // Collect colors (optional)
-                        let mut colors = if let Some(iter) = reader.read_colors(0) {
-                            iter.into_rgba_f32().collect::<Vec<_>>()
-                        } else {
-                            vec![[1.0, 1.0, 1.0, 1.0]; positions.len()]
-                        };
-
-                        // Collect material ids
-                        let mut material_ids = vec![res_material_index; positions.len()];
-
-                        // Collect indices
-                        let mut indices: Vec<u32>;
-                        {
-                            if let Some(indices_reader) = reader.read_indices() {
-                                indices = indices_reader.into_u32().collect();
-                            } else {
-                                if positions.is_empty() {
-                                    return;
-                                }
-
-                                match prim.mode() {
-                                    gltf::mesh::Mode::Triangles => {
-                                        indices = (0..positions.len() as u32).collect();
-                                    }
-                                    _ => {
-                                        panic!(
-                                            "Primitive mode {:?} not supported yet",
-                                            prim.mode()
-                                        );
-                                    }
-                                }
-                            }
-
-                            if flip_winding_order {
-                                for tri in indices.chunks_exact_mut(3) {
-                                    tri.swap(0, 2);
-                                }
-                            }
-                        }
-
-                        if !tangents_found && uvs_found {
-                            log::trace!(
-                                "Mesh had UVs but no tangents. Calculating the tangents..."
-                            );
-
-                            mikktspace::generate_tangents(&mut TangentCalcContext {
-                                indices: indices.as_slice(),
-                                positions: positions.as_slice(),
-                                normals: normals.as_slice(),
-                                uvs: uvs.as_slice(),
-                                tangents: tangents.as_mut_slice(),
-                            });
-                        }
-
-                        // --------------------------------------------------------
-                        // Write it all to the output
-
-                        {
-                            // log::info!("Loading a mesh with {} indices", indices.len());
-                            let base_index = res.positions.len() as u32;
-                            for i in &mut indices {
-                                *i += base_index;
-                            }
-
-                            res.indices.append(&mut indices);
-                            res.colors.append(&mut colors);
-                            res.material_ids.append(&mut material_ids);
-                        }
-
-                        for v in positions {
-                            let pos = (xform * Vec3::from(v).extend(1.0)).truncate();
-                            res.positions.push(pos.into());
-                        }
-
-                        for v in normals {
-                            let norm = (xform * Vec3::from(v).extend(0.0)).truncate().normalize();
-                            res.normals.push(norm.into());
-                        }
-
-                        for v in tangents {
-                            let v = Vec4::from(v);
-                            let t = (xform * v.truncate().extend(0.0)).truncate().normalize();
-                            res.tangents.push(
-                                t.extend(v.w * if flip_winding_order { -1.0 } else { 1.0 })
-                                    .into(),
-                            );
-                        }
-
-                        res.uvs.append(&mut uvs);
-                    }
+    for &j in &sorted {
+        let node = &joint_nodes[j];
+
+        // Find the closest ancestor which is a joint of this skin
+        let mut parent = NO_PARENT_JOINT;
+        let mut parent_xform = node_graph.root_xform;
+        let mut ancestor = node_graph.parents.get(&node.index()).copied();
+        while let Some(ancestor_idx) = ancestor {
+            if let Some(&joint) = node_to_joint.get(&ancestor_idx) {
+                parent = joint;
+                break;
+            }
+            ancestor = node_graph.parents.get(&ancestor_idx).copied();
+        }
+
+        if parent == NO_PARENT_JOINT {
+            if let Some(parent_idx) = node_graph.parents.get(&node.index()) {
+                parent_xform = node_graph.xforms[parent_idx];
+            }
+        }
+
+        let (translation, rotation, scale) = node.transform().decomposed();
+
+        skeleton.push(SkeletonJoint {
+            parent,
+            translation,
+            rotation,
+            scale,
+            parent_xform: parent_xform.to_cols_array(),
+            inverse_bind: (inverse_binds.get(j).copied().unwrap_or(Mat4::IDENTITY)
+                * inv_mesh_xform)
+                .to_cols_array(),
+        });
+
+        joints_by_node
+            .entry(node.index())
+            .or_default()
+            .push(skin_joints[j]);
+    }
+
+    skin_joints
+}
+
+fn load_gltf_animations(
+    gltf: &gltf::Document,
+    buffers: &[bytes::Bytes],
+    joints_by_node: &HashMap<usize, Vec<u32>>,
+) -> Vec<AnimationClip> {
+    use gltf::animation::util::ReadOutputs;
+
+    let mut clips = Vec::new();
+
+    for anim in gltf.animations() {
+        let mut channels = Vec::new();
+        let mut duration = 0.0f32;
+
+        for channel in anim.channels() {
+            let joints = if let Some(joints) = joints_by_node.get(&channel.target().node().index())
+            {
+                joints
+            } else {
+                // Only skeletal animation is supported
+                continue;
+            };
+
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let times: Vec<f32> = if let Some(iter) = reader.read_inputs() {
+                iter.collect()
+            } else {
+                continue;
+            };
+
+            let (property, values): (AnimationProperty, Vec<[f32; 4]>) = match reader.read_outputs()
+            {
+                Some(ReadOutputs::Translations(iter)) => (
+                    AnimationProperty::Translation,
+                    iter.map(|v| [v[0], v[1], v[2], 0.0]).collect(),
+                ),
+                Some(ReadOutputs::Rotations(iter)) => {
+                    (AnimationProperty::Rotation, iter.into_f32().collect())
+                }
+                Some(ReadOutputs::Scales(iter)) => (
+                    AnimationProperty::Scale,
+                    iter.map(|v| [v[0], v[1], v[2], 0.0]).collect(),
+                ),
+                Some(ReadOutputs::MorphTargetWeights(_)) => {
+                    log::warn!("Morph target animation is not supported; skipping");
+                    continue;
                 }
+                None => continue,
             };
 
-            let xform = Mat4::from_scale_rotation_translation(
-                Vec3::splat(self.scale),
-                self.rotation,
-                Vec3::ZERO,
-            );
-            for node in scene.nodes() {
-                iter_gltf_node_tree(&node, xform, &mut process_node);
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Linear => AnimationInterpolation::Linear,
+                gltf::animation::Interpolation::Step => AnimationInterpolation::Step,
+                gltf::animation::Interpolation::CubicSpline => AnimationInterpolation::CubicSpline,
+            };
+
+            // Sampling indexes the values by key, and searches the times
+            let values_per_key = match interpolation {
+                AnimationInterpolation::CubicSpline => 3,
+                _ => 1,
+            };
+            if times.is_empty() || !times.windows(2).all(|pair| pair[0] <= pair[1]) {
+                log::warn!(
+                    "Skipping a channel of animation {:?}: its key times are empty or decreasing",
+                    anim.name().unwrap_or_default()
+                );
+                continue;
+            }
+            if values.len() != times.len() * values_per_key {
+                log::warn!(
+                    "Skipping a channel of animation {:?}: {} values don't match its {} keys",
+                    anim.name().unwrap_or_default(),
+                    values.len(),
+                    times.len()
+                );
+                continue;
             }
 
-            Ok(res)
-        } else {
-            Err(anyhow::anyhow!("No default scene found in gltf"))
+            duration = duration.max(times.last().copied().unwrap_or(0.0));
+
+            for &joint in joints {
+                channels.push(AnimationChannel {
+                    joint,
+                    property,
+                    interpolation,
+                    times: times.clone(),
+                    values: values.clone(),
+                });
+            }
+        }
+
+        if !channels.is_empty() {
+            clips.push(AnimationClip {
+                name: anim
+                    .name()
+                    .map_or_else(|| format!("animation{}", anim.index()), str::to_owned),
+                duration,
+                channels,
+            });
         }
     }
-}
 
-#[derive(Clone, Copy)]
-#[repr(C)]
-pub struct PackedVertex {
-    pub pos: [f32; 3],
-    normal: u32,
+    clips
 }
 
-fn pack_unit_direction_11_10_11(x: f32, y: f32, z: f32) -> u32 {
-    let x = ((x.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 11u32) - 1u32) as f32) as u32;
-    let y = ((y.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 10u32) - 1u32) as f32) as u32;
-    let z = ((z.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 11u32) - 1u32) as f32) as u32;
+/// Reads a vertex attribute as floats. Besides `f32`, accepts the integer component types
+/// of `KHR_mesh_quantization`, normalized or not.
+pub(crate) fn read_gltf_attribute<const N: usize>(
+    prim: &gltf::Primitive,
+    semantic: Semantic,
+    buffers: &[bytes::Bytes],
+) -> Option<Vec<[f32; N]>>
+where
+    [f32; N]: Item,
+    [i8; N]: Item,
+    [u8; N]: Item,
+    [i16; N]: Item,
+    [u16; N]: Item,
+{
+    fn dequantize<T: Into<f32>, const N: usize>(
+        iter: impl Iterator<Item = [T; N]>,
+        // The largest value of `T`, for normalized values
+        normalized_max: Option<f32>,
+    ) -> Vec<[f32; N]> {
+        iter.map(|v| {
+            v.map(|c| match normalized_max {
+                // Signed values can reach one below `-max`, which also maps to -1
+                Some(max) => (c.into() / max).max(-1.0),
+                None => c.into(),
+            })
+        })
+        .collect()
+    }
 
-    (z << 21) | (y << 11) | x
-}
+    let accessor = prim.get(&semantic)?;
+    if accessor.dimensions().multiplicity() != N {
+        log::warn!(
+            "Ignoring the {:?} attribute: expected {} components, found {:?}",
+            semantic,
+            N,
+            accessor.dimensions()
+        );
+        return None;
+    }
 
-#[repr(packed)]
-pub struct FlatVec<T> {
-    len: u64,
-    offset: u64,
-    marker: std::marker::PhantomData<T>,
+    let get_buffer_data = |buffer: gltf::Buffer| buffers.get(buffer.index()).map(|b| &b[..]);
+    let normalized = accessor.normalized();
+
+    Some(match accessor.data_type() {
+        DataType::F32 => AccessorIter::<[f32; N]>::new(accessor, get_buffer_data)?.collect(),
+        DataType::I8 => dequantize(
+            AccessorIter::<[i8; N]>::new(accessor, get_buffer_data)?,
+            normalized.then(|| i8::MAX as f32),
+        ),
+        DataType::U8 => dequantize(
+            AccessorIter::<[u8; N]>::new(accessor, get_buffer_data)?,
+            normalized.then(|| u8::MAX as f32),
+        ),
+        DataType::I16 => dequantize(
+            AccessorIter::<[i16; N]>::new(accessor, get_buffer_data)?,
+            normalized.then(|| i16::MAX as f32),
+        ),
+        DataType::U16 => dequantize(
+            AccessorIter::<[u16; N]>::new(accessor, get_buffer_data)?,
+            normalized.then(|| u16::MAX as f32),
+        ),
+        DataType::U32 => {
+            log::warn!(
+                "Ignoring the {:?} attribute: u32 is not a valid type",
+                semantic
+            );
+            return None;
+        }
+    })
 }
 
-impl<T> std::borrow::Borrow<[T]> for FlatVec<T> {
-    fn borrow(&self) -> &[T] {
-        unsafe {
-            let data = (self as *const Self as *const u8).add(self.offset as usize);
-            std::slice::from_raw_parts(data as *const T, self.len as usize)
-        }
+/// `values` of an optional per-vertex attribute, unless their count differs from the
+/// `vertex_count` of the primitive, in which case the attribute is dropped with a warning.
+fn per_vertex_stream<T>(
+    mesh_name: &str,
+    attribute: &str,
+    vertex_count: usize,
+    values: Option<Vec<T>>,
+) -> Option<Vec<T>> {
+    let values = values?;
+    if values.len() != vertex_count {
+        log::warn!(
+            "Ignoring the {} attribute of a primitive of mesh {:?}: it has {} elements for {} vertices",
+            attribute,
+            mesh_name,
+            values.len(),
+            vertex_count
+        );
+        return None;
     }
+    Some(values)
 }
 
-impl<T> std::ops::Index<usize> for FlatVec<T> {
-    type Output = T;
+// Append all primitives of `mesh` to `res`, transformed by `xform`.
+//
+// `skin_joints` maps skin-local joint indices to indices in `res.skeleton`
+// if the mesh is skinned and the joints should be imported.
+#[allow(clippy::too_many_arguments)]
+fn append_gltf_mesh(
+    res: &mut TriangleMesh,
+    mesh: &gltf::Mesh,
+    xform: Mat4,
+    skin_joints: Option<&[u32]>,
+    normal_generation: NormalGeneration,
+    buffers: &[bytes::Bytes],
+    imgs: &[ImageSource],
+    raw_json: &RawJson,
+) {
+    let flip_winding_order = xform.determinant() < 0.0;
+
+    for prim in mesh.primitives() {
+        match prim.mode() {
+            gltf::mesh::Mode::Triangles
+            | gltf::mesh::Mode::TriangleStrip
+            | gltf::mesh::Mode::TriangleFan => {}
+            mode => {
+                log::warn!(
+                    "Skipping a primitive of mesh {:?}: mode {:?} is not supported",
+                    mesh.name().unwrap_or_default(),
+                    mode
+                );
+                continue;
+            }
+        }
+
+        let reader = prim.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let res_material_index = res.materials.len() as u32;
+
+        {
+            let (mut maps, mut material) = load_gltf_material(&prim.material(), raw_json, imgs);
+
+            let map_base = res.maps.len() as u32;
+            for id in material.maps.iter_mut() {
+                *id += map_base;
+            }
+
+            res.materials.push(material);
+            res.maps.append(&mut maps);
+        }
+
+        // Collect positions (required)
+        let mut positions = if let Some(positions) =
+            read_gltf_attribute::<3>(&prim, Semantic::Positions, buffers)
+        {
+            positions
+        } else {
+            continue;
+        };
+
+        // The document isn't validated, so optional streams of the wrong length get dropped
+        let mesh_name = mesh.name().unwrap_or_default();
+        let vertex_count = positions.len();
+
+        // Collect normals (generated below if missing)
+        let normals = per_vertex_stream(
+            mesh_name,
+            "NORMAL",
+            vertex_count,
+            read_gltf_attribute::<3>(&prim, Semantic::Normals, buffers),
+        );
+
+        // Collect tangents (optional)
+        let (mut tangents, tangents_found) = if let Some(tangents) = per_vertex_stream(
+            mesh_name,
+            "TANGENT",
+            vertex_count,
+            read_gltf_attribute::<4>(&prim, Semantic::Tangents, buffers),
+        ) {
+            // The handedness must be +1 or -1, but some exporters write zero
+            let tangents = tangents
+                .into_iter()
+                .map(|[x, y, z, w]| [x, y, z, if w < 0.0 { -1.0 } else { 1.0 }])
+                .collect::<Vec<_>>();
+            (tangents, true)
+        } else {
+            (vec![[1.0, 0.0, 0.0, 0.0]; positions.len()], false)
+        };
+
+        // Collect uvs (optional)
+        let (mut uvs, uvs_found) = if let Some(uvs) = per_vertex_stream(
+            mesh_name,
+            "TEXCOORD_0",
+            vertex_count,
+            read_gltf_attribute::<2>(&prim, Semantic::TexCoords(0), buffers),
+        ) {
+            (uvs, true)
+        } else {
+            (vec![[0.0, 0.0]; positions.len()], false)
+        };
+
+        // Collect the second uv set (optional)
+        let mut uvs1 = per_vertex_stream(
+            mesh_name,
+            "TEXCOORD_1",
+            vertex_count,
+            read_gltf_attribute::<2>(&prim, Semantic::TexCoords(1), buffers),
+        );
+
+        // Collect colors (optional)
+        let mut colors = if let Some(colors) = per_vertex_stream(
+            mesh_name,
+            "COLOR_0",
+            vertex_count,
+            reader
+                .read_colors(0)
+                .map(|iter| iter.into_rgba_f32().collect::<Vec<_>>()),
+        ) {
+            colors
+        } else {
+            vec![[1.0, 1.0, 1.0, 1.0]; positions.len()]
+        };
+
+        // Collect joints and weights (optional)
+        let mut skinning = skin_joints.and_then(|skin_joints| {
+            // Indices into the whole skeleton, which must fit the 16 bits stored per vertex
+            let remap = |j: u16| -> Option<u16> {
+                u16::try_from(*skin_joints.get(j as usize)?).ok()
+            };
+
+            let joints = reader
+                .read_joints(0)?
+                .into_u16()
+                .map(|j| Some([remap(j[0])?, remap(j[1])?, remap(j[2])?, remap(j[3])?]))
+                .collect::<Option<Vec<_>>>();
+
+            let joints = match joints {
+                Some(joints) => joints,
+                None => {
+                    log::warn!(
+                        "Mesh {:?} refers to joints outside of its skin, or its skeleton has more than {} joints; skipping its skinning",
+                        mesh.name().unwrap_or_default(),
+                        u16::MAX as u32 + 1
+                    );
+                    return None;
+                }
+            };
+
+            let joints = per_vertex_stream(mesh_name, "JOINTS_0", vertex_count, Some(joints))?;
+            let weights = reader.read_weights(0)?.into_f32().collect::<Vec<_>>();
+            let weights = per_vertex_stream(mesh_name, "WEIGHTS_0", vertex_count, Some(weights))?;
+            Some((joints, weights))
+        });
+
+        // Collect material ids
+        let mut material_ids = vec![res_material_index; positions.len()];
+
+        // Collect indices
+        let mut indices: Vec<u32>;
+        {
+            if let Some(indices_reader) = reader.read_indices() {
+                indices = indices_reader.into_u32().collect();
+
+                // Everything below indexes the vertex streams with these directly
+                if let Some(index) = indices.iter().find(|&&i| i as usize >= vertex_count) {
+                    log::warn!(
+                        "Skipping a primitive of mesh {:?}: index {} is out of range of its {} vertices",
+                        mesh_name,
+                        index,
+                        vertex_count
+                    );
+                    continue;
+                }
+            } else {
+                if positions.is_empty() {
+                    continue;
+                }
+
+                indices = (0..positions.len() as u32).collect();
+            }
+
+            indices = triangulate_gltf_indices(prim.mode(), indices);
+        }
+
+        let normals = if let Some(normals) = normals {
+            normals
+        } else {
+            log::debug!(
+                "Mesh {:?} has no normals. Generating {:?} normals...",
+                mesh.name().unwrap_or_default(),
+                normal_generation
+            );
+
+            if normal_generation == NormalGeneration::Flat {
+                // Flat shading needs unique vertices per face
+                unweld_vertices(&mut positions, &indices);
+                unweld_vertices(&mut tangents, &indices);
+                unweld_vertices(&mut uvs, &indices);
+                if let Some(uvs1) = &mut uvs1 {
+                    unweld_vertices(uvs1, &indices);
+                }
+                unweld_vertices(&mut colors, &indices);
+                unweld_vertices(&mut material_ids, &indices);
+                if let Some((joints, weights)) = &mut skinning {
+                    unweld_vertices(joints, &indices);
+                    unweld_vertices(weights, &indices);
+                }
+                indices = (0..indices.len() as u32).collect();
+            }
+
+            generate_normals(&positions, &indices, normal_generation)
+        };
+
+        if flip_winding_order {
+            for tri in indices.chunks_exact_mut(3) {
+                tri.swap(0, 2);
+            }
+        }
+
+        if !tangents_found {
+            generate_tangents(
+                mesh.name().unwrap_or_default(),
+                &positions,
+                &normals,
+                uvs_found.then(|| uvs.as_slice()),
+                &indices,
+                &mut tangents,
+                &mut res.tangent_stats,
+            );
+        }
+
+        // --------------------------------------------------------
+        // Write it all to the output
+
+        {
+            // log::info!("Loading a mesh with {} indices", indices.len());
+            let base_index = res.positions.len() as u32;
+            for i in &mut indices {
+                *i += base_index;
+            }
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.as_slice()[index]
+            res.indices.append(&mut indices);
+            res.colors.append(&mut colors);
+            res.material_ids.append(&mut material_ids);
+
+            if let Some((joints, weights)) = &mut skinning {
+                // Unskinned vertices preceding this primitive get zero weights
+                res.joints.resize(base_index as usize, [0; 4]);
+                res.weights.resize(base_index as usize, [0.0; 4]);
+                res.joints.append(joints);
+                res.weights.append(weights);
+            }
+        }
+
+        for v in positions {
+            let pos = (xform * Vec3::from(v).extend(1.0)).truncate();
+            res.positions.push(pos.into());
+        }
+
+        for v in normals {
+            let norm = (xform * Vec3::from(v).extend(0.0)).truncate().normalize();
+            res.normals.push(norm.into());
+        }
+
+        for v in tangents {
+            let v = Vec4::from(v);
+            let t = (xform * v.truncate().extend(0.0)).truncate().normalize();
+            res.tangents.push(
+                t.extend(v.w * if flip_winding_order { -1.0 } else { 1.0 })
+                    .into(),
+            );
+        }
+
+        res.uvs.append(&mut uvs);
+
+        if let Some(mut uvs1) = uvs1 {
+            // Vertices preceding this primitive don't have a second uv set
+            res.uvs1.resize(res.uvs.len() - uvs1.len(), [0.0, 0.0]);
+            res.uvs1.append(&mut uvs1);
+        }
     }
-}
 
-impl<T> FlatVec<T> {
-    #[inline(always)]
-    pub fn len(&self) -> usize {
-        self.len as usize
+    if !res.uvs1.is_empty() {
+        res.uvs1.resize(res.uvs.len(), [0.0, 0.0]);
     }
+}
+
+/// How to generate normals for glTF primitives which don't have any
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum NormalGeneration {
+    /// Per-face normals, as mandated by the glTF spec. Duplicates shared vertices.
+    Flat,
+    /// Smooth normals, averaged from adjacent faces weighted by their area
+    SmoothAreaWeighted,
+    /// Smooth normals, averaged from adjacent faces weighted by the angle at the vertex
+    SmoothAngleWeighted,
+}
 
-    #[inline(always)]
-    pub fn is_empty(&self) -> bool {
-        self.len == 0
+impl Default for NormalGeneration {
+    fn default() -> Self {
+        Self::Flat
     }
+}
 
-    #[inline(always)]
-    pub fn as_slice(&self) -> &[T] {
-        unsafe {
-            let data = (std::ptr::addr_of!(self.offset) as *const u64 as *const u8)
-                .add(self.offset as usize);
-            std::slice::from_raw_parts(data as *const T, self.len as usize)
+impl std::str::FromStr for NormalGeneration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Self::Flat),
+            "smooth-area" => Ok(Self::SmoothAreaWeighted),
+            "smooth-angle" => Ok(Self::SmoothAngleWeighted),
+            _ => Err(anyhow::anyhow!(
+                "Unknown normal generation mode {:?}; expected flat, smooth-area, or smooth-angle",
+                s
+            )),
         }
     }
+}
+
+/// Fills in the `tangents` of a primitive which has none: from its UVs with mikktspace
+/// where possible, and otherwise around the normals.
+pub(crate) fn generate_tangents(
+    mesh_name: &str,
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    uvs: Option<&[[f32; 2]]>,
+    indices: &[u32],
+    tangents: &mut [[f32; 4]],
+    stats: &mut TangentStats,
+) {
+    let mut tangents_generated = false;
+    if let Some(uvs) = uvs {
+        log::trace!("Mesh had UVs but no tangents. Calculating the tangents...");
+
+        let degenerate_uv_triangles = count_degenerate_uv_triangles(positions, uvs, indices);
+        if degenerate_uv_triangles > 0 {
+            log::warn!(
+                "Mesh {:?}: {} triangles have degenerate UVs, and will get arbitrary tangents",
+                mesh_name,
+                degenerate_uv_triangles
+            );
+            stats.degenerate_uv_triangles += degenerate_uv_triangles;
+        }
+
+        tangents_generated = mikktspace::generate_tangents(&mut TangentCalcContext {
+            indices,
+            positions,
+            normals,
+            uvs,
+            tangents,
+        });
 
-    #[inline(always)]
-    pub fn as_ptr(&self) -> *const T {
-        unsafe {
-            let data = (std::ptr::addr_of!(self.offset) as *const u64 as *const u8)
-                .add(self.offset as usize);
-            data as *const T
+        if tangents_generated {
+            stats.generated_from_uvs += tangents.len();
         }
     }
 
-    #[inline(always)]
-    pub fn iter(&self) -> impl Iterator<Item = &T> {
-        self.as_slice().iter()
+    if !tangents_generated {
+        log::debug!(
+            "Mesh {:?} has no usable UVs to derive tangents from. Synthesizing them...",
+            mesh_name
+        );
+
+        for (tangent, normal) in tangents.iter_mut().zip(normals) {
+            *tangent = tangent_from_normal(Vec3::from(*normal)).extend(1.0).into();
+        }
+        stats.synthesized_without_uvs += tangents.len();
     }
 }
 
-pub fn flatten_vec_header(writer: &mut Vec<u8>, len: usize) -> usize {
-    writer
-        .write_u64::<byteorder::NativeEndian>(len as u64)
-        .unwrap();
-    writer.write_u64::<byteorder::NativeEndian>(0u64).unwrap();
-    writer.len() - 8
+/// An arbitrary unit vector perpendicular to `normal`, for meshes which have no UVs
+/// to derive the tangents from (Duff et al. 2017, "Building an Orthonormal Basis, Revisited").
+fn tangent_from_normal(normal: Vec3) -> Vec3 {
+    let n = normal.try_normalize().unwrap_or(Vec3::Z);
+
+    let sign = 1.0f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x)
 }
 
-pub fn flatten_plain_field<T: Copy + Sized>(writer: &mut impl std::io::Write, data: &T) {
-    writer
-        .write_all(unsafe {
-            std::slice::from_raw_parts(data as *const T as *const u8, std::mem::size_of::<T>())
+/// Number of triangles with a non-zero area whose UVs don't span any.
+fn count_degenerate_uv_triangles(
+    positions: &[[f32; 3]],
+    uvs: &[[f32; 2]],
+    indices: &[u32],
+) -> usize {
+    indices
+        .chunks_exact(3)
+        .filter(|tri| {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| i as usize);
+
+            let (p0, p1, p2) = (
+                Vec3::from(positions[a]),
+                Vec3::from(positions[b]),
+                Vec3::from(positions[c]),
+            );
+            let area = (p1 - p0).cross(p2 - p0).length();
+
+            let (uv0, uv1, uv2) = (Vec2::from(uvs[a]), Vec2::from(uvs[b]), Vec2::from(uvs[c]));
+            let uv_area = (uv1 - uv0).perp_dot(uv2 - uv0).abs();
+
+            area > 1e-12 && uv_area < 1e-12
         })
-        .unwrap();
+        .count()
 }
 
-pub fn flatten_bytes(writer: &mut impl std::io::Write, data: &[u8]) {
-    writer.write_all(data).unwrap();
+pub(crate) fn unweld_vertices<T: Copy>(attribs: &mut Vec<T>, indices: &[u32]) {
+    *attribs = indices.iter().map(|&i| attribs[i as usize]).collect();
 }
 
-pub struct DeferredBlob {
-    pub fixup_addr: usize, // offset within parent
-    pub nested: FlattenCtx,
+pub(crate) fn generate_normals(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    mode: NormalGeneration,
+) -> Vec<[f32; 3]> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+
+    for tri in indices.chunks_exact(3) {
+        let p = [
+            Vec3::from(positions[tri[0] as usize]),
+            Vec3::from(positions[tri[1] as usize]),
+            Vec3::from(positions[tri[2] as usize]),
+        ];
+
+        // Length is twice the triangle's area
+        let face_normal = (p[1] - p[0]).cross(p[2] - p[0]);
+
+        for corner in 0..3 {
+            let weighted = match mode {
+                NormalGeneration::Flat | NormalGeneration::SmoothAreaWeighted => face_normal,
+                NormalGeneration::SmoothAngleWeighted => {
+                    let e0 = (p[(corner + 1) % 3] - p[corner]).normalize_or_zero();
+                    let e1 = (p[(corner + 2) % 3] - p[corner]).normalize_or_zero();
+                    face_normal.normalize_or_zero() * e0.dot(e1).clamp(-1.0, 1.0).acos()
+                }
+            };
+
+            normals[tri[corner] as usize] += weighted;
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.length_squared() > 0.0 {
+                n.normalize().into()
+            } else {
+                [0.0, 1.0, 0.0]
+            }
+        })
+        .collect()
 }
 
-#[derive(Default)]
-pub struct FlattenCtx {
-    pub section_idx: Option<usize>,
-    pub bytes: Vec<u8>,
-    pub deferred: Vec<DeferredBlob>,
+// Convert strips and fans to triangle lists. Other modes are passed through as-is.
+fn triangulate_gltf_indices(mode: gltf::mesh::Mode, indices: Vec<u32>) -> Vec<u32> {
+    match mode {
+        gltf::mesh::Mode::TriangleStrip => {
+            let mut res = Vec::with_capacity(indices.len().saturating_sub(2) * 3);
+            for (i, tri) in indices.windows(3).enumerate() {
+                // Every other triangle of a strip has its winding reversed
+                if i % 2 == 0 {
+                    res.extend_from_slice(&[tri[0], tri[1], tri[2]]);
+                } else {
+                    res.extend_from_slice(&[tri[1], tri[0], tri[2]]);
+                }
+            }
+            res
+        }
+        gltf::mesh::Mode::TriangleFan => {
+            let mut res = Vec::with_capacity(indices.len().saturating_sub(2) * 3);
+            for edge in indices.get(1..).unwrap_or_default().windows(2) {
+                res.extend_from_slice(&[indices[0], edge[0], edge[1]]);
+            }
+            res
+        }
+        _ => indices,
+    }
 }
 
-impl FlattenCtx {
-    fn allocate_section_indices(&mut self) {
-        let mut counter = 0;
-        self.allocate_section_indices_impl(&mut counter);
+#[derive(Clone)]
+pub struct LoadGltfScene {
+    pub path: PathBuf,
+    pub scale: f32,
+    pub rotation: Quat,
+    pub normal_generation: NormalGeneration,
+}
+
+impl Hash for LoadGltfScene {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.scale.to_ne_bytes().hash(state);
+        self.rotation.x.to_ne_bytes().hash(state);
+        self.rotation.y.to_ne_bytes().hash(state);
+        self.rotation.z.to_ne_bytes().hash(state);
+        self.rotation.w.to_ne_bytes().hash(state);
+        self.normal_generation.hash(state);
     }
+}
+
+#[async_trait]
+impl LazyWorker for LoadGltfScene {
+    type Output = anyhow::Result<TriangleMesh>;
+
+    async fn run(self, _ctx: RunContext) -> Self::Output {
+        let (gltf, buffers, imgs, raw_json) = crate::import_gltf::import(&self.path)
+            .with_context(|| format!("Loading GLTF scene from {:?}", self.path))?;
+
+        if let Some(scene) = gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            let mut res: TriangleMesh = TriangleMesh::default();
+
+            let root_xform = Mat4::from_scale_rotation_translation(
+                Vec3::splat(self.scale),
+                self.rotation,
+                Vec3::ZERO,
+            );
+            let node_graph = GltfNodeGraph::new(&scene, root_xform);
 
-    fn allocate_section_indices_impl(&mut self, counter: &mut usize) {
-        self.section_idx = Some(*counter);
-        *counter += 1;
+            // Skeleton joints driven by each node, for binding animation channels
+            let mut joints_by_node: HashMap<usize, Vec<u32>> = HashMap::new();
 
-        for child in &mut self.deferred {
-            child.nested.allocate_section_indices_impl(counter);
+            let mut process_node = |node: &gltf::scene::Node, xform: Mat4| {
+                if let Some(mesh) = node.mesh() {
+                    // Maps skin-local joint indices to indices in `res.skeleton`
+                    let skin_joints: Option<Vec<u32>> = node.skin().map(|skin| {
+                        load_gltf_skin(
+                            &skin,
+                            xform,
+                            &node_graph,
+                            &buffers,
+                            &mut res.skeleton,
+                            &mut joints_by_node,
+                        )
+                    });
+
+                    append_gltf_mesh(
+                        &mut res,
+                        &mesh,
+                        xform,
+                        skin_joints.as_deref(),
+                        self.normal_generation,
+                        &buffers,
+                        &imgs,
+                        &raw_json,
+                    );
+                }
+            };
+
+            for node in scene.nodes() {
+                iter_gltf_node_tree(&node, root_xform, &mut process_node);
+            }
+
+            if !res.joints.is_empty() {
+                res.joints.resize(res.positions.len(), [0; 4]);
+                res.weights.resize(res.positions.len(), [0.0; 4]);
+            }
+
+            res.animations = load_gltf_animations(&gltf, &buffers, &joints_by_node);
+
+            Ok(res)
+        } else {
+            Err(anyhow::anyhow!("No default scene found in gltf"))
         }
     }
+}
 
-    fn finish(mut self, writer: &mut impl std::io::Write) {
-        self.allocate_section_indices();
+/// Hashes the contents of a glTF file, and of all the buffers and images it references.
+/// Used to detect changes to the sources of baked assets.
+pub fn hash_gltf_sources(path: &Path, state: &mut impl std::hash::Hasher) -> anyhow::Result<()> {
+    // Includes the binary chunk of GLB files, and embedded data URIs
+    std::fs::read(crate::import_gltf::filesystem_path(path))?.hash(state);
 
-        type FixupAddr = usize;
-        type SectionIdx = usize;
+    let (_, buffers, images, _) = crate::import_gltf::import(path)?;
+    for buffer in &buffers {
+        buffer.hash(state);
+    }
 
-        struct Section {
-            bytes: Vec<u8>,
-            fixups: Vec<(FixupAddr, SectionIdx)>,
+    // Embedded images are already part of the buffers
+    for image in &images {
+        if let ImageSource::File(_) = image {
+            image.read()?.hash(state);
         }
+    }
 
-        // Build flattened sections over the nested structures
-        let mut sections: Vec<Section> = Vec::new();
+    Ok(())
+}
 
-        let mut ctx_list = vec![self];
-        while !ctx_list.is_empty() {
-            let mut next_ctx_list: Vec<Self> = vec![];
+#[derive(Clone)]
+pub struct SceneNode {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub local_transform: Mat4,
+    pub mesh: Option<usize>,
+}
 
-            for ctx in ctx_list {
-                sections.push(Section {
-                    bytes: ctx.bytes,
-                    fixups: ctx
-                        .deferred
-                        .iter()
-                        .map(|deferred| (deferred.fixup_addr, deferred.nested.section_idx.unwrap()))
-                        .collect(),
-                });
+/// Scene with the glTF node hierarchy preserved. Nodes are sorted parent-first,
+/// and every mesh is kept in its own local space.
+#[derive(Clone, Default)]
+pub struct SceneHierarchy {
+    pub nodes: Vec<SceneNode>,
+    pub meshes: Vec<TriangleMesh>,
+}
 
-                for deferred in ctx.deferred {
-                    next_ctx_list.push(deferred.nested);
-                }
-            }
+#[derive(Clone)]
+pub struct LoadGltfSceneHierarchy {
+    pub path: PathBuf,
+    pub scale: f32,
+    pub rotation: Quat,
+    pub normal_generation: NormalGeneration,
+}
 
-            ctx_list = next_ctx_list;
-        }
+impl Hash for LoadGltfSceneHierarchy {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.scale.to_ne_bytes().hash(state);
+        self.rotation.x.to_ne_bytes().hash(state);
+        self.rotation.y.to_ne_bytes().hash(state);
+        self.rotation.z.to_ne_bytes().hash(state);
+        self.rotation.w.to_ne_bytes().hash(state);
+        self.normal_generation.hash(state);
+    }
+}
 
-        // Lay out the sections
-        let mut total_bytes = 0usize;
-        let section_base_addr: Vec<usize> = sections
-            .iter()
-            .map(|s| {
-                let base_addr = total_bytes;
-                total_bytes += s.bytes.len();
-                base_addr
-            })
-            .collect();
+#[async_trait]
+impl LazyWorker for LoadGltfSceneHierarchy {
+    type Output = anyhow::Result<SceneHierarchy>;
+
+    async fn run(self, _ctx: RunContext) -> Self::Output {
+        let (gltf, buffers, imgs, raw_json) = crate::import_gltf::import(&self.path)
+            .with_context(|| format!("Loading GLTF scene from {:?}", self.path))?;
 
-        // Apply fixups
-        for (section, &section_addr) in sections.iter_mut().zip(&section_base_addr) {
-            for &(fixup_addr, target_section) in &section.fixups {
-                // Apply the fixup of the structure which was pointing at this deferred blob
-                let fixup_target: u64 = section_base_addr[target_section] as u64;
+        let scene = gltf
+            .default_scene()
+            .or_else(|| gltf.scenes().next())
+            .ok_or_else(|| anyhow::anyhow!("No default scene found in gltf"))?;
 
-                // The fixup is relative to the `offset` field itself
-                let fixup_relative = fixup_target - (fixup_addr + section_addr) as u64;
+        let root_xform = Mat4::from_scale_rotation_translation(
+            Vec3::splat(self.scale),
+            self.rotation,
+            Vec3::ZERO,
+        );
+
+        let mut res = SceneHierarchy::default();
+
+        // glTF mesh index to index in `res.meshes`; meshes shared by several nodes are loaded once
+        let mut loaded_meshes: HashMap<usize, Option<usize>> = HashMap::new();
+
+        let mut stack: Vec<(gltf::Node, Option<usize>)> =
+            scene.nodes().map(|node| (node, None)).collect();
+        stack.reverse();
 
-                section.bytes[fixup_addr..fixup_addr + 8]
-                    .copy_from_slice(&fixup_relative.to_ne_bytes());
+        while let Some((node, parent)) = stack.pop() {
+            let mut local_transform = Mat4::from_cols_array_2d(&node.transform().matrix());
+            if parent.is_none() {
+                local_transform = root_xform * local_transform;
             }
-        }
 
-        // Write sections out
-        for section in sections {
-            writer.write_all(section.bytes.as_slice()).unwrap();
+            if node.skin().is_some() {
+                log::warn!(
+                    "Skinning is not supported when preserving the node hierarchy; importing {:?} as a static mesh",
+                    node.name()
+                );
+            }
+
+            let mesh = node.mesh().and_then(|mesh| {
+                *loaded_meshes.entry(mesh.index()).or_insert_with(|| {
+                    let mut loaded = TriangleMesh::default();
+                    append_gltf_mesh(
+                        &mut loaded,
+                        &mesh,
+                        Mat4::IDENTITY,
+                        None,
+                        self.normal_generation,
+                        &buffers,
+                        &imgs,
+                        &raw_json,
+                    );
+
+                    if loaded.indices.is_empty() {
+                        None
+                    } else {
+                        res.meshes.push(loaded);
+                        Some(res.meshes.len() - 1)
+                    }
+                })
+            });
+
+            let node_idx = res.nodes.len();
+            res.nodes.push(SceneNode {
+                name: node
+                    .name()
+                    .map_or_else(|| format!("node{}", node.index()), str::to_owned),
+                parent,
+                local_transform,
+                mesh,
+            });
+
+            stack.extend(
+                node.children()
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, Some(node_idx))),
+            );
         }
+
+        Ok(res)
     }
 }
 
-macro_rules! def_asset {
-    // Vector
-    (@proto_ty Vec($($type:tt)+)) => {
-        Vec<def_asset!(@proto_ty $($type)+ )>
-    };
-    (@flat_ty Vec($($type:tt)+)) => {
-        FlatVec<def_asset!(@flat_ty $($type)+ )>
-    };
-    (@flatten $output:expr; $field:expr; Vec($($type:tt)+)) => {
-        let fixup_addr = flatten_vec_header(&mut $output.bytes, $field.len());
-        let mut nested = FlattenCtx::default();
-        for item in $field.iter() {
-            def_asset!(@flatten &mut nested; item; $($type)+ );
-        }
-        $output.deferred.push(DeferredBlob {
-            fixup_addr,
-            nested,
-        });
-    };
+#[derive(Clone, Copy, bytecheck::CheckBytes)]
+#[repr(C)]
+pub struct PackedVertex {
+    pub pos: [f32; 3],
+    normal: u32,
+}
 
-    // Bytes
-    (@proto_ty Bytes) => {
-        bytes::Bytes
-    };
-    (@flat_ty Bytes) => {
-        FlatVec<u8>
-    };
-    (@flatten $output:expr; $field:expr; Bytes) => {
-        let fixup_addr = flatten_vec_header(&mut $output.bytes, $field.len());
-        let mut nested = FlattenCtx::default();
-        flatten_bytes(&mut $output.bytes, $field);
-        $output.deferred.push(DeferredBlob {
-            fixup_addr,
-            nested,
-        });
-    };
+fn pack_unit_direction_11_10_11(x: f32, y: f32, z: f32) -> u32 {
+    let x = ((x.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 11u32) - 1u32) as f32) as u32;
+    let y = ((y.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 10u32) - 1u32) as f32) as u32;
+    let z = ((z.max(-1.0).min(1.0) * 0.5 + 0.5) * ((1u32 << 11u32) - 1u32) as f32) as u32;
 
-    // Asset
-    (@proto_ty Asset($($type:tt)+)) => {
-        Lazy<def_asset!(@proto_ty $($type)+ ::Proto)>
-    };
-    (@flat_ty Asset($($type:tt)+)) => {
-        AssetRef<def_asset!(@flat_ty $($type)+ ::Flat)>
-    };
-    (@flatten $output:expr; $field:expr; Asset($($type:tt)+)) => {
-        let asset_ref: AssetRef<$($type ::Flat)+> = AssetRef {
-            identity: $field.identity(),
-            marker: std::marker::PhantomData,
-        };
-        flatten_plain_field(&mut $output.bytes, &asset_ref)
-    };
+    (z << 21) | (y << 11) | x
+}
+
+/// Implements `rkyv::Archive` for plain `Copy` types which are baked as-is.
+/// Their validation is up to a `bytecheck::CheckBytes` derive.
+macro_rules! impl_archive_as_self {
+    ($($type:ty),+ $(,)?) => {
+        $(
+            impl rkyv::Archive for $type {
+                type Archived = Self;
+                type Resolver = ();
 
+                #[inline]
+                unsafe fn resolve(&self, _pos: usize, _resolver: (), out: *mut Self) {
+                    out.write(*self);
+                }
+            }
 
-    // Plain type
-    (@proto_ty $($type:tt)+) => {
-        $($type)+
-    };
-    (@flat_ty $($type:tt)+) => {
-        $($type)+
-    };
-    (@flatten $output:expr; $field:expr; $($type:tt)+) => {
-        flatten_plain_field(&mut $output.bytes, $field)
+            impl<S: rkyv::Fallible + ?Sized> rkyv::Serialize<S> for $type {
+                #[inline]
+                fn serialize(&self, _serializer: &mut S) -> Result<(), S::Error> {
+                    Ok(())
+                }
+            }
+        )+
     };
+}
+pub(crate) use impl_archive_as_self;
 
+impl_archive_as_self!(PackedVertex, MeshMaterial, PackedSceneNode, PackedMeshLod);
+
+macro_rules! def_asset {
     (
         $(
             #[derive($($derive:tt)+)]
         )?
         $struct_name:ident {
             $(
-                $name:ident { $($type:tt)+ }
+                $(#[$field_attr:meta])*
+                $name:ident: $type:ty,
             )+
         }
     ) => {
@@ -710,34 +1721,36 @@ macro_rules! def_asset {
             use super::*;
 
             $(#[derive($($derive)+)])?
+            #[derive(rkyv::Archive, rkyv::Serialize)]
+            #[archive(check_bytes)]
             pub struct Proto {
                 $(
-                    pub $name: def_asset!(@proto_ty $($type)+ ),
+                    $(#[$field_attr])*
+                    pub $name: $type,
                 )*
             }
 
-            #[repr(packed)]
-            pub struct Flat {
-                $(
-                    pub $name: def_asset!(@flat_ty $($type)+ ),
-                )*
-            }
+            /// Zero-copy view of a baked `Proto`
+            pub type Flat = ArchivedProto;
 
             impl Proto {
                 pub fn flatten_into(&self, writer: &mut impl std::io::Write) {
-                    let mut output = FlattenCtx {
-                        bytes: Vec::new(),
-                        deferred: Default::default(),
-                        section_idx: None,
-                    };
-
-                    $(
-                        def_asset!(@flatten &mut output; &self.$name; $($type)+ );
-                    )*
+                    self.try_flatten_into(writer).unwrap()
+                }
 
-                    output.finish(writer)
+                pub fn try_flatten_into(
+                    &self,
+                    writer: &mut impl std::io::Write,
+                ) -> std::io::Result<()> {
+                    let payload = rkyv::to_bytes::<_, 256>(self).unwrap();
+                    $crate::asset_header::write_baked_asset::<Flat>(writer, &payload)
                 }
             }
+
+            impl $crate::asset_header::BakedAsset for Flat {
+                const KIND: &'static str = stringify!($struct_name);
+                type Source = Proto;
+            }
         }
     };
 }
@@ -783,27 +1796,194 @@ impl<T> Ord for AssetRef<T> {
     }
 }
 
-// TODO: use `rkyv` instead
+impl<T> rkyv::Archive for AssetRef<T> {
+    type Archived = Self;
+    type Resolver = ();
+
+    #[inline]
+    unsafe fn resolve(&self, _pos: usize, _resolver: (), out: *mut Self) {
+        out.write(*self);
+    }
+}
+
+impl<T, S: rkyv::Fallible + ?Sized> rkyv::Serialize<S> for AssetRef<T> {
+    #[inline]
+    fn serialize(&self, _serializer: &mut S) -> Result<(), S::Error> {
+        Ok(())
+    }
+}
+
+// Any identity is valid; a missing asset is reported when it's loaded.
+impl<T, C: ?Sized> bytecheck::CheckBytes<C> for AssetRef<T> {
+    type Error = std::convert::Infallible;
+
+    #[inline]
+    unsafe fn check_bytes<'a>(
+        value: *const Self,
+        _context: &mut C,
+    ) -> Result<&'a Self, Self::Error> {
+        Ok(&*value)
+    }
+}
+
+/// Archives lazily processed images as references to their baked files.
+pub struct AsImageRefs;
+
+impl rkyv::with::ArchiveWith<Vec<Lazy<GpuImage::Proto>>> for AsImageRefs {
+    type Archived = rkyv::vec::ArchivedVec<AssetRef<GpuImage::Flat>>;
+    type Resolver = rkyv::vec::VecResolver;
+
+    #[inline]
+    unsafe fn resolve_with(
+        field: &Vec<Lazy<GpuImage::Proto>>,
+        pos: usize,
+        resolver: Self::Resolver,
+        out: *mut Self::Archived,
+    ) {
+        rkyv::vec::ArchivedVec::resolve_from_len(field.len(), pos, resolver, out);
+    }
+}
+
+impl<S> rkyv::with::SerializeWith<Vec<Lazy<GpuImage::Proto>>, S> for AsImageRefs
+where
+    S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer + ?Sized,
+{
+    fn serialize_with(
+        field: &Vec<Lazy<GpuImage::Proto>>,
+        serializer: &mut S,
+    ) -> Result<Self::Resolver, S::Error> {
+        let refs: Vec<AssetRef<GpuImage::Flat>> = field
+            .iter()
+            .map(|img| AssetRef {
+                identity: img.identity(),
+                marker: std::marker::PhantomData,
+            })
+            .collect();
+
+        rkyv::vec::ArchivedVec::serialize_from_slice(&refs, serializer)
+    }
+}
+
+/// Archives `vk::Format` as its raw value.
+pub struct AsRawFormat;
+
+impl rkyv::with::ArchiveWith<vk::Format> for AsRawFormat {
+    type Archived = i32;
+    type Resolver = ();
+
+    #[inline]
+    unsafe fn resolve_with(field: &vk::Format, _pos: usize, _resolver: (), out: *mut i32) {
+        out.write(field.as_raw());
+    }
+}
+
+impl<S: rkyv::Fallible + ?Sized> rkyv::with::SerializeWith<vk::Format, S> for AsRawFormat {
+    #[inline]
+    fn serialize_with(_field: &vk::Format, _serializer: &mut S) -> Result<(), S::Error> {
+        Ok(())
+    }
+}
+
 def_asset! {
+    #[derive(Clone)]
     GpuImage {
-        format { kajiya_backend::ash::vk::Format }
-        extent { [u32; 3] }
-        mips { Vec(Vec(u8)) }
+        // Raw `vk::Format` in the baked asset
+        #[with(AsRawFormat)]
+        format: vk::Format,
+        extent: [u32; 3],
+        // Six per cube for cube maps, with faces in the order +X, -X, +Y, -Y, +Z, -Z
+        array_layers: u32,
+        cube: bool,
+        // All the mips of the first array layer, followed by those of the next one, etc.
+        mips: Vec<Vec<u8>>,
     }
 }
 
-// TODO: use `rkyv` instead
 def_asset! {
     #[derive(Clone)]
     PackedTriMesh {
-        verts { Vec(PackedVertex) }
-        uvs { Vec([f32; 2]) }
-        tangents { Vec([f32; 4]) }
-        colors { Vec([f32; 4]) }
-        indices { Vec(u32) }
-        material_ids { Vec(u32) }
-        materials { Vec(MeshMaterial) }
-        maps { Vec(Asset(GpuImage)) }
+        verts: Vec<PackedVertex>,
+        uvs: Vec<[f32; 2]>,
+        uvs1: Vec<[f32; 2]>,
+        tangents: Vec<[f32; 4]>,
+        colors: Vec<[f32; 4]>,
+        indices: Vec<u32>,
+        material_ids: Vec<u32>,
+        materials: Vec<MeshMaterial>,
+        #[with(AsImageRefs)]
+        maps: Vec<Lazy<GpuImage::Proto>>,
+        joints: Vec<[u16; 4]>,
+        weights: Vec<[f32; 4]>,
+        // Simplified versions of `indices`, coarsest last
+        lods: Vec<PackedMeshLod>,
+        lod_indices: Vec<u32>,
+    }
+}
+
+def_asset! {
+    PackedAnimationSet {
+        skeleton: Vec<SkeletonJoint>,
+        clips: Vec<PackedAnimationClip>,
+        clip_names: Vec<Vec<u8>>,
+        channels: Vec<PackedAnimationChannel>,
+        keyframe_times: Vec<f32>,
+        keyframe_values: Vec<[f32; 4]>,
+    }
+}
+
+#[derive(Clone, Copy, bytecheck::CheckBytes)]
+#[repr(C)]
+pub struct PackedSceneNode {
+    pub parent: u32,
+    pub mesh: u32,
+    pub local_transform: [f32; 16],
+}
+
+impl PackedSceneNode {
+    pub const NONE: u32 = !0;
+}
+
+#[derive(Clone, Copy, bytecheck::CheckBytes)]
+#[repr(C)]
+pub struct PackedMeshLod {
+    // Range of `PackedTriMesh::lod_indices`
+    pub index_offset: u32,
+    pub index_count: u32,
+    // See `TriangleMeshLod::error`
+    pub error: f32,
+}
+
+def_asset! {
+    PackedScene {
+        nodes: Vec<PackedSceneNode>,
+        node_names: Vec<Vec<u8>>,
+        // File names of the baked meshes, without the `.mesh` extension
+        mesh_names: Vec<Vec<u8>>,
+    }
+}
+
+pub fn pack_scene_hierarchy(scene: &SceneHierarchy, mesh_names: &[String]) -> PackedScene::Proto {
+    assert_eq!(scene.meshes.len(), mesh_names.len());
+
+    PackedScene::Proto {
+        nodes: scene
+            .nodes
+            .iter()
+            .map(|node| PackedSceneNode {
+                parent: node.parent.map_or(PackedSceneNode::NONE, |p| p as u32),
+                mesh: node.mesh.map_or(PackedSceneNode::NONE, |m| m as u32),
+                local_transform: node.local_transform.to_cols_array(),
+            })
+            .collect(),
+        node_names: scene
+            .nodes
+            .iter()
+            .map(|node| node.name.as_bytes().to_vec())
+            .collect(),
+        mesh_names: mesh_names
+            .iter()
+            .map(|name| name.as_bytes().to_vec())
+            .collect(),
     }
 }
 
@@ -821,7 +2001,33 @@ pub struct PackedTriangleMesh {
 
 pub type PackedTriangleMesh = PackedTriMesh::Proto;
 
-pub fn pack_triangle_mesh(mesh: &TriangleMesh) -> PackedTriangleMesh {
+/// The first material using `mesh.maps[map_idx]`, and the slot of its `maps` the map is used at.
+fn first_map_use(mesh: &TriangleMesh, map_idx: usize) -> Option<(&MeshMaterial, usize)> {
+    mesh.materials.iter().find_map(|material| {
+        let slot = material
+            .maps
+            .iter()
+            .position(|&idx| idx as usize == map_idx)?;
+        Some((material, slot))
+    })
+}
+
+/// The normal map whose variance gets baked into the roughness of `mesh.maps[map_idx]`,
+/// if that is used as a spec map.
+pub fn roughness_normal_map(mesh: &TriangleMesh, map_idx: usize) -> Option<&ImageSource> {
+    match first_map_use(mesh, map_idx)? {
+        (material, 1) => match &mesh.maps[material.maps[0] as usize] {
+            MeshMaterialMap::Image { source, .. } => Some(source),
+            MeshMaterialMap::Placeholder(_) => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn pack_triangle_mesh(
+    mesh: &TriangleMesh,
+    tex_settings: &TexBakeSettings,
+) -> PackedTriangleMesh {
     let mut verts: Vec<PackedVertex> = Vec::with_capacity(mesh.positions.len());
 
     for (i, pos) in mesh.positions.iter().enumerate() {
@@ -836,9 +2042,10 @@ pub fn pack_triangle_mesh(mesh: &TriangleMesh) -> PackedTriangleMesh {
     let maps = mesh
         .maps
         .iter()
-        .map(|map| {
+        .enumerate()
+        .map(|(map_idx, map)| {
             let (image, params) = match map {
-                MeshMaterialMap::Image { source, params } => (
+                MeshMaterialMap::Image { source, params, .. } => (
                     super::image::LoadImage::new(source).unwrap().into_lazy(),
                     *params,
                 ),
@@ -848,24 +2055,56 @@ pub fn pack_triangle_mesh(mesh: &TriangleMesh) -> PackedTriangleMesh {
                         gamma: crate::mesh::TexGamma::Linear,
                         use_mips: false,
                         compression: TexCompressionMode::None,
+                        compression_quality: Default::default(),
                         channel_swizzle: None,
+                        normal_map: false,
+                        max_size: None,
                     },
                 ),
             };
 
-            crate::image::CreateGpuImage { image, params }.into_lazy()
+            let slot = first_map_use(mesh, map_idx).map(|(_, slot)| slot);
+            let roughness_normal_map = roughness_normal_map(mesh, map_idx)
+                .map(|source| super::image::LoadImage::new(source).unwrap().into_lazy());
+
+            crate::image::CreateGpuImage {
+                image,
+                params: tex_settings.apply(params, slot),
+                roughness_normal_map,
+            }
+            .into_lazy()
+        })
+        .collect();
+
+    let mut lod_indices = Vec::new();
+    let lods = mesh
+        .lods
+        .iter()
+        .map(|lod| {
+            let index_offset = lod_indices.len() as u32;
+            lod_indices.extend_from_slice(&lod.indices);
+            PackedMeshLod {
+                index_offset,
+                index_count: lod.indices.len() as u32,
+                error: lod.error,
+            }
         })
         .collect();
 
     PackedTriangleMesh {
         verts,
         uvs: mesh.uvs.clone(),
+        uvs1: mesh.uvs1.clone(),
         tangents: mesh.tangents.clone(),
         colors: mesh.colors.clone(),
         indices: mesh.indices.clone(),
         material_ids: mesh.material_ids.clone(),
         materials: mesh.materials.clone(),
         maps,
+        joints: mesh.joints.clone(),
+        weights: mesh.weights.clone(),
+        lods,
+        lod_indices,
     }
 }
 
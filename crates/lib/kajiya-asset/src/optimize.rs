@@ -0,0 +1,235 @@
+use std::collections::VecDeque;
+
+use glam::Vec3;
+
+use crate::mesh::{TriangleMesh, TriangleMeshLod};
+
+const MAX_LODS: usize = 8;
+const MIN_LOD_TRIANGLES: usize = 64;
+
+// Relative to the extents of the mesh; coarser LODs aren't generated
+const MAX_LOD_ERROR: f32 = 0.1;
+
+/// Sizes of a mesh before and after `optimize_triangle_mesh`, for bake reports.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MeshOptimizationStats {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    pub triangles_before: usize,
+    pub triangles_after: usize,
+    // Zero-area triangles which were removed
+    pub degenerate_triangles: usize,
+    // Average cache miss ratio: vertices transformed per triangle, with a FIFO cache
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+impl std::ops::AddAssign for MeshOptimizationStats {
+    fn add_assign(&mut self, other: Self) {
+        let weighted_acmr = |a: f32, b: f32| {
+            let triangles = self.triangles_before + other.triangles_before;
+            if triangles == 0 {
+                0.0
+            } else {
+                (a * self.triangles_before as f32 + b * other.triangles_before as f32)
+                    / triangles as f32
+            }
+        };
+
+        self.acmr_before = weighted_acmr(self.acmr_before, other.acmr_before);
+        self.acmr_after = weighted_acmr(self.acmr_after, other.acmr_after);
+        self.vertices_before += other.vertices_before;
+        self.vertices_after += other.vertices_after;
+        self.triangles_before += other.triangles_before;
+        self.triangles_after += other.triangles_after;
+        self.degenerate_triangles += other.degenerate_triangles;
+    }
+}
+
+/// Welds identical vertices, strips zero-area triangles, and reorders the triangles
+/// for the post-transform vertex cache and the vertices for fetch locality.
+///
+/// The result renders the same as the source. Must run before `generate_lods`.
+pub fn optimize_triangle_mesh(mesh: &mut TriangleMesh) -> MeshOptimizationStats {
+    assert!(mesh.lods.is_empty(), "LODs would not be remapped");
+
+    let mut stats = MeshOptimizationStats {
+        vertices_before: mesh.positions.len(),
+        triangles_before: mesh.indices.len() / 3,
+        acmr_before: average_cache_miss_ratio(&mesh.indices),
+        ..Default::default()
+    };
+
+    remove_degenerate_triangles(mesh);
+    stats.degenerate_triangles = stats.triangles_before - mesh.indices.len() / 3;
+
+    // After stripping, so that vertices used only by degenerate triangles are dropped
+    weld_vertices(mesh);
+
+    mesh.indices = meshopt::optimize_vertex_cache(&mesh.indices, mesh.positions.len());
+    reorder_vertices_by_first_use(mesh);
+
+    stats.vertices_after = mesh.positions.len();
+    stats.triangles_after = mesh.indices.len() / 3;
+    stats.acmr_after = average_cache_miss_ratio(&mesh.indices);
+    stats
+}
+
+/// Replaces `mesh.lods` with a chain of simplified index buffers, each with about half
+/// the triangles of the previous one. The chain ends early once simplification stops
+/// making progress, e.g. because of material seams.
+pub fn generate_lods(mesh: &mut TriangleMesh) -> anyhow::Result<()> {
+    let vertices = meshopt::VertexDataAdapter::new(
+        meshopt::typed_to_bytes(&mesh.positions),
+        std::mem::size_of::<[f32; 3]>(),
+        0,
+    )
+    .map_err(|err| anyhow::anyhow!("Preparing the mesh for simplification: {:?}", err))?;
+
+    // `simplify` measures errors relative to the mesh extents
+    let error_scale = meshopt::simplify_scale(&vertices);
+
+    mesh.lods.clear();
+    let mut prev_index_count = mesh.indices.len();
+    let mut prev_error = 0.0f32;
+
+    while mesh.lods.len() < MAX_LODS {
+        let target_triangles = prev_index_count / 3 / 2;
+        if target_triangles < MIN_LOD_TRIANGLES {
+            break;
+        }
+
+        // Simplifying the full mesh rather than the previous LOD keeps the errors accurate
+        let mut error = 0.0f32;
+        let indices = meshopt::simplify(
+            &mesh.indices,
+            &vertices,
+            target_triangles * 3,
+            MAX_LOD_ERROR,
+            meshopt::SimplifyOptions::empty(),
+            Some(&mut error),
+        );
+
+        // Not worth a LOD unless noticeably smaller
+        if indices.is_empty() || indices.len() as f32 > prev_index_count as f32 * 0.85 {
+            break;
+        }
+
+        prev_index_count = indices.len();
+        prev_error = prev_error.max(error * error_scale);
+
+        mesh.lods.push(TriangleMeshLod {
+            indices: meshopt::optimize_vertex_cache(&indices, mesh.positions.len()),
+            error: prev_error,
+        });
+    }
+
+    Ok(())
+}
+
+/// Merges vertices whose attributes are all bit-identical, and drops unreferenced ones.
+fn weld_vertices(mesh: &mut TriangleMesh) {
+    let vertex_count = mesh.positions.len();
+
+    // Optional streams are either empty, or have an entry for every vertex
+    fn stream<T>(attribs: &[T], vertex_count: usize) -> Option<meshopt::VertexStream<'_>> {
+        if attribs.is_empty() {
+            return None;
+        }
+
+        assert_eq!(
+            attribs.len(),
+            vertex_count,
+            "Vertex attribute count mismatch"
+        );
+        Some(meshopt::VertexStream::new(attribs.as_ptr()))
+    }
+
+    let streams: Vec<_> = [
+        stream(&mesh.positions, vertex_count),
+        stream(&mesh.normals, vertex_count),
+        stream(&mesh.colors, vertex_count),
+        stream(&mesh.uvs, vertex_count),
+        stream(&mesh.uvs1, vertex_count),
+        stream(&mesh.tangents, vertex_count),
+        stream(&mesh.material_ids, vertex_count),
+        stream(&mesh.joints, vertex_count),
+        stream(&mesh.weights, vertex_count),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let (unique_count, remap) =
+        meshopt::generate_vertex_remap_multi(vertex_count, &streams, Some(&mesh.indices));
+
+    mesh.indices = meshopt::remap_index_buffer(Some(&mesh.indices), vertex_count, &remap);
+    remap_vertices(mesh, unique_count, &remap);
+}
+
+/// Removes triangles with zero area: ones with coincident corners, or collinear ones.
+fn remove_degenerate_triangles(mesh: &mut TriangleMesh) {
+    let positions = &mesh.positions;
+    let indices = mesh
+        .indices
+        .chunks_exact(3)
+        .filter(|tri| {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| Vec3::from(positions[i as usize]));
+            (b - a).cross(c - a) != Vec3::ZERO
+        })
+        .flatten()
+        .copied()
+        .collect();
+
+    mesh.indices = indices;
+}
+
+/// Stores the vertices in the order they're first used.
+fn reorder_vertices_by_first_use(mesh: &mut TriangleMesh) {
+    // Every vertex must be referenced: `optimize_vertex_fetch_remap` truncates the table
+    // to the number of referenced vertices.
+    let remap = meshopt::optimize_vertex_fetch_remap(&mesh.indices, mesh.positions.len());
+    assert_eq!(remap.len(), mesh.positions.len());
+
+    mesh.indices = meshopt::remap_index_buffer(Some(&mesh.indices), remap.len(), &remap);
+    remap_vertices(mesh, remap.len(), &remap);
+}
+
+/// Moves vertex `i` to `remap[i]`, dropping the ones mapped to `u32::MAX`.
+fn remap_vertices(mesh: &mut TriangleMesh, vertex_count: usize, remap: &[u32]) {
+    fn apply<T: Clone + Default>(attribs: &mut Vec<T>, vertex_count: usize, remap: &[u32]) {
+        if !attribs.is_empty() {
+            *attribs = meshopt::remap_vertex_buffer(attribs, vertex_count, remap);
+        }
+    }
+
+    apply(&mut mesh.positions, vertex_count, remap);
+    apply(&mut mesh.normals, vertex_count, remap);
+    apply(&mut mesh.colors, vertex_count, remap);
+    apply(&mut mesh.uvs, vertex_count, remap);
+    apply(&mut mesh.uvs1, vertex_count, remap);
+    apply(&mut mesh.tangents, vertex_count, remap);
+    apply(&mut mesh.material_ids, vertex_count, remap);
+    apply(&mut mesh.joints, vertex_count, remap);
+    apply(&mut mesh.weights, vertex_count, remap);
+}
+
+fn average_cache_miss_ratio(indices: &[u32]) -> f32 {
+    // Matches the cache size `meshopt` optimizes for
+    const CACHE_SIZE: usize = 16;
+
+    let mut cache: VecDeque<u32> = VecDeque::with_capacity(CACHE_SIZE);
+    let mut misses = 0usize;
+
+    for &index in indices {
+        if !cache.contains(&index) {
+            misses += 1;
+            if cache.len() == CACHE_SIZE {
+                cache.pop_front();
+            }
+            cache.push_back(index);
+        }
+    }
+
+    misses as f32 / (indices.len() / 3).max(1) as f32
+}
@@ -0,0 +1,463 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use bytes::Bytes;
+use gltf::{buffer, image, json, Semantic};
+
+use crate::{
+    import_gltf::{import_buffer_data, load_document, uri_file_path, RawJson},
+    mesh::read_gltf_attribute,
+};
+
+/// glTF extensions which the importer understands. Others are ignored, or fail the bake
+/// if the document requires them.
+const SUPPORTED_GLTF_EXTENSIONS: &[&str] = &[
+    "EXT_meshopt_compression",
+    "KHR_materials_clearcoat",
+    "KHR_materials_emissive_strength",
+    "KHR_materials_ior",
+    "KHR_materials_pbrSpecularGlossiness",
+    "KHR_materials_sheen",
+    "KHR_materials_transmission",
+    "KHR_mesh_quantization",
+    "KHR_texture_transform",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValidationSeverity {
+    // The asset bakes, but may not look as intended
+    Warning,
+    // The asset can't be baked
+    Error,
+}
+
+impl ValidationSeverity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationIssueKind {
+    MissingFile,
+    UnsupportedUri,
+    UnsupportedExtension,
+    InvalidBuffer,
+    EmptyPrimitive,
+    AccessorOutOfBounds,
+    NonFinitePosition,
+    IndexOutOfRange,
+}
+
+impl ValidationIssueKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::MissingFile => "missing-file",
+            Self::UnsupportedUri => "unsupported-uri",
+            Self::UnsupportedExtension => "unsupported-extension",
+            Self::InvalidBuffer => "invalid-buffer",
+            Self::EmptyPrimitive => "empty-primitive",
+            Self::AccessorOutOfBounds => "accessor-out-of-bounds",
+            Self::NonFinitePosition => "non-finite-position",
+            Self::IndexOutOfRange => "index-out-of-range",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationIssue {
+    pub severity: ValidationSeverity,
+    pub kind: ValidationIssueKind,
+    // Which part of the document the issue is in, e.g. `images[3]`
+    pub location: String,
+    pub message: String,
+}
+
+/// Problems found in a source asset before baking it.
+#[derive(Clone, Debug)]
+pub struct ValidationReport {
+    pub source: PathBuf,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    fn new(source: &Path) -> Self {
+        Self {
+            source: source.to_owned(),
+            issues: Vec::new(),
+        }
+    }
+
+    fn push(
+        &mut self,
+        severity: ValidationSeverity,
+        kind: ValidationIssueKind,
+        location: impl Into<String>,
+        message: impl Into<String>,
+    ) {
+        self.issues.push(ValidationIssue {
+            severity,
+            kind,
+            location: location.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn count(&self, severity: ValidationSeverity) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(ValidationSeverity::Error) > 0
+    }
+
+    /// One line per issue, errors first.
+    pub fn to_text(&self) -> String {
+        let mut issues: Vec<&ValidationIssue> = self.issues.iter().collect();
+        issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+
+        let mut text = format!(
+            "Validation of {:?}: {} errors, {} warnings\n",
+            self.source,
+            self.count(ValidationSeverity::Error),
+            self.count(ValidationSeverity::Warning)
+        );
+
+        for issue in issues {
+            text += &format!(
+                "{:<7} {:<22} {}: {}\n",
+                issue.severity.as_str(),
+                issue.kind.as_str(),
+                issue.location,
+                issue.message
+            );
+        }
+
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let string = |s: &str| json::Value::String(s.to_owned());
+        let count = |severity| json::Value::from(self.count(severity));
+
+        let issues = self
+            .issues
+            .iter()
+            .map(|issue| {
+                json::Value::Object(
+                    [
+                        ("severity", string(issue.severity.as_str())),
+                        ("kind", string(issue.kind.as_str())),
+                        ("location", string(&issue.location)),
+                        ("message", string(&issue.message)),
+                    ]
+                    .into_iter()
+                    .map(|(key, value)| (key.to_owned(), value))
+                    .collect(),
+                )
+            })
+            .collect();
+
+        let report = json::Value::Object(
+            [
+                ("source", string(&self.source.to_string_lossy())),
+                ("errors", count(ValidationSeverity::Error)),
+                ("warnings", count(ValidationSeverity::Warning)),
+                ("issues", json::Value::Array(issues)),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect(),
+        );
+
+        // Plain JSON values always serialize
+        json::serialize::to_string_pretty(&report).unwrap()
+    }
+}
+
+/// Checks a glTF document for problems which would make the bake fail or go wrong:
+/// missing files, unsupported extensions, empty primitives, non-finite positions,
+/// and out-of-range indices.
+///
+/// Only fails if the document itself can't be read; everything else is reported.
+pub fn validate_gltf(path: &Path) -> anyhow::Result<ValidationReport> {
+    let (gltf, raw_json, base) =
+        load_document(path).with_context(|| format!("Reading glTF document {:?}", path))?;
+    let document = &gltf.document;
+
+    let mut report = ValidationReport::new(path);
+    check_extensions(document, &mut report);
+
+    let buffers_found = check_buffer_files(document, &raw_json, &base, &mut report);
+    check_image_files(document, &base, &mut report);
+
+    // Without all of their buffers, the meshes can't be checked
+    if buffers_found {
+        match import_buffer_data(document, &raw_json, Some(&base), gltf.blob) {
+            Ok(buffers) => check_meshes(document, &buffers, &mut report),
+            Err(err) => report.push(
+                ValidationSeverity::Error,
+                ValidationIssueKind::InvalidBuffer,
+                "buffers",
+                err.to_string(),
+            ),
+        }
+    }
+
+    Ok(report)
+}
+
+fn check_extensions(document: &gltf::Document, report: &mut ValidationReport) {
+    let required: Vec<&str> = document.extensions_required().collect();
+
+    for ext in document.extensions_used() {
+        if SUPPORTED_GLTF_EXTENSIONS.contains(&ext) {
+            continue;
+        }
+
+        if required.contains(&ext) {
+            report.push(
+                ValidationSeverity::Error,
+                ValidationIssueKind::UnsupportedExtension,
+                "extensionsRequired",
+                format!("{} is required, but not supported", ext),
+            );
+        } else {
+            report.push(
+                ValidationSeverity::Warning,
+                ValidationIssueKind::UnsupportedExtension,
+                "extensionsUsed",
+                format!("{} is not supported, and will be ignored", ext),
+            );
+        }
+    }
+}
+
+/// Reports a missing or unsupported external file. Returns false if it can't be read.
+fn check_uri(base: &Path, uri: &str, location: String, report: &mut ValidationReport) -> bool {
+    match uri_file_path(base, uri) {
+        Ok(Some(path)) if !path.is_file() => {
+            report.push(
+                ValidationSeverity::Error,
+                ValidationIssueKind::MissingFile,
+                location,
+                format!("{:?} not found (resolved to {:?})", uri, path),
+            );
+            false
+        }
+        Ok(_) => true,
+        Err(scheme) => {
+            report.push(
+                ValidationSeverity::Error,
+                ValidationIssueKind::UnsupportedUri,
+                location,
+                format!("{:?}: the {:?} scheme is not supported", uri, scheme),
+            );
+            false
+        }
+    }
+}
+
+fn check_buffer_files(
+    document: &gltf::Document,
+    raw_json: &RawJson,
+    base: &Path,
+    report: &mut ValidationReport,
+) -> bool {
+    let mut all_found = true;
+
+    for buffer in document.buffers() {
+        // Not read; filled in with decompressed data instead
+        if raw_json.is_meshopt_fallback_buffer(buffer.index()) {
+            continue;
+        }
+
+        if let buffer::Source::Uri(uri) = buffer.source() {
+            let location = format!("buffers[{}]", buffer.index());
+            all_found &= check_uri(base, uri, location, report);
+        }
+    }
+
+    all_found
+}
+
+fn check_image_files(document: &gltf::Document, base: &Path, report: &mut ValidationReport) {
+    for image in document.images() {
+        if let image::Source::Uri { uri, .. } = image.source() {
+            let location = match image.name() {
+                Some(name) => format!("images[{}] {:?}", image.index(), name),
+                None => format!("images[{}]", image.index()),
+            };
+            check_uri(base, uri, location, report);
+        }
+    }
+}
+
+/// Whether `len` bytes at `offset` in the view stay within it, and the view within its buffer.
+fn view_range_in_bounds(view: &buffer::View, offset: usize, len: usize, buffers: &[Bytes]) -> bool {
+    let buffer_len = buffers.get(view.buffer().index()).map_or(0, Bytes::len);
+    view.offset() + view.length() <= buffer_len && offset + len <= view.length()
+}
+
+/// Whether reading the accessor, including its sparse indices and values, stays within
+/// their buffer views, and the views within their buffers.
+fn accessor_in_bounds(accessor: &gltf::Accessor, buffers: &[Bytes]) -> bool {
+    let element_size = accessor.size();
+
+    // Sparse accessors without a view start out zeroed
+    let dense_in_bounds = accessor.view().map_or(true, |view| {
+        let stride = view.stride().unwrap_or(element_size);
+        let len = match accessor.count() {
+            0 => 0,
+            count => stride * (count - 1) + element_size,
+        };
+        view_range_in_bounds(&view, accessor.offset(), len, buffers)
+    });
+
+    // Sparse indices and values are tightly packed
+    let sparse_in_bounds = accessor.sparse().map_or(true, |sparse| {
+        let count = sparse.count() as usize;
+        let (indices, values) = (sparse.indices(), sparse.values());
+
+        view_range_in_bounds(
+            &indices.view(),
+            indices.offset() as usize,
+            count * indices.index_type().size(),
+            buffers,
+        ) && view_range_in_bounds(
+            &values.view(),
+            values.offset() as usize,
+            count * element_size,
+            buffers,
+        )
+    });
+
+    dense_in_bounds && sparse_in_bounds
+}
+
+fn check_meshes(document: &gltf::Document, buffers: &[Bytes], report: &mut ValidationReport) {
+    for mesh in document.meshes() {
+        for prim in mesh.primitives() {
+            let location = match mesh.name() {
+                Some(name) => format!(
+                    "meshes[{}] {:?} primitives[{}]",
+                    mesh.index(),
+                    name,
+                    prim.index()
+                ),
+                None => format!("meshes[{}] primitives[{}]", mesh.index(), prim.index()),
+            };
+
+            check_primitive(&prim, buffers, location, report);
+        }
+    }
+}
+
+fn check_primitive(
+    prim: &gltf::Primitive,
+    buffers: &[Bytes],
+    location: String,
+    report: &mut ValidationReport,
+) {
+    // Reading out-of-bounds accessors would panic
+    let mut in_bounds = true;
+    for (semantic, accessor) in prim.attributes() {
+        if !accessor_in_bounds(&accessor, buffers) {
+            report.push(
+                ValidationSeverity::Error,
+                ValidationIssueKind::AccessorOutOfBounds,
+                location.clone(),
+                format!(
+                    "the {:?} attribute (accessors[{}]) reads past the end of its buffer",
+                    semantic,
+                    accessor.index()
+                ),
+            );
+            in_bounds = false;
+        }
+    }
+    if let Some(accessor) = prim.indices() {
+        if !accessor_in_bounds(&accessor, buffers) {
+            report.push(
+                ValidationSeverity::Error,
+                ValidationIssueKind::AccessorOutOfBounds,
+                location.clone(),
+                format!(
+                    "the indices (accessors[{}]) read past the end of their buffer",
+                    accessor.index()
+                ),
+            );
+            in_bounds = false;
+        }
+    }
+    if !in_bounds {
+        return;
+    }
+
+    let positions =
+        read_gltf_attribute::<3>(prim, Semantic::Positions, buffers).unwrap_or_default();
+    if positions.is_empty() {
+        report.push(
+            ValidationSeverity::Warning,
+            ValidationIssueKind::EmptyPrimitive,
+            location,
+            "no vertex positions; the primitive will be skipped",
+        );
+        return;
+    }
+
+    let non_finite = positions
+        .iter()
+        .filter(|p| !p.iter().all(|x| x.is_finite()))
+        .count();
+    if non_finite > 0 {
+        report.push(
+            ValidationSeverity::Error,
+            ValidationIssueKind::NonFinitePosition,
+            location.clone(),
+            format!(
+                "{} of {} vertex positions are NaN or infinite",
+                non_finite,
+                positions.len()
+            ),
+        );
+    }
+
+    let reader = prim.reader(|buffer| buffers.get(buffer.index()).map(|b| &b[..]));
+    if let Some(indices) = reader.read_indices() {
+        let indices: Vec<u32> = indices.into_u32().collect();
+        if indices.is_empty() {
+            report.push(
+                ValidationSeverity::Warning,
+                ValidationIssueKind::EmptyPrimitive,
+                location,
+                "no indices; the primitive will be skipped",
+            );
+            return;
+        }
+
+        let out_of_range: Vec<u32> = indices
+            .into_iter()
+            .filter(|&i| i as usize >= positions.len())
+            .collect();
+        if let Some(max_index) = out_of_range.iter().max() {
+            report.push(
+                ValidationSeverity::Error,
+                ValidationIssueKind::IndexOutOfRange,
+                location,
+                format!(
+                    "{} indices are out of range, up to {}, with {} vertices",
+                    out_of_range.len(),
+                    max_index,
+                    positions.len()
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use kajiya_asset::{
+    asset_header::{baked_asset, AssetHeader, BakedAsset},
+    mesh::{
+        pack_triangle_mesh, GpuImage, MeshMaterialMap, PackedScene, PackedTriMesh, TexBakeSettings,
+        TriangleMesh,
+    },
+};
+use kajiya_backend::ash::vk;
+use rkyv::AlignedVec;
+
+// Baked assets are memory-mapped at page boundaries; mimic that alignment.
+fn bake(write: impl FnOnce(&mut Vec<u8>)) -> AlignedVec {
+    let mut bytes = Vec::new();
+    write(&mut bytes);
+
+    let mut aligned = AlignedVec::new();
+    aligned.extend_from_slice(&bytes);
+    aligned
+}
+
+fn test_image() -> GpuImage::Proto {
+    GpuImage::Proto {
+        format: vk::Format::R8G8B8A8_UNORM,
+        extent: [2, 2, 1],
+        array_layers: 1,
+        cube: false,
+        mips: vec![(0..16).collect(), vec![1, 2, 3, 4]],
+    }
+}
+
+#[test]
+fn image_roundtrip() {
+    let image = test_image();
+    let bytes = bake(|out| image.flatten_into(out));
+    let baked = baked_asset::<GpuImage::Flat>(&bytes).unwrap();
+
+    assert_eq!(vk::Format::from_raw(baked.format), image.format);
+    assert_eq!(baked.extent, image.extent);
+    assert_eq!(baked.array_layers, 1);
+    assert!(!baked.cube);
+    assert_eq!(baked.mips.len(), image.mips.len());
+    for (baked_mip, mip) in baked.mips.iter().zip(&image.mips) {
+        assert_eq!(baked_mip.as_slice(), mip.as_slice());
+    }
+}
+
+#[test]
+fn mesh_roundtrip() {
+    let mesh = TriangleMesh {
+        positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        normals: vec![[0.0, 0.0, 1.0]; 3],
+        colors: vec![[1.0; 4]; 3],
+        uvs: vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]],
+        tangents: vec![[1.0, 0.0, 0.0, 1.0]; 3],
+        material_ids: vec![0; 3],
+        indices: vec![0, 1, 2],
+        maps: vec![MeshMaterialMap::Placeholder([255, 0, 0, 255])],
+        ..Default::default()
+    };
+
+    let packed = pack_triangle_mesh(&mesh, &TexBakeSettings::default());
+    let bytes = bake(|out| packed.flatten_into(out));
+    assert!(
+        baked_asset::<GpuImage::Flat>(&bytes).is_err(),
+        "a mesh must not load as an image"
+    );
+
+    let baked = baked_asset::<PackedTriMesh::Flat>(&bytes).unwrap();
+    assert_eq!(baked.indices.as_slice(), mesh.indices.as_slice());
+    assert_eq!(baked.uvs.as_slice(), mesh.uvs.as_slice());
+    assert!(baked.uvs1.is_empty());
+    assert_eq!(baked.tangents.as_slice(), mesh.tangents.as_slice());
+    assert_eq!(
+        baked.verts.iter().map(|v| v.pos).collect::<Vec<_>>(),
+        mesh.positions
+    );
+    assert_eq!(baked.maps.len(), 1);
+    assert_eq!(baked.maps[0].identity(), packed.maps[0].identity());
+}
+
+#[test]
+fn scene_roundtrip() {
+    let scene = PackedScene::Proto {
+        nodes: Vec::new(),
+        node_names: vec![b"root".to_vec(), b"child".to_vec()],
+        mesh_names: vec![b"scene.0".to_vec()],
+    };
+
+    let bytes = bake(|out| scene.flatten_into(out));
+    let baked = baked_asset::<PackedScene::Flat>(&bytes).unwrap();
+
+    assert!(baked.nodes.is_empty());
+    assert_eq!(baked.node_names.len(), 2);
+    assert_eq!(baked.node_names[1].as_slice(), b"child");
+    assert_eq!(baked.mesh_names[0].as_slice(), b"scene.0");
+}
+
+#[test]
+fn rejects_truncated_asset() {
+    let bytes = bake(|out| test_image().flatten_into(out));
+    let truncated = &bytes[..bytes.len() - 4];
+
+    assert!(baked_asset::<GpuImage::Flat>(truncated).is_err());
+}
+
+#[test]
+fn rejects_corrupt_archive() {
+    let mut bytes = bake(|out| test_image().flatten_into(out));
+
+    // Keep the header intact, but point everything out of bounds
+    for byte in &mut bytes[std::mem::size_of::<AssetHeader>()..] {
+        *byte = 0xff;
+    }
+
+    let err = baked_asset::<GpuImage::Flat>(&bytes)
+        .err()
+        .expect("corrupt archive accepted");
+    assert!(
+        err.to_string().contains(GpuImage::Flat::KIND),
+        "unexpected error: {}",
+        err
+    );
+}
@@ -0,0 +1,74 @@
+use kajiya_asset::{
+    ktx2::{read_ktx2, write_ktx2},
+    mesh::GpuImage,
+};
+use kajiya_backend::ash::vk;
+
+fn write(image: &GpuImage::Proto) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_ktx2(image, &mut bytes).unwrap();
+    bytes
+}
+
+fn assert_same_image(read: &GpuImage::Proto, written: &GpuImage::Proto) {
+    assert_eq!(read.format, written.format);
+    assert_eq!(read.extent, written.extent);
+    assert_eq!(read.array_layers, written.array_layers);
+    assert_eq!(read.cube, written.cube);
+    assert_eq!(read.mips, written.mips);
+}
+
+#[test]
+fn rgba8_roundtrip() {
+    let image = GpuImage::Proto {
+        format: vk::Format::R8G8B8A8_SRGB,
+        extent: [4, 2, 1],
+        array_layers: 1,
+        cube: false,
+        mips: vec![(0..32).collect(), (32..40).collect(), (40..44).collect()],
+    };
+
+    assert_same_image(&read_ktx2(&write(&image)).unwrap(), &image);
+}
+
+#[test]
+fn bc_cube_roundtrip() {
+    // 2x2 blocks of 16 bytes in the top mip, and one block in the rest; all the mips
+    // of each face are stored together
+    let image = GpuImage::Proto {
+        format: vk::Format::BC7_UNORM_BLOCK,
+        extent: [8, 8, 1],
+        array_layers: 6,
+        cube: true,
+        mips: (0..6 * 4)
+            .map(|subresource| {
+                let blocks = if subresource % 4 == 0 { 4 } else { 1 };
+                vec![subresource as u8; 16 * blocks]
+            })
+            .collect(),
+    };
+
+    assert_same_image(&read_ktx2(&write(&image)).unwrap(), &image);
+}
+
+#[test]
+fn rejects_level_size_mismatch() {
+    let image = GpuImage::Proto {
+        format: vk::Format::R8G8B8A8_UNORM,
+        extent: [2, 2, 1],
+        array_layers: 1,
+        cube: false,
+        mips: vec![(0..16).collect()],
+    };
+    let mut bytes = write(&image);
+
+    // Claim a huge array; the level data can't back it up
+    bytes[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(read_ktx2(&bytes).is_err());
+
+    // Claim a huge uncompressed size for the level
+    let mut bytes = write(&image);
+    let level_entry = 12 + 9 * 4 + 4 * 4 + 2 * 8;
+    bytes[level_entry + 16..level_entry + 24].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(read_ktx2(&bytes).is_err());
+}
@@ -0,0 +1,83 @@
+use glam::Quat;
+use kajiya_asset::mesh::{LoadGltfScene, NormalGeneration, TriangleMesh};
+use turbosloth::*;
+
+// Two primitives sharing the positions, each with its own indices
+const DOCUMENT: &str = r#"{
+    "asset": { "version": "2.0" },
+    "scene": 0,
+    "scenes": [{ "nodes": [0] }],
+    "nodes": [{ "mesh": 0 }],
+    "meshes": [{ "primitives": [
+        { "attributes": { "POSITION": 0 }, "indices": 1 },
+        { "attributes": { "POSITION": 0 }, "indices": 2 }
+    ] }],
+    "buffers": [{ "uri": "data:application/octet-stream;base64,$DATA", "byteLength": $BUFFER_LEN }],
+    "bufferViews": [
+        { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+        { "buffer": 0, "byteOffset": 36, "byteLength": 6 },
+        { "buffer": 0, "byteOffset": 42, "byteLength": 6 }
+    ],
+    "accessors": [
+        { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+        { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" },
+        { "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }
+    ]
+}"#;
+
+const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+/// Loads a document whose mesh has a triangle for each of the given index lists.
+fn load(name: &str, first_indices: [u16; 3], second_indices: [u16; 3]) -> TriangleMesh {
+    let mut buffer: Vec<u8> = TRIANGLE
+        .iter()
+        .flatten()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    buffer.extend(
+        first_indices
+            .iter()
+            .chain(&second_indices)
+            .flat_map(|i| i.to_le_bytes()),
+    );
+
+    let document = DOCUMENT
+        .replace("$DATA", &base64::encode(&buffer))
+        .replace("$BUFFER_LEN", &buffer.len().to_string());
+
+    let dir = std::env::temp_dir().join(format!("kajiya-load-gltf-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.gltf", name));
+    std::fs::write(&path, document).unwrap();
+
+    let lazy_cache = LazyCache::create();
+    let mesh = LoadGltfScene {
+        path: path.clone(),
+        scale: 1.0,
+        rotation: Quat::IDENTITY,
+        normal_generation: NormalGeneration::Flat,
+    }
+    .into_lazy();
+    let mesh = smol::block_on(mesh.eval(&lazy_cache)).unwrap();
+
+    let _ = std::fs::remove_file(&path);
+    (*mesh).clone()
+}
+
+#[test]
+fn loads_both_primitives() {
+    let mesh = load("valid", [0, 1, 2], [2, 1, 0]);
+    assert_eq!(mesh.indices.len(), 6);
+    assert_eq!(mesh.positions.len(), 6);
+}
+
+#[test]
+fn skips_primitive_with_out_of_range_index() {
+    let mesh = load("index-out-of-range", [0, 1, 3], [0, 1, 2]);
+    assert_eq!(mesh.indices.len(), 3);
+    assert_eq!(mesh.positions.len(), 3);
+    assert!(mesh
+        .indices
+        .iter()
+        .all(|&i| (i as usize) < mesh.positions.len()));
+}
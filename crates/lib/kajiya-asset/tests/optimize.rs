@@ -0,0 +1,97 @@
+use kajiya_asset::{mesh::TriangleMesh, optimize::optimize_triangle_mesh};
+
+/// A mesh with one vertex per index, as the importers produce before optimization.
+fn unwelded_mesh(triangles: &[[[f32; 3]; 3]], uvs: &[[[f32; 2]; 3]]) -> TriangleMesh {
+    let positions: Vec<[f32; 3]> = triangles.iter().flatten().copied().collect();
+    let vertex_count = positions.len();
+
+    TriangleMesh {
+        positions,
+        normals: vec![[0.0, 0.0, 1.0]; vertex_count],
+        colors: vec![[1.0; 4]; vertex_count],
+        uvs: uvs.iter().flatten().copied().collect(),
+        tangents: vec![[1.0, 0.0, 0.0, 1.0]; vertex_count],
+        material_ids: vec![0; vertex_count],
+        indices: (0..vertex_count as u32).collect(),
+        ..Default::default()
+    }
+}
+
+/// The triangles as position triples, starting at the smallest corner, so that they
+/// compare equal regardless of vertex and triangle order.
+fn rendered_triangles(mesh: &TriangleMesh) -> Vec<[[u32; 3]; 3]> {
+    let mut triangles: Vec<[[u32; 3]; 3]> = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|tri| {
+            let mut corners =
+                [tri[0], tri[1], tri[2]].map(|i| mesh.positions[i as usize].map(|x| x.to_bits()));
+            let first = (0..3).min_by_key(|&i| corners[i]).unwrap();
+            corners.rotate_left(first);
+            corners
+        })
+        .collect();
+    triangles.sort_unstable();
+    triangles
+}
+
+const QUAD: [[[f32; 3]; 3]; 2] = [
+    [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+    [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+];
+
+const QUAD_UVS: [[[f32; 2]; 3]; 2] = [
+    [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]],
+    [[0.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+];
+
+#[test]
+fn welds_identical_vertices() {
+    let mut mesh = unwelded_mesh(&QUAD, &QUAD_UVS);
+    let expected = rendered_triangles(&mesh);
+
+    let stats = optimize_triangle_mesh(&mut mesh);
+
+    assert_eq!(stats.vertices_before, 6);
+    assert_eq!(stats.vertices_after, 4);
+    assert_eq!(stats.triangles_after, 2);
+    assert_eq!(stats.degenerate_triangles, 0);
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.uvs.len(), 4);
+    assert_eq!(mesh.material_ids.len(), 4);
+    assert_eq!(rendered_triangles(&mesh), expected);
+}
+
+#[test]
+fn keeps_vertices_which_differ_in_any_attribute() {
+    // A UV seam along the diagonal
+    let mut uvs = QUAD_UVS;
+    uvs[1][1] = [0.5, 0.5];
+    let mut mesh = unwelded_mesh(&QUAD, &uvs);
+
+    optimize_triangle_mesh(&mut mesh);
+
+    assert_eq!(mesh.positions.len(), 5);
+    assert_eq!(mesh.uvs.len(), 5);
+}
+
+#[test]
+fn strips_degenerate_triangles() {
+    let coincident = [[2.0, 0.0, 0.0], [2.0, 0.0, 0.0], [3.0, 1.0, 0.0]];
+    let collinear = [[0.0, 2.0, 0.0], [1.0, 3.0, 0.0], [2.0, 4.0, 0.0]];
+    let triangles = [QUAD[0], coincident, QUAD[1], collinear];
+    let uvs = [QUAD_UVS[0], [[0.0; 2]; 3], QUAD_UVS[1], [[0.0; 2]; 3]];
+
+    let mut mesh = unwelded_mesh(&triangles, &uvs);
+    let stats = optimize_triangle_mesh(&mut mesh);
+
+    assert_eq!(stats.triangles_before, 4);
+    assert_eq!(stats.degenerate_triangles, 2);
+    assert_eq!(stats.triangles_after, 2);
+    // Vertices only used by the removed triangles are dropped too
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(
+        rendered_triangles(&mesh),
+        rendered_triangles(&unwelded_mesh(&QUAD, &QUAD_UVS))
+    );
+}
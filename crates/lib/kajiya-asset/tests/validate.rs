@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+
+use kajiya_asset::validate::{
+    validate_gltf, ValidationIssueKind, ValidationReport, ValidationSeverity,
+};
+
+// One indexed primitive, with its buffer embedded in the document
+const DOCUMENT: &str = r#"{
+    "asset": { "version": "2.0" },
+    "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+    "buffers": [{ "uri": "data:application/octet-stream;base64,$DATA", "byteLength": $BUFFER_LEN }],
+    "bufferViews": [
+        { "buffer": 0, "byteOffset": 0, "byteLength": $POSITIONS_LEN },
+        { "buffer": 0, "byteOffset": $POSITIONS_LEN, "byteLength": $INDICES_LEN }
+    ],
+    "accessors": [
+        { "bufferView": 0, "componentType": 5126, "count": $POSITION_COUNT, "type": "VEC3" $POSITIONS_EXTRA },
+        { "bufferView": 1, "componentType": 5123, "count": $INDEX_COUNT, "type": "SCALAR" }
+    ]
+    $DOCUMENT_EXTRA
+}"#;
+
+const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+/// Validates a document with the given mesh. The extras are spliced into the position
+/// accessor, and into the document's top level.
+fn validate(
+    name: &str,
+    positions: &[[f32; 3]],
+    indices: &[u16],
+    positions_extra: &str,
+    document_extra: &str,
+) -> ValidationReport {
+    let mut buffer: Vec<u8> = positions
+        .iter()
+        .flatten()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let positions_len = buffer.len();
+    buffer.extend(indices.iter().flat_map(|i| i.to_le_bytes()));
+
+    let document = DOCUMENT
+        .replace("$DATA", &base64::encode(&buffer))
+        .replace("$BUFFER_LEN", &buffer.len().to_string())
+        .replace("$POSITIONS_LEN", &positions_len.to_string())
+        .replace("$INDICES_LEN", &(indices.len() * 2).to_string())
+        .replace("$POSITION_COUNT", &positions.len().to_string())
+        .replace("$INDEX_COUNT", &indices.len().to_string())
+        .replace("$POSITIONS_EXTRA", positions_extra)
+        .replace("$DOCUMENT_EXTRA", document_extra);
+
+    let dir = std::env::temp_dir().join(format!("kajiya-validate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path: PathBuf = dir.join(format!("{}.gltf", name));
+    std::fs::write(&path, document).unwrap();
+
+    let report = validate_gltf(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    report
+}
+
+fn error_kinds(report: &ValidationReport) -> Vec<ValidationIssueKind> {
+    report
+        .issues
+        .iter()
+        .filter(|issue| issue.severity == ValidationSeverity::Error)
+        .map(|issue| issue.kind)
+        .collect()
+}
+
+#[test]
+fn valid_document_has_no_issues() {
+    let report = validate("valid", &TRIANGLE, &[0, 1, 2], "", "");
+    assert!(report.issues.is_empty(), "{}", report.to_text());
+}
+
+#[test]
+fn reports_missing_image() {
+    let report = validate(
+        "missing-image",
+        &TRIANGLE,
+        &[0, 1, 2],
+        "",
+        r#", "images": [{ "uri": "textures/missing.png" }]"#,
+    );
+    assert_eq!(error_kinds(&report), [ValidationIssueKind::MissingFile]);
+    assert_eq!(report.issues[0].location, "images[0]");
+}
+
+#[test]
+fn reports_out_of_range_index() {
+    let report = validate("index-out-of-range", &TRIANGLE, &[0, 1, 3], "", "");
+    assert_eq!(error_kinds(&report), [ValidationIssueKind::IndexOutOfRange]);
+}
+
+#[test]
+fn reports_nan_position() {
+    let mut positions = TRIANGLE;
+    positions[1][2] = f32::NAN;
+
+    let report = validate("nan-position", &positions, &[0, 1, 2], "", "");
+    assert_eq!(
+        error_kinds(&report),
+        [ValidationIssueKind::NonFinitePosition]
+    );
+}
+
+#[test]
+fn reports_sparse_indices_out_of_bounds() {
+    // The sparse indices start at the end of their view
+    let sparse = r#", "sparse": {
+        "count": 1,
+        "indices": { "bufferView": 1, "byteOffset": 6, "componentType": 5123 },
+        "values": { "bufferView": 0 }
+    }"#;
+
+    let report = validate("sparse-out-of-bounds", &TRIANGLE, &[0, 1, 2], sparse, "");
+    assert_eq!(
+        error_kinds(&report),
+        [ValidationIssueKind::AccessorOutOfBounds]
+    );
+}
@@ -4,7 +4,11 @@ use hotwatch::Hotwatch;
 use lazy_static::lazy_static;
 use normpath::PathExt;
 use parking_lot::Mutex;
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
 use turbosloth::*;
 
 lazy_static! {
@@ -105,14 +109,41 @@ pub fn normalized_path_from_vfs(path: impl Into<PathBuf>) -> anyhow::Result<Path
     Ok(path)
 }
 
+/// The inverse of `canonical_path_from_vfs`: expresses a path relative to the most specific
+/// mount point containing it, making it independent of where the mounted folders are.
+/// Paths outside of all the mount points are returned as-is.
+pub fn vfs_path_from_canonical(path: impl Into<PathBuf>) -> PathBuf {
+    let path = path.into();
+    let normalized = match path.normalize() {
+        Ok(normalized) => normalized.as_path().to_owned(),
+        Err(_) => return path,
+    };
+
+    let vfs_path = VFS_MOUNT_POINTS
+        .lock()
+        .iter()
+        .filter_map(|(mount_point, mounted_path)| {
+            let mounted_path = mounted_path.normalize().ok()?;
+            let rel_path = normalized.strip_prefix(mounted_path.as_path()).ok()?;
+            Some((
+                mounted_path.as_path().components().count(),
+                Path::new(mount_point).join(rel_path),
+            ))
+        })
+        .max_by_key(|(depth, _)| *depth);
+
+    vfs_path.map_or(path, |(_, vfs_path)| vfs_path)
+}
+
 #[derive(Clone, Hash)]
 pub struct LoadFile {
+    // Kept in VFS form, so that the identity doesn't depend on where the mount points are
     path: PathBuf,
 }
 
 impl LoadFile {
     pub fn new(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
-        let path = canonical_path_from_vfs(path)?;
+        let path = vfs_path_from_canonical(canonical_path_from_vfs(path)?);
         Ok(Self { path })
     }
 }
@@ -123,19 +154,20 @@ impl LazyWorker for LoadFile {
 
     async fn run(self, ctx: RunContext) -> Self::Output {
         let invalidation_trigger = ctx.get_invalidation_trigger();
+        let path = canonical_path_from_vfs(&self.path)?;
 
         FILE_WATCHER
             .lock()
-            .watch(self.path.clone(), move |event| {
+            .watch(path.clone(), move |event| {
                 if matches!(event, hotwatch::Event::Write(_)) {
                     invalidation_trigger();
                 }
             })
-            .with_context(|| format!("LoadFile: trying to watch {:?}", self.path))?;
+            .with_context(|| format!("LoadFile: trying to watch {:?}", path))?;
 
         let mut buffer = Vec::new();
-        std::io::Read::read_to_end(&mut File::open(&self.path)?, &mut buffer)
-            .with_context(|| format!("LoadFile: trying to read {:?}", self.path))?;
+        std::io::Read::read_to_end(&mut File::open(&path)?, &mut buffer)
+            .with_context(|| format!("LoadFile: trying to read {:?}", path))?;
 
         Ok(Bytes::from(buffer))
     }
@@ -11,7 +11,9 @@ pub mod vulkan;
 
 pub use ash;
 pub use error::BackendError;
-pub use file::{canonical_path_from_vfs, normalized_path_from_vfs, set_vfs_mount_point};
+pub use file::{
+    canonical_path_from_vfs, normalized_path_from_vfs, set_vfs_mount_point, vfs_path_from_canonical,
+};
 pub use gpu_allocator;
 pub use gpu_profiler;
 pub use rspirv_reflect;
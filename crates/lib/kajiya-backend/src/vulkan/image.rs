@@ -210,7 +210,9 @@ impl Image {
                 level_count: desc.level_count.unwrap_or(image_desc.mip_levels as u32),
                 base_array_layer: 0,
                 layer_count: match image_desc.image_type {
-                    ImageType::Cube | ImageType::CubeArray => 6,
+                    ImageType::Tex1dArray | ImageType::Tex2dArray => image_desc.array_elements,
+                    ImageType::Cube => 6,
+                    ImageType::CubeArray => 6 * image_desc.array_elements,
                     _ => 1,
                 },
             })
@@ -303,10 +305,18 @@ impl Device {
                 vk::Format::R16G16B16A16_SFLOAT => 1,
                 vk::Format::BC1_RGB_UNORM_BLOCK => 8,
                 vk::Format::BC1_RGB_SRGB_BLOCK => 8,
+                vk::Format::BC1_RGBA_UNORM_BLOCK => 8,
+                vk::Format::BC1_RGBA_SRGB_BLOCK => 8,
+                vk::Format::BC2_UNORM_BLOCK => 16,
+                vk::Format::BC2_SRGB_BLOCK => 16,
                 vk::Format::BC3_UNORM_BLOCK => 16,
                 vk::Format::BC3_SRGB_BLOCK => 16,
+                vk::Format::BC4_UNORM_BLOCK => 8,
+                vk::Format::BC4_SNORM_BLOCK => 8,
                 vk::Format::BC5_UNORM_BLOCK => 16,
                 vk::Format::BC5_SNORM_BLOCK => 16,
+                vk::Format::BC6H_UFLOAT_BLOCK => 16,
+                vk::Format::BC6H_SFLOAT_BLOCK => 16,
                 vk::Format::BC7_UNORM_BLOCK => 16,
                 vk::Format::BC7_SRGB_BLOCK => 16,
                 _ => todo!("{:?}", desc.format),
@@ -324,10 +334,16 @@ impl Device {
             let mapped_slice_mut = image_buffer.allocation.mapped_slice_mut().unwrap();
             let mut offset = 0;
 
+            // All the mips of the first array layer, followed by those of the next one, etc.
+            let mip_levels = desc.mip_levels.max(1) as usize;
+
             let buffer_copy_regions = initial_data
                 .into_iter()
                 .enumerate()
-                .map(|(level, sub)| {
+                .map(|(subresource, sub)| {
+                    let level = subresource % mip_levels;
+                    let layer = subresource / mip_levels;
+
                     mapped_slice_mut[offset..offset + sub.data.len()].copy_from_slice(sub.data);
                     assert_eq!(offset % block_bytes, 0);
 
@@ -336,6 +352,7 @@ impl Device {
                         .image_subresource(
                             vk::ImageSubresourceLayers::builder()
                                 .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .base_array_layer(layer as _)
                                 .layer_count(1)
                                 .mip_level(level as _)
                                 .build(),
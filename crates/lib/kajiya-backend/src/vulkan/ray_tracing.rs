@@ -36,6 +36,8 @@ pub struct RayTracingGeometryDesc {
     pub vertex_format: vk::Format,
     pub vertex_stride: usize,
     pub parts: Vec<RayTracingGeometryPart>,
+    // Non-opaque geometry invokes any hit shaders
+    pub opaque: bool,
 }
 
 #[derive(Clone)]
@@ -43,6 +45,8 @@ pub struct RayTracingInstanceDesc {
     pub blas: Arc<RayTracingAcceleration>,
     pub transformation: Affine3A,
     pub mesh_index: u32,
+    // If false, rays don't cull back faces of this instance even when asked to
+    pub face_cull: bool,
 }
 
 #[derive(Clone)]
@@ -123,7 +127,11 @@ impl Device {
                                     .index_type(ash::vk::IndexType::UINT32) // TODO
                                     .build(),
                         })
-                        .flags(ash::vk::GeometryFlagsKHR::OPAQUE)
+                        .flags(if desc.opaque {
+                            ash::vk::GeometryFlagsKHR::OPAQUE
+                        } else {
+                            ash::vk::GeometryFlagsKHR::empty()
+                        })
                         .build();
 
                     Ok(geometry)
@@ -210,9 +218,7 @@ impl Device {
                     desc.mesh_index, /* instance id */
                     0xff,
                     0,
-                    /*ash::vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
-                    | */
-                    ash::vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE,
+                    instance_flags(desc),
                     blas_address,
                 )
             })
@@ -442,9 +448,7 @@ impl Device {
                 desc.mesh_index, /* instance id */
                 0xff,
                 0,
-                /*ash::vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
-                | */
-                ash::vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE,
+                instance_flags(desc),
                 blas_address,
             )
         }));
@@ -831,6 +835,7 @@ pub fn create_ray_tracing_pipeline(
                     assert!(
                         prev_stage == Some(ShaderPipelineStage::RayMiss)
                             || prev_stage == Some(ShaderPipelineStage::RayClosestHit)
+                            || prev_stage == Some(ShaderPipelineStage::RayAnyHit)
                     );
                     hit_entry_count += 1;
 
@@ -856,6 +861,42 @@ pub fn create_ray_tracing_pipeline(
                     shader_stages.push(stage);
                     shader_groups.push(group);
                 }
+                ShaderPipelineStage::RayAnyHit => {
+                    assert!(
+                        prev_stage == Some(ShaderPipelineStage::RayMiss)
+                            || prev_stage == Some(ShaderPipelineStage::RayClosestHit)
+                            || prev_stage == Some(ShaderPipelineStage::RayAnyHit)
+                    );
+
+                    let (module, entry_point) = create_shader_module(desc);
+
+                    entry_points.push(std::ffi::CString::new(entry_point).unwrap());
+                    let entry_point = &**entry_points.last().unwrap();
+
+                    let stage = ash::vk::PipelineShaderStageCreateInfo::builder()
+                        .stage(ash::vk::ShaderStageFlags::ANY_HIT_KHR)
+                        .module(module)
+                        .name(entry_point)
+                        .build();
+
+                    if prev_stage == Some(ShaderPipelineStage::RayClosestHit) {
+                        shader_groups.last_mut().unwrap().any_hit_shader = group_idx as _;
+                    } else {
+                        hit_entry_count += 1;
+
+                        let group = ash::vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                            .ty(ash::vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                            .general_shader(ash::vk::SHADER_UNUSED_KHR)
+                            .closest_hit_shader(ash::vk::SHADER_UNUSED_KHR)
+                            .any_hit_shader(group_idx as _)
+                            .intersection_shader(ash::vk::SHADER_UNUSED_KHR)
+                            .build();
+
+                        shader_groups.push(group);
+                    }
+
+                    shader_stages.push(stage);
+                }
                 _ => unimplemented!(),
             }
 
@@ -941,6 +982,14 @@ struct GeometryInstance {
     blas_address: vk::DeviceAddress,
 }
 
+fn instance_flags(desc: &RayTracingInstanceDesc) -> ash::vk::GeometryInstanceFlagsKHR {
+    if desc.face_cull {
+        ash::vk::GeometryInstanceFlagsKHR::empty()
+    } else {
+        ash::vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
+    }
+}
+
 impl GeometryInstance {
     fn new(
         transform: [f32; 12],
@@ -481,6 +481,9 @@ pub enum ShaderPipelineStage {
     RayGen,
     RayMiss,
     RayClosestHit,
+    // Joins the hit group of the preceding closest hit shader; starts a group
+    // without a closest hit shader if not preceded by one
+    RayAnyHit,
 }
 
 #[derive(Builder, Hash, PartialEq, Eq, Clone, Debug)]
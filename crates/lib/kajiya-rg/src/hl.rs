@@ -160,12 +160,46 @@ impl<'rg> SimpleRenderPass<'rg, RgComputePipelineHandle> {
     }
 }
 
+/// Shaders invoked on ray hits; either one is optional, but not both.
+pub struct RtHitGroup {
+    pub closest_hit: Option<ShaderSource>,
+    pub any_hit: Option<ShaderSource>,
+}
+
+impl RtHitGroup {
+    pub fn new(closest_hit: ShaderSource) -> Self {
+        Self {
+            closest_hit: Some(closest_hit),
+            any_hit: None,
+        }
+    }
+
+    /// For rays which skip the closest hit shader
+    pub fn any_hit_only(any_hit: ShaderSource) -> Self {
+        Self {
+            closest_hit: None,
+            any_hit: Some(any_hit),
+        }
+    }
+
+    pub fn any_hit(mut self, any_hit: ShaderSource) -> Self {
+        self.any_hit = Some(any_hit);
+        self
+    }
+}
+
+impl From<ShaderSource> for RtHitGroup {
+    fn from(closest_hit: ShaderSource) -> Self {
+        Self::new(closest_hit)
+    }
+}
+
 impl<'rg> SimpleRenderPass<'rg, RgRtPipelineHandle> {
     pub fn new_rt(
         mut pass: PassBuilder<'rg>,
         rgen: ShaderSource,
         miss: impl IntoIterator<Item = ShaderSource>,
-        hit: impl IntoIterator<Item = ShaderSource>,
+        hit: impl IntoIterator<Item = impl Into<RtHitGroup>>,
     ) -> Self {
         let miss = miss.into_iter();
         let hit = hit.into_iter();
@@ -187,13 +221,41 @@ impl<'rg> SimpleRenderPass<'rg, RgRtPipelineHandle> {
             );
         }
 
-        for source in hit {
-            shaders.push(
-                PipelineShaderDesc::builder(ShaderPipelineStage::RayClosestHit)
-                    .source(source)
-                    .build()
-                    .unwrap(),
+        // An any hit shader joins the group of a closest hit shader right before it,
+        // so a group without an any hit can't be followed by one without a closest hit.
+        let mut prev_group_joinable = false;
+
+        for group in hit {
+            let group: RtHitGroup = group.into();
+            assert!(
+                group.closest_hit.is_some() || group.any_hit.is_some(),
+                "Empty ray tracing hit group"
             );
+
+            if let Some(closest_hit) = group.closest_hit {
+                shaders.push(
+                    PipelineShaderDesc::builder(ShaderPipelineStage::RayClosestHit)
+                        .source(closest_hit)
+                        .build()
+                        .unwrap(),
+                );
+            } else {
+                assert!(
+                    !prev_group_joinable,
+                    "A hit group without a closest hit shader can't follow one without an any hit shader"
+                );
+            }
+
+            prev_group_joinable = group.any_hit.is_none();
+
+            if let Some(any_hit) = group.any_hit {
+                shaders.push(
+                    PipelineShaderDesc::builder(ShaderPipelineStage::RayAnyHit)
+                        .source(any_hit)
+                        .build()
+                        .unwrap(),
+                );
+            }
         }
 
         let pipeline = pass.register_ray_tracing_pipeline(
@@ -32,7 +32,10 @@ impl WorldRenderer {
                         gamma: TexGamma::Linear,
                         use_mips: false,
                         compression: kajiya_asset::mesh::TexCompressionMode::None,
+                        compression_quality: Default::default(),
                         channel_swizzle: None,
+                        normal_map: false,
+                        max_size: None,
                     },
                     device: backend.device.clone(),
                 }
@@ -1,7 +1,10 @@
 use std::{hash::Hash, sync::Arc};
 
-use image::{imageops::FilterType, DynamicImage, GenericImageView};
-use kajiya_asset::{image::RawImage, mesh::TexParams};
+use image::{DynamicImage, GenericImageView};
+use kajiya_asset::{
+    image::{resize_texture, RawImage},
+    mesh::TexParams,
+};
 use kajiya_backend::{ash::vk, Device, Image, ImageDesc, ImageSubResourceData};
 use turbosloth::*;
 
@@ -27,9 +30,17 @@ impl LazyWorker for UploadGpuImage {
         let src = self.image.eval(&ctx).await?;
         let src = match &*src {
             RawImage::Rgba8(src) => src,
+            RawImage::Rgba16(_) | RawImage::Rgba32f(_) => {
+                return Err(anyhow::anyhow!(
+                    "UploadGpuImage does not support 16-bit and float images yet"
+                ));
+            }
             RawImage::Dds(_) => {
                 return Err(anyhow::anyhow!("UploadGpuImage does not support Dds yet"));
             }
+            RawImage::Ktx2(_) => {
+                return Err(anyhow::anyhow!("UploadGpuImage does not support KTX2 yet"));
+            }
         };
 
         let format = match self.params.gamma {
@@ -60,11 +71,11 @@ impl LazyWorker for UploadGpuImage {
             );
 
             let downsample = |image: &DynamicImage| {
-                // TODO: gamma-correct resize
-                image.resize_exact(
+                resize_texture(
+                    image,
                     (image.dimensions().0 / 2).max(1),
                     (image.dimensions().1 / 2).max(1),
-                    FilterType::Lanczos3,
+                    &self.params,
                 )
             };
 
@@ -1,23 +1,75 @@
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{collections::HashMap, fs::File, path::PathBuf, time::SystemTime};
 
 use anyhow::Context;
+use kajiya_asset::asset_header::{baked_asset, baked_asset_unchecked, BakedAsset};
 use parking_lot::Mutex;
 
 lazy_static::lazy_static! {
-    static ref ASSET_MMAPS: Mutex<HashMap<PathBuf, memmap2::Mmap>> = Mutex::new(HashMap::new());
+    static ref ASSET_MMAPS: Mutex<AssetMmaps> = Mutex::new(AssetMmaps::default());
 }
 
-pub fn mmapped_asset<T, P: Into<std::path::PathBuf>>(path: P) -> anyhow::Result<&'static T> {
+#[derive(Default)]
+struct AssetMmaps {
+    current: HashMap<PathBuf, AssetMmap>,
+    // Mappings of files which have since been re-baked. References into them
+    // may still be alive, so they are never unmapped.
+    retired: Vec<memmap2::Mmap>,
+}
+
+struct AssetMmap {
+    mmap: memmap2::Mmap,
+    // Identifies the version of the file which was mapped
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+pub fn mmapped_asset<T: BakedAsset, P: Into<std::path::PathBuf>>(
+    path: P,
+) -> anyhow::Result<&'static T> {
     let path = path.into();
     let path = kajiya_backend::canonical_path_from_vfs(&path)
         .with_context(|| format!("Can't mmap asset: file doesn't exist: {:?}", path))?;
 
+    let file = File::open(&path).with_context(|| format!("Could not mmap {:?}", path))?;
+    let metadata = file
+        .metadata()
+        .with_context(|| format!("Could not mmap {:?}", path))?;
+    let modified = metadata.modified().ok();
+    let len = metadata.len();
+
     let mut mmaps = ASSET_MMAPS.lock();
-    let data: &[u8] = mmaps.entry(path.clone()).or_insert_with(|| {
-        let file =
-            File::open(&path).unwrap_or_else(|e| panic!("Could not mmap {:?}: {:?}", path, e));
-        unsafe { memmap2::MmapOptions::new().map(&file).unwrap() }
+    let is_current = mmaps.current.get(&path).map_or(false, |mapped| {
+        mapped.modified == modified && mapped.len == len
     });
-    let asset: &T = unsafe { (data.as_ptr() as *const T).as_ref() }.unwrap();
-    Ok(asset)
+
+    if !is_current {
+        // The asset pipeline never modifies baked files in place; it replaces them
+        // with new ones, so the contents of a mapping don't change under our feet.
+        let mmap = unsafe { memmap2::MmapOptions::new().map(&file) }
+            .with_context(|| format!("Could not mmap {:?}", path))?;
+
+        // Validate the whole archive once per version of the file.
+        baked_asset::<T>(&mmap).with_context(|| format!("Invalid baked asset {:?}", path))?;
+
+        let previous = mmaps.current.insert(
+            path.clone(),
+            AssetMmap {
+                mmap,
+                modified,
+                len,
+            },
+        );
+
+        if let Some(previous) = previous {
+            mmaps.retired.push(previous.mmap);
+        }
+    }
+
+    // Mappings are never removed, so they live for the rest of the program.
+    let mmap = &mmaps.current[&path].mmap;
+    let data: &'static [u8] = unsafe { std::slice::from_raw_parts(mmap.as_ptr(), mmap.len()) };
+
+    // Validated above; the header check still rejects loading the file as a different kind of asset.
+    unsafe { baked_asset_unchecked::<T>(data) }
+        .with_context(|| format!("Invalid baked asset {:?}", path))
 }
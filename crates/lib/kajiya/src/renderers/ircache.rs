@@ -397,7 +397,11 @@ impl IrcacheRenderState {
                 ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
                 ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
             ],
-            std::iter::empty(),
+            [
+                // Duplicated because `rt.hlsl` hardcodes the shadow hit group index to 1
+                super::shadow_hit_group(),
+                super::shadow_hit_group(),
+            ],
         )
         .read(&self.ircache_spatial_buf)
         .read(&self.ircache_life_buf)
@@ -416,7 +420,7 @@ impl IrcacheRenderState {
                 ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                 ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
             ],
-            [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+            [super::gbuffer_hit_group(), super::shadow_hit_group()],
         )
         .read(&self.ircache_spatial_buf)
         .read(sky_cube)
@@ -442,7 +446,7 @@ impl IrcacheRenderState {
                 ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                 ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
             ],
-            [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+            [super::gbuffer_hit_group(), super::shadow_hit_group()],
         )
         .read(&self.ircache_spatial_buf)
         .read(sky_cube)
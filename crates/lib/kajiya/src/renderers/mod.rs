@@ -1,6 +1,6 @@
 use std::cell::{Ref, RefCell};
 
-use kajiya_backend::Image;
+use kajiya_backend::{vulkan::shader::ShaderSource, Image};
 use kajiya_rg::{self as rg, GetOrCreateTemporal};
 
 pub mod deferred;
@@ -70,6 +70,19 @@ impl GbufferDepth {
     }
 }
 
+/// Hit shaders for tracing rays against the scene; the any hit shader handles
+/// alpha-tested and single-sided materials in non-opaque meshes.
+pub fn gbuffer_hit_group() -> rg::RtHitGroup {
+    rg::RtHitGroup::new(ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl"))
+        .any_hit(ShaderSource::hlsl("/shaders/rt/gbuffer.rahit.hlsl"))
+}
+
+/// Hit group of shadow rays, which `rt.hlsl` expects at index 1. It only needs
+/// the any hit shader, as shadow rays skip the closest hit.
+pub fn shadow_hit_group() -> rg::RtHitGroup {
+    rg::RtHitGroup::any_hit_only(ShaderSource::hlsl("/shaders/rt/shadow.rahit.hlsl"))
+}
+
 pub struct PingPongTemporalResource {
     pub output_tex: rg::TemporalResourceKey,
     pub history_tex: rg::TemporalResourceKey,
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use glam::Vec3;
 use kajiya_backend::{
     ash::vk,
     vk_sync::AccessType,
@@ -16,11 +17,40 @@ use super::GbufferDepth;
 pub struct UploadedTriMesh {
     pub index_buffer_offset: u64,
     pub index_count: u32,
+    // Simplified versions of the mesh, coarsest last. Ray tracing always uses the full mesh.
+    pub lods: Vec<UploadedMeshLod>,
+    // Encloses the vertices, in the space of the mesh
+    pub bounding_sphere_center: Vec3,
+    pub bounding_sphere_radius: f32,
+}
+
+#[derive(Clone, Copy)]
+pub struct UploadedMeshLod {
+    pub index_buffer_offset: u64,
+    pub index_count: u32,
+    // How far the simplified surface strays from the full one, in the units of the mesh
+    pub error: f32,
+}
+
+impl UploadedTriMesh {
+    /// Index buffer offset and index count of `lod`, where zero is the full mesh,
+    /// and `n` is `lods[n - 1]`.
+    pub fn lod_indices(&self, lod: usize) -> (u64, u32) {
+        match lod.checked_sub(1) {
+            Some(lod) => (
+                self.lods[lod].index_buffer_offset,
+                self.lods[lod].index_count,
+            ),
+            None => (self.index_buffer_offset, self.index_count),
+        }
+    }
 }
 
 pub struct RasterMeshesData<'a> {
     pub meshes: &'a [UploadedTriMesh],
     pub instances: &'a [MeshInstance],
+    // For each instance, as passed to `UploadedTriMesh::lod_indices`
+    pub instance_lods: Vec<usize>,
     pub vertex_buffer: Arc<Buffer>,
     pub bindless_descriptor_set: vk::DescriptorSet,
 }
@@ -55,6 +85,7 @@ pub fn raster_meshes(
 
     let meshes: Vec<UploadedTriMesh> = mesh_data.meshes.to_vec();
     let instances: Vec<MeshInstance> = mesh_data.instances.to_vec();
+    let instance_lods = mesh_data.instance_lods;
 
     let depth_ref = pass.raster(
         &mut gbuffer_depth.depth,
@@ -147,11 +178,12 @@ pub fn raster_meshes(
 
             for (draw_idx, instance) in instances.into_iter().enumerate() {
                 let mesh = &meshes[instance.mesh.0];
+                let (index_buffer_offset, index_count) = mesh.lod_indices(instance_lods[draw_idx]);
 
                 raw_device.cmd_bind_index_buffer(
                     cb.raw,
                     vertex_buffer.raw,
-                    mesh.index_buffer_offset,
+                    index_buffer_offset,
                     vk::IndexType::UINT32,
                 );
 
@@ -167,7 +199,7 @@ pub fn raster_meshes(
                     ),
                 );
 
-                raw_device.cmd_draw_indexed(cb.raw, mesh.index_count, 1, 0, 0, 0);
+                raw_device.cmd_draw_indexed(cb.raw, index_count, 1, 0, 0, 0);
             }
         }
 
@@ -297,7 +297,7 @@ impl RtdgiRenderer {
                     ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                     ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
                 ],
-                [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+                [super::gbuffer_hit_group(), super::shadow_hit_group()],
             )
             .read(&*half_view_normal_tex)
             .read_aspect(&gbuffer_depth.depth, vk::ImageAspectFlags::DEPTH)
@@ -325,7 +325,7 @@ impl RtdgiRenderer {
                     ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                     ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
                 ],
-                [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+                [super::gbuffer_hit_group(), super::shadow_hit_group()],
             )
             .read(&*half_view_normal_tex)
             .read_aspect(&gbuffer_depth.depth, vk::ImageAspectFlags::DEPTH)
@@ -483,7 +483,7 @@ impl RtdgiRenderer {
                         ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                         ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
                     ],
-                    [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+                    [super::gbuffer_hit_group(), super::shadow_hit_group()],
                 )
                 .read(&*half_depth_tex)
                 .read(&temporal_reservoir_packed_tex)
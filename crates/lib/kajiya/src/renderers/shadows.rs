@@ -23,7 +23,11 @@ pub fn trace_sun_shadow_mask(
             ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
             ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
         ],
-        std::iter::empty(),
+        [
+            // Duplicated because `rt.hlsl` hardcodes the shadow hit group index to 1
+            super::shadow_hit_group(),
+            super::shadow_hit_group(),
+        ],
     )
     .read_aspect(&gbuffer_depth.depth, vk::ImageAspectFlags::DEPTH)
     .read(&gbuffer_depth.geometric_normal)
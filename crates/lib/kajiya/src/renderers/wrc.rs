@@ -50,7 +50,7 @@ pub fn wrc_trace(
             ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
             ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
         ],
-        [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+        [super::gbuffer_hit_group(), super::shadow_hit_group()],
     )
     .read(sky_cube)
     .bind_mut(ircache)
@@ -84,7 +84,7 @@ impl WrcRenderState {
                 ShaderSource::hlsl("/shaders/rt/gbuffer.rmiss.hlsl"),
                 ShaderSource::hlsl("/shaders/rt/shadow.rmiss.hlsl"),
             ],
-            [ShaderSource::hlsl("/shaders/rt/gbuffer.rchit.hlsl")],
+            [super::gbuffer_hit_group(), super::shadow_hit_group()],
         )
         .bind(self)
         .read(sky_cube)
@@ -73,6 +73,7 @@ impl WorldRenderer {
                 RasterMeshesData {
                     meshes: self.meshes.as_slice(),
                     instances: self.instances.as_slice(),
+                    instance_lods: self.select_instance_lods(frame_desc),
                     vertex_buffer: self.vertex_buffer.lock().clone(),
                     bindless_descriptor_set: self.bindless_descriptor_set,
                 },
@@ -51,6 +51,7 @@ struct GpuMesh {
 
     mat_data_offset: u32,
     index_offset: u32,
+    vertex_uv1_offset: u32,
 }
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
@@ -163,6 +164,8 @@ pub struct WorldRenderer {
     mesh_buffer: Mutex<Arc<Buffer>>,
 
     mesh_blas: Vec<Arc<RayTracingAcceleration>>,
+    // Per mesh; false if any of its materials is double-sided
+    mesh_face_cull: Vec<bool>,
     tlas: Option<Arc<RayTracingAcceleration>>,
     accel_scratch: RayTracingAccelerationScratchBuffer,
 
@@ -210,6 +213,10 @@ pub struct WorldRenderer {
 
     pub render_overrides: RenderOverrides,
 
+    /// Meshes are rasterized with their coarsest LOD whose error projects to at most
+    /// this many pixels. Zero always uses the full meshes.
+    pub lod_error_threshold: f32,
+
     // One for each render mode
     pub(crate) exposure_state: [ExposureState; 2],
 }
@@ -297,16 +304,24 @@ pub struct BindlessImageHandle(pub u32);
 fn load_gpu_image_asset(
     device: Arc<kajiya_backend::Device>,
     asset: AssetRef<GpuImage::Flat>,
-) -> Arc<Image> {
-    let asset = crate::mmap::mmapped_asset::<GpuImage::Flat, _>(&format!(
-        "/cache/{:8.8x}.image",
-        asset.identity()
-    ))
-    .unwrap();
+) -> anyhow::Result<Arc<Image>> {
+    let path = format!("/cache/{:8.8x}.image", asset.identity());
+    let asset = crate::mmap::mmapped_asset::<GpuImage::Flat, _>(&path)?;
+
+    // Material maps go into the bindless table of 2D textures
+    if asset.cube || asset.array_layers > 1 {
+        anyhow::bail!(
+            "{:?}: cube maps and texture arrays can't be used as material maps",
+            path
+        );
+    }
 
-    let desc = ImageDesc::new_2d(asset.format, [asset.extent[0], asset.extent[1]])
-        .usage(vk::ImageUsageFlags::SAMPLED)
-        .mip_levels(asset.mips.len() as _);
+    let desc = ImageDesc::new_2d(
+        vk::Format::from_raw(asset.format),
+        [asset.extent[0], asset.extent[1]],
+    )
+    .usage(vk::ImageUsageFlags::SAMPLED)
+    .mip_levels(asset.mips.len() as _);
 
     let initial_data = asset
         .mips
@@ -319,10 +334,10 @@ fn load_gpu_image_asset(
         })
         .collect::<Vec<_>>();
 
-    Arc::new(device.create_image(desc, initial_data).unwrap())
+    Ok(Arc::new(device.create_image(desc, initial_data)?))
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy)]
 pub struct AddMeshOptions {
     pub use_lights: bool,
 }
@@ -455,6 +470,7 @@ impl WorldRenderer {
             mesh_lights: Default::default(),
 
             mesh_blas: Default::default(),
+            mesh_face_cull: Default::default(),
             tlas: Default::default(),
             accel_scratch,
 
@@ -511,6 +527,8 @@ impl WorldRenderer {
 
             render_overrides: Default::default(),
 
+            lod_error_threshold: 1.0,
+
             exposure_state: Default::default(),
         })
     }
@@ -580,7 +598,14 @@ impl WorldRenderer {
         assert_eq!(handle.0 as usize, id);
     }
 
+    /// Adds a 2D image to the bindless texture table.
     pub fn add_image(&mut self, image: Arc<Image>) -> BindlessImageHandle {
+        assert_eq!(
+            image.desc.image_type,
+            ImageType::Tex2d,
+            "Only 2D images can be bindless textures"
+        );
+
         let image_size: [f32; 4] = image.desc.extent_inv_extent_2d();
 
         let handle = self.add_bindless_image_view(
@@ -605,7 +630,7 @@ impl WorldRenderer {
         &mut self,
         mesh: &'static PackedTriMesh::Flat,
         opts: AddMeshOptions,
-    ) -> MeshHandle {
+    ) -> anyhow::Result<MeshHandle> {
         let mesh_idx = self.meshes.len();
         let mut unique_images: Vec<AssetRef<GpuImage::Flat>> = mesh.maps.as_slice().to_vec();
         unique_images.sort();
@@ -618,6 +643,8 @@ impl WorldRenderer {
                     load_gpu_image_asset(device, asset)
                 })
                 .run()
+                .into_iter()
+                .collect::<anyhow::Result<Vec<_>>>()?
         };
         /*let loaded_images = {
             let device = self.device.clone();
@@ -654,15 +681,40 @@ impl WorldRenderer {
             }
         }
 
+        let has_double_sided_materials = materials
+            .iter()
+            .any(|mat| mat.flags & MeshMaterialFlags::MESH_MATERIAL_FLAG_DOUBLE_SIDED != 0);
+        let has_alpha_tested_materials = materials
+            .iter()
+            .any(|mat| mat.flags & MeshMaterialFlags::MESH_MATERIAL_FLAGS_ALPHA_TESTED != 0);
+
         let vertex_data_offset = self.vertex_buffer_written as u32;
 
         let mut buffer_builder = BufferBuilder::new();
         let vertex_index_offset =
             buffer_builder.append(mesh.indices.as_slice()) as u32 + vertex_data_offset;
+        // The LODs are selected with bounds of the rest pose, which skinned meshes
+        // can deform well outside of; they always use the full mesh instead.
+        let lods = if mesh.joints.is_empty() {
+            mesh.lods.as_slice()
+        } else {
+            &[]
+        };
+
+        let lod_index_offset = if lods.is_empty() {
+            0
+        } else {
+            buffer_builder.append(mesh.lod_indices.as_slice()) as u32 + vertex_data_offset
+        };
         let vertex_core_offset =
             buffer_builder.append(mesh.verts.as_slice()) as u32 + vertex_data_offset;
         let vertex_uv_offset =
             buffer_builder.append(mesh.uvs.as_slice()) as u32 + vertex_data_offset;
+        let vertex_uv1_offset = if mesh.uvs1.is_empty() {
+            0
+        } else {
+            buffer_builder.append(mesh.uvs1.as_slice()) as u32 + vertex_data_offset
+        };
         let vertex_mat_offset =
             buffer_builder.append(mesh.material_ids.as_slice()) as u32 + vertex_data_offset;
         let vertex_aux_offset =
@@ -716,6 +768,11 @@ impl WorldRenderer {
                                 .max()
                                 .expect("mesh must not be empty"),
                         }],
+                        // Culling of single-sided materials in double-sided meshes
+                        // is done by the any hit shader too. This applies to every triangle
+                        // of the mesh, in every ray traced pass; meshes which don't need it
+                        // can be baked with `force_opaque`.
+                        opaque: !has_alpha_tested_materials && !has_double_sided_materials,
                     }],
                 })
                 .expect("blas");
@@ -723,6 +780,8 @@ impl WorldRenderer {
             self.mesh_blas.push(Arc::new(blas));
         }
 
+        self.mesh_face_cull.push(!has_double_sided_materials);
+
         mesh_buffer_dst[mesh_idx] = GpuMesh {
             vertex_core_offset,
             vertex_uv_offset,
@@ -731,11 +790,28 @@ impl WorldRenderer {
             vertex_tangent_offset,
             mat_data_offset,
             index_offset: vertex_index_offset,
+            vertex_uv1_offset,
         };
 
+        let (bounds_min, bounds_max) = mesh.verts.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), v| (min.min(v.pos.into()), max.max(v.pos.into())),
+        );
+
         self.meshes.push(UploadedTriMesh {
             index_buffer_offset: vertex_index_offset as u64,
             index_count: mesh.indices.len() as _,
+            lods: lods
+                .iter()
+                .map(|lod| UploadedMeshLod {
+                    index_buffer_offset: lod_index_offset as u64
+                        + (lod.index_offset as usize * size_of::<u32>()) as u64,
+                    index_count: lod.index_count,
+                    error: lod.error,
+                })
+                .collect(),
+            bounding_sphere_center: (bounds_min + bounds_max) * 0.5,
+            bounding_sphere_radius: (bounds_max - bounds_min).length() * 0.5,
         });
 
         let mesh_lights = if opts.use_lights {
@@ -772,7 +848,50 @@ impl WorldRenderer {
             lights: mesh_lights,
         });
 
-        MeshHandle(mesh_idx)
+        Ok(MeshHandle(mesh_idx))
+    }
+
+    /// The LOD to rasterize each instance with, as passed to `UploadedTriMesh::lod_indices`:
+    /// the coarsest one whose error projects to at most `lod_error_threshold` pixels.
+    pub(super) fn select_instance_lods(&self, frame_desc: &WorldFrameDesc) -> Vec<usize> {
+        let eye_position = frame_desc.camera_matrices.eye_position();
+
+        // Size in pixels of one world unit at a distance of one
+        let pixels_per_unit = frame_desc.camera_matrices.view_to_clip.y_axis.y
+            * frame_desc.render_extent[1] as f32
+            * 0.5;
+
+        self.instances
+            .iter()
+            .map(|inst| {
+                let mesh = &self.meshes[inst.mesh.0];
+                if mesh.lods.is_empty() {
+                    return 0;
+                }
+
+                let scale = inst
+                    .transform
+                    .x_axis
+                    .length()
+                    .max(inst.transform.y_axis.length())
+                    .max(inst.transform.z_axis.length());
+
+                let center = inst.transform.transform_point3(mesh.bounding_sphere_center);
+                let distance = center.distance(eye_position) - mesh.bounding_sphere_radius * scale;
+
+                // The camera could be right next to the surface
+                if distance <= 0.0 {
+                    return 0;
+                }
+
+                mesh.lods
+                    .iter()
+                    .rposition(|lod| {
+                        lod.error * scale * pixels_per_unit / distance <= self.lod_error_threshold
+                    })
+                    .map_or(0, |lod| lod + 1)
+            })
+            .collect()
     }
 
     pub fn add_instance(&mut self, mesh: MeshHandle, transform: Affine3A) -> InstanceHandle {
@@ -846,6 +965,7 @@ impl WorldRenderer {
                             blas: self.mesh_blas[inst.mesh.0].clone(),
                             transformation: inst.transform,
                             mesh_index: inst.mesh.0 as u32,
+                            face_cull: self.mesh_face_cull[inst.mesh.0],
                         })
                         .collect::<Vec<_>>(),
                     preallocate_bytes: TLAS_PREALLOCATE_BYTES,
@@ -878,6 +998,7 @@ impl WorldRenderer {
                 blas: self.mesh_blas[inst.mesh.0].clone(),
                 transformation: inst.transform,
                 mesh_index: inst.mesh.0 as u32,
+                face_cull: self.mesh_face_cull[inst.mesh.0],
             })
             .collect::<Vec<_>>();
 
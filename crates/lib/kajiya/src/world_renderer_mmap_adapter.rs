@@ -1,6 +1,32 @@
-use kajiya_asset::mesh::PackedTriMesh;
+use glam::{Affine3A, Mat4};
+use kajiya_asset::mesh::{PackedScene, PackedSceneNode, PackedTriMesh};
 
-use crate::world_renderer::{AddMeshOptions, MeshHandle, WorldRenderer};
+use crate::world_renderer::{AddMeshOptions, InstanceHandle, MeshHandle, WorldRenderer};
+
+pub struct BakedSceneNode {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub local_transform: Affine3A,
+    pub mesh: Option<MeshHandle>,
+}
+
+/// A baked scene with its node hierarchy, and meshes already uploaded to the `WorldRenderer`.
+/// Can be instantiated any number of times via `WorldRenderer::instantiate_baked_scene`.
+pub struct BakedScene {
+    // Sorted parent-first; checked by `add_baked_scene`
+    pub nodes: Vec<BakedSceneNode>,
+}
+
+impl BakedScene {
+    pub fn world_transforms(&self, root_transform: Affine3A) -> Vec<Affine3A> {
+        let mut res: Vec<Affine3A> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let parent = node.parent.map_or(root_transform, |parent| res[parent]);
+            res.push(parent * node.local_transform);
+        }
+        res
+    }
+}
 
 impl WorldRenderer {
     pub fn add_baked_mesh(
@@ -8,9 +34,98 @@ impl WorldRenderer {
         path: impl Into<std::path::PathBuf>,
         opts: AddMeshOptions,
     ) -> anyhow::Result<MeshHandle> {
-        Ok(self.add_mesh(
+        self.add_mesh(
             crate::mmap::mmapped_asset::<PackedTriMesh::Flat, _>(path)?,
             opts,
-        ))
+        )
+    }
+
+    pub fn add_baked_scene(
+        &mut self,
+        path: impl Into<std::path::PathBuf>,
+        opts: AddMeshOptions,
+    ) -> anyhow::Result<BakedScene> {
+        let path = path.into();
+        let scene = crate::mmap::mmapped_asset::<PackedScene::Flat, _>(&path)?;
+
+        let meshes = scene
+            .mesh_names
+            .iter()
+            .map(|name| {
+                let name = std::str::from_utf8(name.as_slice())?;
+                self.add_baked_mesh(path.with_file_name(format!("{}.mesh", name)), opts)
+            })
+            .collect::<anyhow::Result<Vec<MeshHandle>>>()?;
+
+        if scene.node_names.len() != scene.nodes.len() {
+            anyhow::bail!(
+                "{:?} has {} nodes, but {} node names",
+                path,
+                scene.nodes.len(),
+                scene.node_names.len()
+            );
+        }
+
+        // Only the structure of the archive has been validated; the indices could be anything
+        let nodes = scene
+            .nodes
+            .iter()
+            .zip(scene.node_names.iter())
+            .enumerate()
+            .map(|(node_idx, (node, name))| {
+                let parent = (node.parent != PackedSceneNode::NONE).then(|| node.parent as usize);
+                if parent.map_or(false, |parent| parent >= node_idx) {
+                    anyhow::bail!(
+                        "Node {} of {:?} has the parent {}, which isn't stored before it",
+                        node_idx,
+                        path,
+                        node.parent
+                    );
+                }
+
+                let mesh = if node.mesh == PackedSceneNode::NONE {
+                    None
+                } else {
+                    Some(*meshes.get(node.mesh as usize).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Node {} of {:?} references mesh {}, but there are only {}",
+                            node_idx,
+                            path,
+                            node.mesh,
+                            meshes.len()
+                        )
+                    })?)
+                };
+
+                Ok(BakedSceneNode {
+                    name: String::from_utf8_lossy(name.as_slice()).into_owned(),
+                    parent,
+                    local_transform: Affine3A::from_mat4(Mat4::from_cols_array(
+                        &node.local_transform,
+                    )),
+                    mesh,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(BakedScene { nodes })
+    }
+
+    /// Add an instance for every mesh node of `scene`. Returns the node index of each instance,
+    /// so that the instances can be moved individually via `set_instance_transform`.
+    pub fn instantiate_baked_scene(
+        &mut self,
+        scene: &BakedScene,
+        transform: Affine3A,
+    ) -> Vec<(usize, InstanceHandle)> {
+        scene
+            .world_transforms(transform)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(node_idx, node_transform)| {
+                let mesh = scene.nodes[node_idx].mesh?;
+                Some((node_idx, self.add_instance(mesh, node_transform)))
+            })
+            .collect()
     }
 }
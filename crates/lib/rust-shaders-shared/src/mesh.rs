@@ -11,6 +11,7 @@ pub struct MeshDescriptor {
     pub vertex_tangent_offset: u32,
     pub mat_data_offset: u32,
     pub index_offset: u32,
+    pub vertex_uv1_offset: u32, // zero if the mesh has no second uv set
 }
 
 #[repr(C, align(16))]
@@ -90,9 +91,18 @@ pub struct MaterialDescriptor {
     pub emissive: Vec4,
     pub flags: u32,
     pub map_transforms: [[f32; 6]; 4],
+    pub map_uv_sets: UVec4,
+    pub alpha_cutoff: f32,
+    pub transmission: f32,
+    pub ior: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub sheen_color: Vec4,
+    pub sheen_roughness: f32,
 }
 
 impl MaterialDescriptor {
+    /// Reads a `MeshMaterial` as baked, whose `repr(C)` layout is tightly packed dwords.
     pub fn load(data: &[u32], byte_offset: u32) -> Self {
         let offset = (byte_offset >> 2) as usize;
         let base_color_mult = load_vec4(data, offset);
@@ -104,9 +114,32 @@ impl MaterialDescriptor {
         ));
         let roughness_mult = f32::from_bits(data[offset + 8]);
         let metalness_factor = f32::from_bits(data[offset + 9]);
-        let emissive = load_vec4(data, offset + 10);
-        let flags = data[offset + 15];
-        let map_transforms = load_map_transforms(data, offset + 16);
+        let emissive = Vec4::new(
+            f32::from_bits(data[offset + 10]),
+            f32::from_bits(data[offset + 11]),
+            f32::from_bits(data[offset + 12]),
+            0.0,
+        );
+        let flags = data[offset + 13];
+        let map_transforms = load_map_transforms(data, offset + 14);
+        let map_uv_sets = UVec4::new(
+            data[offset + 38],
+            data[offset + 39],
+            data[offset + 40],
+            data[offset + 41],
+        );
+        let alpha_cutoff = f32::from_bits(data[offset + 42]);
+        let transmission = f32::from_bits(data[offset + 43]);
+        let ior = f32::from_bits(data[offset + 44]);
+        let clearcoat = f32::from_bits(data[offset + 45]);
+        let clearcoat_roughness = f32::from_bits(data[offset + 46]);
+        let sheen_color = Vec4::new(
+            f32::from_bits(data[offset + 47]),
+            f32::from_bits(data[offset + 48]),
+            f32::from_bits(data[offset + 49]),
+            0.0,
+        );
+        let sheen_roughness = f32::from_bits(data[offset + 50]);
 
         Self {
             base_color_mult,
@@ -116,6 +149,14 @@ impl MaterialDescriptor {
             emissive,
             flags,
             map_transforms,
+            map_uv_sets,
+            alpha_cutoff,
+            transmission,
+            ior,
+            clearcoat,
+            clearcoat_roughness,
+            sheen_color,
+            sheen_roughness,
         }
     }
     pub fn transform_uv(&self, uv: Vec2, map_idx: usize) -> Vec2 {